@@ -0,0 +1,115 @@
+use solana_sdk::{account::Account, clock::Epoch, sysvar::stake_history::StakeHistory};
+use solana_stake_program::stake_state::StakeState;
+use std::ops::AddAssign;
+
+/// The lamports of a stake account, broken down by activation state.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct StakeBalance {
+    /// Delegated stake that is earning rewards, including stake that is deactivating.
+    pub effective: u64,
+    /// Delegated stake that is warming up.
+    pub activating: u64,
+    /// Effective stake that is cooling down.
+    pub deactivating: u64,
+    /// Delegated stake that is neither effective nor activating.
+    pub inactive: u64,
+    /// Lamports that are not delegated, including the rent-exempt reserve.
+    pub undelegated: u64,
+}
+
+impl StakeBalance {
+    pub fn lamports(&self) -> u64 {
+        self.effective + self.activating + self.inactive + self.undelegated
+    }
+}
+
+impl AddAssign for StakeBalance {
+    fn add_assign(&mut self, other: Self) {
+        self.effective += other.effective;
+        self.activating += other.activating;
+        self.deactivating += other.deactivating;
+        self.inactive += other.inactive;
+        self.undelegated += other.undelegated;
+    }
+}
+
+/// Return the balance of a stake account at the given epoch, broken down by
+/// activation state.
+pub(crate) fn get_stake_balance(
+    account: &Account,
+    epoch: Epoch,
+    stake_history: &StakeHistory,
+) -> StakeBalance {
+    match StakeState::stake_from(account) {
+        Some(stake) => {
+            let delegation = &stake.delegation;
+            let (effective, activating, deactivating) =
+                delegation.stake_activating_and_deactivating(epoch, Some(stake_history));
+            StakeBalance {
+                effective,
+                activating,
+                deactivating,
+                inactive: delegation.stake.saturating_sub(effective + activating),
+                undelegated: account.lamports.saturating_sub(delegation.stake),
+            }
+        }
+        None => StakeBalance {
+            undelegated: account.lamports,
+            ..StakeBalance::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_stake_program::stake_state::{Delegation, Meta, Stake};
+
+    fn create_stake_account(lamports: u64, stake: u64, activation_epoch: Epoch) -> Account {
+        let stake = Stake {
+            delegation: Delegation {
+                voter_pubkey: Pubkey::new_rand(),
+                stake,
+                activation_epoch,
+                ..Delegation::default()
+            },
+            ..Stake::default()
+        };
+        let state = StakeState::Stake(Meta::default(), stake);
+        Account::new_data(lamports, &state, &solana_stake_program::id()).unwrap()
+    }
+
+    #[test]
+    fn test_get_stake_balance() {
+        let stake_history = StakeHistory::default();
+
+        let state = StakeState::Initialized(Meta::default());
+        let account = Account::new_data(42, &state, &solana_stake_program::id()).unwrap();
+        let balance = get_stake_balance(&account, 10, &stake_history);
+        assert_eq!(
+            balance,
+            StakeBalance {
+                undelegated: 42,
+                ..StakeBalance::default()
+            }
+        );
+
+        let account = create_stake_account(150, 100, 0);
+        let balance = get_stake_balance(&account, 10, &stake_history);
+        assert_eq!(balance.effective, 100);
+        assert_eq!(balance.undelegated, 50);
+        assert_eq!(balance.lamports(), 150);
+
+        let account = create_stake_account(150, 100, 10);
+        let balance = get_stake_balance(&account, 10, &stake_history);
+        assert_eq!(balance.effective, 0);
+        assert_eq!(balance.activating, 100);
+        assert_eq!(balance.lamports(), 150);
+
+        let mut total = StakeBalance::default();
+        total += get_stake_balance(&create_stake_account(150, 100, 0), 10, &stake_history);
+        total += get_stake_balance(&create_stake_account(150, 100, 10), 10, &stake_history);
+        assert_eq!(total.lamports(), 300);
+    }
+}
@@ -1,9 +1,21 @@
-use clap::{value_t_or_exit, App, Arg, ArgMatches, SubCommand};
+use crate::error::Error;
+use crate::profile::{default_profile_file, load_profile, load_profiles, Profile, SeedScheme};
+use crate::vest::Period;
+use chrono::{DateTime, NaiveDate};
+use clap::{value_t, value_t_or_exit, values_t_or_exit, App, Arg, ArgMatches, SubCommand};
 use solana_clap_utils::input_validators::{is_amount, is_valid_pubkey, is_valid_signer};
 use solana_cli_config::CONFIG_FILE;
-use solana_sdk::native_token::sol_to_lamports;
+use solana_sdk::{
+    clock::{Slot, UnixTimestamp},
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    native_token::sol_to_lamports,
+};
 use std::ffi::OsString;
-use std::process::exit;
+use std::fs;
+use std::path::Path;
+
+/// The most indices `--indices` may select.
+const MAX_INDICES: usize = 100_000;
 
 pub(crate) struct NewCommandConfig {
     pub fee_payer: String,
@@ -15,22 +27,22 @@ pub(crate) struct NewCommandConfig {
 }
 
 pub(crate) struct CountCommandConfig {
-    pub base_pubkey: String,
+    pub base_pubkeys: Vec<String>,
 }
 
 pub(crate) struct QueryCommandConfig {
-    pub base_pubkey: String,
-    pub num_accounts: usize,
+    pub base_pubkeys: Vec<String>,
+    pub indices: Vec<usize>,
 }
 
 pub(crate) struct AuthorizeCommandConfig {
     pub fee_payer: String,
-    pub base_pubkey: String,
+    pub base_pubkeys: Vec<String>,
     pub stake_authority: String,
     pub withdraw_authority: String,
     pub new_stake_authority: String,
     pub new_withdraw_authority: String,
-    pub num_accounts: usize,
+    pub indices: Vec<usize>,
 }
 
 pub(crate) struct RebaseCommandConfig {
@@ -38,7 +50,7 @@ pub(crate) struct RebaseCommandConfig {
     pub base_pubkey: String,
     pub new_base_keypair: String,
     pub stake_authority: String,
-    pub num_accounts: usize,
+    pub indices: Vec<usize>,
 }
 
 pub(crate) struct MoveCommandConfig {
@@ -46,6 +58,139 @@ pub(crate) struct MoveCommandConfig {
     pub authorize_config: AuthorizeCommandConfig,
 }
 
+pub(crate) struct SnapshotCommandConfig {
+    pub query_config: QueryCommandConfig,
+    pub output_path: String,
+}
+
+pub(crate) struct DiffCommandConfig {
+    pub before_path: String,
+    pub after_path: String,
+}
+
+pub(crate) struct WatchCommandConfig {
+    pub query_config: QueryCommandConfig,
+    pub expected_stake_authority: Option<String>,
+    pub expected_withdraw_authority: Option<String>,
+    pub expected_custodian: Option<String>,
+    pub interval_secs: u64,
+    pub log_file: Option<String>,
+    pub alert_command: Option<String>,
+}
+
+/// How a command that prints records formats them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+pub(crate) struct HistoryCommandConfig {
+    pub query_config: QueryCommandConfig,
+    pub start_slot: Slot,
+    pub end_slot: Option<Slot>,
+    pub output_format: OutputFormat,
+}
+
+pub(crate) struct DistributeCommandConfig {
+    pub fee_payer: String,
+    pub sender_keypair: String,
+    pub base_keypair: String,
+    pub allocations_path: String,
+    pub db_path: String,
+}
+
+pub(crate) struct VestCommandConfig {
+    pub fee_payer: String,
+    pub sender_keypair: String,
+    pub base_keypair: String,
+    pub lamports: u64,
+    pub stake_authority: String,
+    pub withdraw_authority: String,
+    pub custodian: Option<String>,
+    pub start: UnixTimestamp,
+    pub period: Period,
+    pub num_tranches: usize,
+    pub cliff: Option<Period>,
+}
+
+pub(crate) struct ClawbackCommandConfig {
+    pub fee_payer: String,
+    pub base_pubkey: String,
+    pub withdraw_authority: String,
+    pub custodian: String,
+    pub treasury: String,
+    pub indices: Vec<usize>,
+}
+
+pub(crate) struct UnlocksCommandConfig {
+    pub query_config: QueryCommandConfig,
+    pub output_format: OutputFormat,
+}
+
+pub(crate) struct AuditCommandConfig {
+    pub query_config: QueryCommandConfig,
+    pub policy_path: String,
+    pub output_format: OutputFormat,
+}
+
+pub(crate) struct DelegateCommandConfig {
+    pub fee_payer: String,
+    pub base_keypair: String,
+    pub stake_authority: String,
+    pub weights_path: String,
+    pub indices: Vec<usize>,
+}
+
+pub(crate) struct RunPlanCommandConfig {
+    pub fee_payer: String,
+    pub stake_authority: Option<String>,
+    pub withdraw_authority: Option<String>,
+    pub plan_path: String,
+    pub state_path: String,
+    pub interval_secs: u64,
+}
+
+pub(crate) struct RebalanceCommandConfig {
+    pub delegate_config: DelegateCommandConfig,
+    pub state_path: String,
+}
+
+/// A command whose transactions are planned rather than sent.
+pub(crate) enum PlannedCommand {
+    Authorize(AuthorizeCommandConfig),
+    Rebase(RebaseCommandConfig),
+    Move(MoveCommandConfig),
+}
+
+pub(crate) struct PlanCommandConfig {
+    pub command: PlannedCommand,
+    pub output_path: String,
+    pub nonce_accounts: Vec<String>,
+    pub nonce_authority: String,
+}
+
+pub(crate) struct SignCommandConfig {
+    pub plan_path: String,
+    pub signer: String,
+}
+
+pub(crate) struct SubmitCommandConfig {
+    pub plan_path: String,
+}
+
+pub(crate) struct StatusCommandConfig {
+    pub receipt_path: String,
+}
+
+pub(crate) struct LocateCommandConfig {
+    pub address: String,
+    /// Each base to search, and the seed scheme to derive its accounts with.
+    pub bases: Vec<(String, SeedScheme)>,
+    pub search_limit: usize,
+}
+
 pub(crate) enum Command {
     New(NewCommandConfig),
     Count(CountCommandConfig),
@@ -54,18 +199,39 @@ pub(crate) enum Command {
     Authorize(AuthorizeCommandConfig),
     Rebase(RebaseCommandConfig),
     Move(MoveCommandConfig),
+    Snapshot(SnapshotCommandConfig),
+    Diff(DiffCommandConfig),
+    Watch(WatchCommandConfig),
+    History(HistoryCommandConfig),
+    Distribute(DistributeCommandConfig),
+    Vest(VestCommandConfig),
+    Clawback(ClawbackCommandConfig),
+    Unlocks(UnlocksCommandConfig),
+    Audit(AuditCommandConfig),
+    Delegate(DelegateCommandConfig),
+    Rebalance(RebalanceCommandConfig),
+    RunPlan(RunPlanCommandConfig),
+    Plan(PlanCommandConfig),
+    Sign(SignCommandConfig),
+    Submit(SubmitCommandConfig),
+    Status(StatusCommandConfig),
+    Locate(LocateCommandConfig),
 }
 
 pub(crate) struct CommandConfig {
     pub config_file: String,
-    pub url: Option<String>,
+    /// RPC nodes in order of preference, empty to use the config file's.
+    pub urls: Vec<String>,
+    pub requests_per_second: Option<u32>,
+    pub commitment: CommitmentConfig,
+    pub finalized: bool,
+    pub receipt_path: Option<String>,
     pub command: Command,
 }
 
 fn fee_payer_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("fee_payer")
         .long("fee-payer")
-        .required(true)
         .takes_value(true)
         .value_name("KEYPAIR")
         .validator(is_valid_signer)
@@ -81,18 +247,40 @@ fn sender_keypair_arg<'a, 'b>() -> Arg<'a, 'b> {
         .help("Keypair to fund accounts")
 }
 
+fn base_keypair_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("base_keypair")
+        .required(true)
+        .takes_value(true)
+        .value_name("BASE_KEYPAIR")
+        .validator(is_valid_signer)
+        .help("Keypair which stake account addresses are derived from")
+}
+
 fn base_pubkey_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("base_pubkey")
-        .required(true)
         .takes_value(true)
         .value_name("BASE_PUBKEY")
         .validator(is_valid_pubkey)
         .help("Public key which stake account addresses are derived from")
 }
 
+fn base_pubkeys_arg<'a, 'b>() -> Arg<'a, 'b> {
+    base_pubkey_arg()
+        .multiple(true)
+        .value_name("BASE_PUBKEY")
+        .help("Public keys which stake account addresses are derived from")
+}
+
+fn base_pubkeys_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("base_pubkeys_file")
+        .long("base-pubkeys-file")
+        .takes_value(true)
+        .value_name("FILEPATH")
+        .help("File of base public keys, one per line")
+}
+
 fn new_base_keypair_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("new_base_keypair")
-        .required(true)
         .takes_value(true)
         .value_name("NEW_BASE_KEYPAIR")
         .validator(is_valid_signer)
@@ -102,7 +290,6 @@ fn new_base_keypair_arg<'a, 'b>() -> Arg<'a, 'b> {
 fn stake_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("stake_authority")
         .long("stake-authority")
-        .required(true)
         .takes_value(true)
         .value_name("KEYPAIR")
         .validator(is_valid_signer)
@@ -112,7 +299,6 @@ fn stake_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
 fn withdraw_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("withdraw_authority")
         .long("withdraw-authority")
-        .required(true)
         .takes_value(true)
         .value_name("KEYPAIR")
         .validator(is_valid_signer)
@@ -122,7 +308,6 @@ fn withdraw_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
 fn new_stake_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("new_stake_authority")
         .long("new-stake-authority")
-        .required(true)
         .takes_value(true)
         .value_name("PUBKEY")
         .validator(is_valid_pubkey)
@@ -132,7 +317,6 @@ fn new_stake_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
 fn new_withdraw_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("new_withdraw_authority")
         .long("new-withdraw-authority")
-        .required(true)
         .takes_value(true)
         .value_name("PUBKEY")
         .validator(is_valid_pubkey)
@@ -142,12 +326,177 @@ fn new_withdraw_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
 fn num_accounts_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("num_accounts")
         .long("num-accounts")
-        .required(true)
         .takes_value(true)
         .value_name("NUMBER")
         .help("Number of derived stake accounts")
 }
 
+fn indices_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("indices")
+        .long("indices")
+        .takes_value(true)
+        .value_name("LIST")
+        .validator(is_indices)
+        .conflicts_with_all(&["num_accounts", "start_index"])
+        .help("Indices of the derived stake accounts, such as 10-19,3,7,42")
+}
+
+fn start_index_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("start_index")
+        .long("start-index")
+        .takes_value(true)
+        .value_name("INDEX")
+        .help("Skip the derived stake accounts below this index")
+}
+
+// Parse a comma-separated list of indices and inclusive ranges, returning the
+// indices in order, without repeats.
+fn parse_indices(value: &str) -> Result<Vec<usize>, String> {
+    let parse_index = |index: &str| {
+        index
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid index: {}", index))
+    };
+    let mut indices = vec![];
+    for item in value.split(',') {
+        let mut bounds = item.splitn(2, '-');
+        let first = parse_index(bounds.next().unwrap())?;
+        let last = match bounds.next() {
+            Some(last) => parse_index(last)?,
+            None => first,
+        };
+        if last < first {
+            return Err(format!("invalid range: {}", item));
+        }
+        if last - first >= MAX_INDICES - indices.len() {
+            return Err(format!("more than {} indices", MAX_INDICES));
+        }
+        indices.extend(first..=last);
+    }
+    indices.sort();
+    indices.dedup();
+    Ok(indices)
+}
+
+fn is_indices(value: String) -> Result<(), String> {
+    parse_indices(&value).map(|_| ())
+}
+
+fn is_unix_timestamp(value: String) -> Result<(), String> {
+    parse_unix_timestamp(&value).map(|_| ())
+}
+
+fn is_period(value: String) -> Result<(), String> {
+    let period = value.parse::<Period>()?;
+    // One period from the Unix epoch must be a representable time.
+    period.after(0, 1).map(|_| ())
+}
+
+fn is_positive_number(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(number) if number > 0 => Ok(()),
+        _ => Err(format!("expected a positive number, got {}", value)),
+    }
+}
+
+fn output_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("output_format")
+        .long("output-format")
+        .takes_value(true)
+        .value_name("FORMAT")
+        .possible_values(&["table", "json", "csv"])
+        .default_value("table")
+        .help("Output format")
+}
+
+fn authorize_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("authorize")
+        .about("Set new authorities in all derived stake accounts")
+        .arg(fee_payer_arg())
+        .arg(base_pubkeys_arg().index(1))
+        .arg(base_pubkeys_file_arg())
+        .arg(stake_authority_arg())
+        .arg(withdraw_authority_arg())
+        .arg(new_stake_authority_arg())
+        .arg(new_withdraw_authority_arg())
+        .arg(num_accounts_arg())
+        .arg(start_index_arg())
+        .arg(indices_arg())
+}
+
+fn rebase_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("rebase")
+        .about("Relocate derived stake accounts")
+        .arg(fee_payer_arg())
+        .arg(base_pubkey_arg().index(1))
+        .arg(new_base_keypair_arg().index(2))
+        .arg(stake_authority_arg())
+        .arg(num_accounts_arg())
+        .arg(start_index_arg())
+        .arg(indices_arg())
+}
+
+fn move_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("move")
+        .about("Rebase and set new authorities in all derived stake accounts")
+        .arg(fee_payer_arg())
+        .arg(base_pubkey_arg().index(1))
+        .arg(new_base_keypair_arg().index(2))
+        .arg(stake_authority_arg())
+        .arg(withdraw_authority_arg())
+        .arg(new_stake_authority_arg())
+        .arg(new_withdraw_authority_arg())
+        .arg(num_accounts_arg())
+        .arg(start_index_arg())
+        .arg(indices_arg())
+}
+
+fn planned_subcommand<'a, 'b>(subcommand: App<'a, 'b>) -> App<'a, 'b> {
+    subcommand
+        .arg(plan_output_arg())
+        .arg(nonce_arg())
+        .arg(nonce_authority_arg())
+}
+
+fn plan_output_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("output_path")
+        .long("output")
+        .required(true)
+        .takes_value(true)
+        .value_name("FILEPATH")
+        .help("File to write the unsigned transactions to")
+}
+
+fn nonce_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("nonce")
+        .long("nonce")
+        .required(true)
+        .takes_value(true)
+        .multiple(true)
+        .value_name("PUBKEY")
+        .validator(is_valid_pubkey)
+        .help("Nonce accounts to plan on, one per transaction")
+}
+
+fn nonce_authority_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("nonce_authority")
+        .long("nonce-authority")
+        .takes_value(true)
+        .value_name("KEYPAIR")
+        .validator(is_valid_signer)
+        .help("Authority of the nonce accounts [default: the fee payer]")
+}
+
+fn plan_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("plan_path")
+        .required(true)
+        .index(1)
+        .takes_value(true)
+        .value_name("PLAN_FILE")
+        .help("File written by the plan command")
+}
+
 pub(crate) fn get_matches<'a, I, T>(args: I) -> ArgMatches<'a>
 where
     I: IntoIterator<Item = T>,
@@ -165,28 +514,74 @@ where
                 .default_value(default_config_file)
                 .help("Config file"),
         )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .global(true)
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Name of a profile supplying defaults for omitted arguments"),
+        )
+        .arg(
+            Arg::with_name("profile_file")
+                .long("profile-file")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILEPATH")
+                .help("Profile file [default: stake-accounts.yml next to the config file]"),
+        )
         .arg(
             Arg::with_name("url")
                 .long("url")
                 .global(true)
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .value_name("URL")
-                .help("RPC entrypoint address. i.e. http://devnet.solana.com"),
+                .help(
+                    "RPC entrypoint address. i.e. http://devnet.solana.com. \
+                     Repeat to fail over to further nodes",
+                ),
+        )
+        .arg(
+            Arg::with_name("requests_per_second")
+                .long("requests-per-second")
+                .global(true)
+                .takes_value(true)
+                .value_name("NUMBER")
+                .validator(is_positive_number)
+                .help("Limit on the rate of RPC requests"),
+        )
+        .arg(
+            Arg::with_name("commitment")
+                .long("commitment")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["recent", "single", "root", "max"])
+                .value_name("COMMITMENT_LEVEL")
+                .help("Commitment level of account reads and of confirmations [default: max]"),
+        )
+        .arg(
+            Arg::with_name("finalized")
+                .long("finalized")
+                .global(true)
+                .takes_value(false)
+                .help("Wait for each transaction sent to be finalized before reporting success"),
+        )
+        .arg(
+            Arg::with_name("receipt")
+                .long("receipt")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Append a receipt of every transaction sent to this file"),
         )
         .subcommand(
             SubCommand::with_name("new")
                 .about("Create derived stake accounts")
                 .arg(fee_payer_arg())
                 .arg(sender_keypair_arg().index(1))
-                .arg(
-                    Arg::with_name("base_keypair")
-                        .required(true)
-                        .index(2)
-                        .takes_value(true)
-                        .value_name("BASE_KEYPAIR")
-                        .validator(is_valid_signer)
-                        .help("Keypair which stake account addresses are derived from"),
-                )
+                .arg(base_keypair_arg().index(2))
                 .arg(
                     Arg::with_name("amount")
                         .required(true)
@@ -199,7 +594,6 @@ where
                 .arg(
                     Arg::with_name("stake_authority")
                         .long("stake-authority")
-                        .required(true)
                         .takes_value(true)
                         .value_name("PUBKEY")
                         .validator(is_valid_pubkey)
@@ -208,7 +602,6 @@ where
                 .arg(
                     Arg::with_name("withdraw_authority")
                         .long("withdraw-authority")
-                        .required(true)
                         .takes_value(true)
                         .value_name("PUBKEY")
                         .validator(is_valid_pubkey)
@@ -218,154 +611,1176 @@ where
         .subcommand(
             SubCommand::with_name("count")
                 .about("Count derived stake accounts")
-                .arg(base_pubkey_arg().index(1)),
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg()),
         )
         .subcommand(
             SubCommand::with_name("pubkeys")
                 .about("Show public keys of all derived stake accounts")
-                .arg(base_pubkey_arg().index(1))
-                .arg(num_accounts_arg()),
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg()),
         )
         .subcommand(
             SubCommand::with_name("balance")
-                .about("Sum balances of all derived stake accounts")
-                .arg(base_pubkey_arg().index(1))
-                .arg(num_accounts_arg()),
+                .about("Show balances of all derived stake accounts by activation state")
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg()),
+        )
+        .subcommand(authorize_subcommand())
+        .subcommand(rebase_subcommand())
+        .subcommand(move_subcommand())
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("Save the state of all derived stake accounts to a file")
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg())
+                .arg(
+                    Arg::with_name("output_path")
+                        .long("output")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("File to write the snapshot to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Show what changed between two snapshots")
+                .arg(
+                    Arg::with_name("before_path")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .value_name("BEFORE")
+                        .help("Earlier snapshot file"),
+                )
+                .arg(
+                    Arg::with_name("after_path")
+                        .required(true)
+                        .index(2)
+                        .takes_value(true)
+                        .value_name("AFTER")
+                        .help("Later snapshot file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Poll derived stake accounts and alert on changes")
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg())
+                .arg(
+                    Arg::with_name("expected_stake_authority")
+                        .long("expected-stake-authority")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Alert if an account's stake authority is not this one"),
+                )
+                .arg(
+                    Arg::with_name("expected_withdraw_authority")
+                        .long("expected-withdraw-authority")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Alert if an account's withdraw authority is not this one"),
+                )
+                .arg(
+                    Arg::with_name("expected_custodian")
+                        .long("expected-custodian")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Alert if an account's lockup custodian is not this one"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .value_name("SECONDS")
+                        .default_value("60")
+                        .help("Seconds between polls"),
+                )
+                .arg(
+                    Arg::with_name("log_file")
+                        .long("log-file")
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("Append alerts to this file instead of printing them"),
+                )
+                .arg(
+                    Arg::with_name("alert_command")
+                        .long("exec")
+                        .takes_value(true)
+                        .value_name("COMMAND")
+                        .help("Shell command to run on each alert"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("List the transactions that touched derived stake accounts")
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg())
+                .arg(
+                    Arg::with_name("start_slot")
+                        .long("start-slot")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("SLOT")
+                        .help("First slot to search"),
+                )
+                .arg(
+                    Arg::with_name("end_slot")
+                        .long("end-slot")
+                        .takes_value(true)
+                        .value_name("SLOT")
+                        .help("Last slot to search [default: the current slot]"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("distribute")
+                .about("Create a derived stake account for each recipient in a CSV file")
+                .arg(fee_payer_arg())
+                .arg(sender_keypair_arg().index(1))
+                .arg(base_keypair_arg().index(2))
+                .arg(
+                    Arg::with_name("allocations_path")
+                        .long("allocations")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("CSV of recipient, amount, and optional lockup_date and custodian"),
+                )
+                .arg(
+                    Arg::with_name("db_path")
+                        .long("db-path")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("Database recording which stake accounts were created"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vest")
+                .about("Split a grant into derived stake accounts with graduated lockups")
+                .arg(fee_payer_arg())
+                .arg(sender_keypair_arg().index(1))
+                .arg(base_keypair_arg().index(2))
+                .arg(
+                    Arg::with_name("amount")
+                        .required(true)
+                        .index(3)
+                        .takes_value(true)
+                        .value_name("AMOUNT")
+                        .validator(is_amount)
+                        .help("Total amount of the grant, in SOL"),
+                )
+                .arg(
+                    Arg::with_name("stake_authority")
+                        .long("stake-authority")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Stake authority"),
+                )
+                .arg(
+                    Arg::with_name("withdraw_authority")
+                        .long("withdraw-authority")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Withdraw authority"),
+                )
+                .arg(
+                    Arg::with_name("custodian")
+                        .long("custodian")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Custodian who may lift the lockups"),
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("DATE")
+                        .validator(is_unix_timestamp)
+                        .help("Vesting start date, as YYYY-MM-DD"),
+                )
+                .arg(
+                    Arg::with_name("period")
+                        .long("period")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("PERIOD")
+                        .validator(is_period)
+                        .help("Time between tranches, such as 30d, 2w, 1mo or 1y"),
+                )
+                .arg(
+                    Arg::with_name("num_tranches")
+                        .long("tranches")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .help("Number of tranches, each in its own stake account"),
+                )
+                .arg(
+                    Arg::with_name("cliff")
+                        .long("cliff")
+                        .takes_value(true)
+                        .value_name("PERIOD")
+                        .validator(is_period)
+                        .help("Time after the start before which no tranche unlocks"),
+                ),
         )
         .subcommand(
-            SubCommand::with_name("authorize")
-                .about("Set new authorities in all derived stake accounts")
+            SubCommand::with_name("clawback")
+                .about("Withdraw derived stake accounts whose lockups have not expired")
                 .arg(fee_payer_arg())
                 .arg(base_pubkey_arg().index(1))
-                .arg(stake_authority_arg())
                 .arg(withdraw_authority_arg())
-                .arg(new_stake_authority_arg())
-                .arg(new_withdraw_authority_arg())
-                .arg(num_accounts_arg()),
+                .arg(
+                    Arg::with_name("custodian")
+                        .long("custodian")
+                        .takes_value(true)
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .help("Lockup custodian"),
+                )
+                .arg(
+                    Arg::with_name("treasury")
+                        .long("treasury")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .help("Account to withdraw the reclaimed lamports to"),
+                )
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg()),
         )
         .subcommand(
-            SubCommand::with_name("rebase")
-                .about("Relocate derived stake accounts")
+            SubCommand::with_name("unlocks")
+                .about("Show when the lockups of derived stake accounts expire")
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg())
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Check derived stake accounts against a policy")
+                .arg(base_pubkeys_arg().index(1))
+                .arg(base_pubkeys_file_arg())
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg())
+                .arg(
+                    Arg::with_name("policy_path")
+                        .long("policy")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("YAML file of the expected authorities, lockup, vote accounts and balance"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("delegate")
+                .about("Delegate derived stake accounts across vote accounts by weight")
                 .arg(fee_payer_arg())
-                .arg(base_pubkey_arg().index(1))
-                .arg(new_base_keypair_arg().index(2))
+                .arg(base_keypair_arg().index(1))
                 .arg(stake_authority_arg())
-                .arg(num_accounts_arg()),
+                .arg(
+                    Arg::with_name("weights_path")
+                        .long("weights")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("CSV of vote_account and weight"),
+                )
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg()),
         )
         .subcommand(
-            SubCommand::with_name("move")
-                .about("Rebase and set new authorities in all derived stake accounts")
+            SubCommand::with_name("rebalance")
+                .about("Move delegated stake toward new target weights, over several epochs")
+                .arg(fee_payer_arg())
+                .arg(base_keypair_arg().index(1))
+                .arg(stake_authority_arg())
+                .arg(
+                    Arg::with_name("weights_path")
+                        .long("weights")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("CSV of vote_account and weight"),
+                )
+                .arg(
+                    Arg::with_name("state_path")
+                        .long("state-file")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("File recording the rebalance's progress between runs"),
+                )
+                .arg(num_accounts_arg())
+                .arg(start_index_arg())
+                .arg(indices_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("run-plan")
+                .about("Send the steps of a plan as each becomes possible, polling until done")
+                .arg(
+                    Arg::with_name("plan_path")
+                        .index(1)
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("PLAN_FILE")
+                        .help("YAML file listing the steps"),
+                )
                 .arg(fee_payer_arg())
-                .arg(base_pubkey_arg().index(1))
-                .arg(new_base_keypair_arg().index(2))
                 .arg(stake_authority_arg())
                 .arg(withdraw_authority_arg())
-                .arg(new_stake_authority_arg())
-                .arg(new_withdraw_authority_arg())
-                .arg(num_accounts_arg()),
+                .arg(
+                    Arg::with_name("state_path")
+                        .long("state-file")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILEPATH")
+                        .help("File recording which steps are done"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .value_name("SECONDS")
+                        .default_value("60")
+                        .help("Seconds between polls"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Write the unsigned transactions of a command to a file, for signing elsewhere")
+                .subcommand(planned_subcommand(authorize_subcommand()))
+                .subcommand(planned_subcommand(rebase_subcommand()))
+                .subcommand(planned_subcommand(move_subcommand())),
+        )
+        .subcommand(
+            SubCommand::with_name("sign")
+                .about("Add one signer's signatures to a plan file")
+                .arg(plan_file_arg())
+                .arg(
+                    Arg::with_name("signer")
+                        .required(true)
+                        .index(2)
+                        .takes_value(true)
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .help("Keypair to sign with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("submit")
+                .about("Send the transactions of a fully signed plan file")
+                .arg(plan_file_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Update the confirmation status of the transactions in a receipt")
+                .arg(
+                    Arg::with_name("receipt_path")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .value_name("RECEIPT_FILE")
+                        .help("Receipt file written with --receipt"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("locate")
+                .about("Find the base, seed and index that derive a stake account address")
+                .arg(
+                    Arg::with_name("address")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .value_name("ADDRESS")
+                        .validator(is_valid_pubkey)
+                        .help("Stake account address"),
+                )
+                .arg(base_pubkeys_arg().index(2))
+                .arg(base_pubkeys_file_arg())
+                .arg(
+                    Arg::with_name("search_limit")
+                        .long("search-limit")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("1000")
+                        .help("Number of indices to search of each base"),
+                ),
         )
         .get_matches_from(args)
 }
 
-fn parse_new_args(matches: &ArgMatches<'_>) -> NewCommandConfig {
-    let fee_payer = value_t_or_exit!(matches, "fee_payer", String);
+// Return the value of the given argument, or if absent, the profile's value.
+fn value_or_profile(
+    matches: &ArgMatches<'_>,
+    name: &str,
+    profile_value: &Option<String>,
+) -> Result<String, Error> {
+    matches
+        .value_of(name)
+        .map(|value| value.to_string())
+        .or_else(|| profile_value.clone())
+        .ok_or_else(|| not_provided(name))
+}
+
+fn not_provided(name: &str) -> Error {
+    Error::Config(format!(
+        "The argument '{}' was not provided and is not set in the profile",
+        name
+    ))
+}
+
+// Return the public keys in a base pubkeys file, skipping blank lines and '#'
+// comments, or the first line that isn't a public key.
+fn parse_base_pubkeys_file(contents: &str) -> Result<Vec<String>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            is_valid_pubkey(line.to_string())
+                .map(|()| line.to_string())
+                .map_err(|err| format!("line {}: {}", line_number, err))
+        })
+        .collect()
+}
+
+// Return the base pubkeys given as arguments and in the base pubkeys file.
+fn base_pubkeys_of(matches: &ArgMatches<'_>) -> Result<Vec<String>, Error> {
+    let mut base_pubkeys: Vec<String> = matches
+        .values_of("base_pubkey")
+        .map(|values| values.map(|value| value.to_string()).collect())
+        .unwrap_or_default();
+    if let Some(path) = matches.value_of("base_pubkeys_file") {
+        let contents =
+            fs::read_to_string(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+        let file_pubkeys = parse_base_pubkeys_file(&contents)
+            .map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+        base_pubkeys.extend(file_pubkeys);
+    }
+    Ok(base_pubkeys)
+}
+
+// Return the base pubkeys given as arguments and in the base pubkeys file, or
+// if there are none, the profile's.
+fn base_pubkeys_or_profile(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<Vec<String>, Error> {
+    let mut base_pubkeys = base_pubkeys_of(matches)?;
+    if base_pubkeys.is_empty() {
+        base_pubkeys.push(value_or_profile(
+            matches,
+            "base_pubkey",
+            &profile.base_pubkey,
+        )?);
+    }
+    Ok(base_pubkeys)
+}
+
+fn num_accounts_or_profile(matches: &ArgMatches<'_>, profile: &Profile) -> Result<usize, Error> {
+    if matches.is_present("num_accounts") {
+        return value_t!(matches, "num_accounts", usize)
+            .map_err(|err| Error::Config(err.to_string()));
+    }
+    profile
+        .num_accounts
+        .ok_or_else(|| not_provided("num_accounts"))
+}
+
+// Return the indices given by --indices, or else those from --start-index up
+// to the number of accounts.
+fn indices_or_profile(matches: &ArgMatches<'_>, profile: &Profile) -> Result<Vec<usize>, Error> {
+    if let Some(indices) = matches.value_of("indices") {
+        return parse_indices(indices).map_err(Error::Config);
+    }
+    let start_index = if matches.is_present("start_index") {
+        value_t!(matches, "start_index", usize).map_err(|err| Error::Config(err.to_string()))?
+    } else {
+        0
+    };
+    Ok((start_index..num_accounts_or_profile(matches, profile)?).collect())
+}
+
+fn parse_new_args(matches: &ArgMatches<'_>, profile: &Profile) -> Result<NewCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
     let sender_keypair = value_t_or_exit!(matches, "sender_keypair", String);
     let lamports = sol_to_lamports(value_t_or_exit!(matches, "amount", f64));
     let base_keypair = value_t_or_exit!(matches, "base_keypair", String);
-    let stake_authority = value_t_or_exit!(matches, "stake_authority", String);
-    let withdraw_authority = value_t_or_exit!(matches, "withdraw_authority", String);
-    NewCommandConfig {
+    let stake_authority = value_or_profile(matches, "stake_authority", &profile.stake_authority)?;
+    let withdraw_authority =
+        value_or_profile(matches, "withdraw_authority", &profile.withdraw_authority)?;
+    Ok(NewCommandConfig {
         fee_payer,
         sender_keypair,
         lamports,
         base_keypair,
         stake_authority,
         withdraw_authority,
-    }
+    })
 }
 
-fn parse_count_args(matches: &ArgMatches<'_>) -> CountCommandConfig {
-    let base_pubkey = value_t_or_exit!(matches, "base_pubkey", String);
-    CountCommandConfig { base_pubkey }
+fn parse_count_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<CountCommandConfig, Error> {
+    let base_pubkeys = base_pubkeys_or_profile(matches, profile)?;
+    Ok(CountCommandConfig { base_pubkeys })
 }
 
-fn parse_query_args(matches: &ArgMatches<'_>) -> QueryCommandConfig {
-    let base_pubkey = value_t_or_exit!(matches, "base_pubkey", String);
-    let num_accounts = value_t_or_exit!(matches, "num_accounts", usize);
-    QueryCommandConfig {
-        base_pubkey,
-        num_accounts,
-    }
+fn parse_query_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<QueryCommandConfig, Error> {
+    let base_pubkeys = base_pubkeys_or_profile(matches, profile)?;
+    let indices = indices_or_profile(matches, profile)?;
+    Ok(QueryCommandConfig {
+        base_pubkeys,
+        indices,
+    })
 }
 
-fn parse_authorize_args(matches: &ArgMatches<'_>) -> AuthorizeCommandConfig {
-    let fee_payer = value_t_or_exit!(matches, "fee_payer", String);
-    let base_pubkey = value_t_or_exit!(matches, "base_pubkey", String);
-    let stake_authority = value_t_or_exit!(matches, "stake_authority", String);
-    let withdraw_authority = value_t_or_exit!(matches, "withdraw_authority", String);
-    let new_stake_authority = value_t_or_exit!(matches, "new_stake_authority", String);
-    let new_withdraw_authority = value_t_or_exit!(matches, "new_withdraw_authority", String);
-    let num_accounts = value_t_or_exit!(matches, "num_accounts", usize);
-    AuthorizeCommandConfig {
+fn parse_authorize_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<AuthorizeCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    let base_pubkeys = base_pubkeys_or_profile(matches, profile)?;
+    let stake_authority = value_or_profile(matches, "stake_authority", &profile.stake_authority)?;
+    let withdraw_authority =
+        value_or_profile(matches, "withdraw_authority", &profile.withdraw_authority)?;
+    let new_stake_authority =
+        value_or_profile(matches, "new_stake_authority", &profile.new_stake_authority)?;
+    let new_withdraw_authority = value_or_profile(
+        matches,
+        "new_withdraw_authority",
+        &profile.new_withdraw_authority,
+    )?;
+    let indices = indices_or_profile(matches, profile)?;
+    Ok(AuthorizeCommandConfig {
         fee_payer,
-        base_pubkey,
+        base_pubkeys,
         stake_authority,
         withdraw_authority,
         new_stake_authority,
         new_withdraw_authority,
-        num_accounts,
-    }
+        indices,
+    })
 }
 
-fn parse_rebase_args(matches: &ArgMatches<'_>) -> RebaseCommandConfig {
-    let fee_payer = value_t_or_exit!(matches, "fee_payer", String);
+// Return the base pubkey and new base keypair of `rebase` and `move`. Both are
+// positional, so when the profile supplies the base pubkey, a lone positional
+// argument is the new base keypair.
+fn parse_base_and_new_base_args(matches: &ArgMatches<'_>, profile: &Profile) -> (String, String) {
+    if !matches.is_present("new_base_keypair") && profile.base_pubkey.is_some() {
+        let new_base_keypair = value_t_or_exit!(matches, "base_pubkey", String);
+        let base_pubkey = profile.base_pubkey.clone().unwrap();
+        return (base_pubkey, new_base_keypair);
+    }
     let base_pubkey = value_t_or_exit!(matches, "base_pubkey", String);
     let new_base_keypair = value_t_or_exit!(matches, "new_base_keypair", String);
-    let stake_authority = value_t_or_exit!(matches, "stake_authority", String);
-    let num_accounts = value_t_or_exit!(matches, "num_accounts", usize);
-    RebaseCommandConfig {
+    (base_pubkey, new_base_keypair)
+}
+
+fn parse_rebase_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<RebaseCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    let (base_pubkey, new_base_keypair) = parse_base_and_new_base_args(matches, profile);
+    let stake_authority = value_or_profile(matches, "stake_authority", &profile.stake_authority)?;
+    let indices = indices_or_profile(matches, profile)?;
+    Ok(RebaseCommandConfig {
         fee_payer,
         base_pubkey,
         new_base_keypair,
         stake_authority,
-        num_accounts,
-    }
+        indices,
+    })
 }
 
-fn parse_move_args(matches: &ArgMatches<'_>) -> MoveCommandConfig {
-    let rebase_config = parse_rebase_args(matches);
-    let authorize_config = parse_authorize_args(matches);
-    MoveCommandConfig {
+fn parse_move_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<MoveCommandConfig, Error> {
+    let rebase_config = parse_rebase_args(matches, profile)?;
+    let mut authorize_config = parse_authorize_args(matches, profile)?;
+    authorize_config.base_pubkeys = vec![rebase_config.base_pubkey.clone()];
+    Ok(MoveCommandConfig {
         rebase_config,
         authorize_config,
+    })
+}
+
+fn parse_snapshot_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<SnapshotCommandConfig, Error> {
+    let query_config = parse_query_args(matches, profile)?;
+    let output_path = value_t_or_exit!(matches, "output_path", String);
+    Ok(SnapshotCommandConfig {
+        query_config,
+        output_path,
+    })
+}
+
+fn parse_diff_args(matches: &ArgMatches<'_>) -> DiffCommandConfig {
+    let before_path = value_t_or_exit!(matches, "before_path", String);
+    let after_path = value_t_or_exit!(matches, "after_path", String);
+    DiffCommandConfig {
+        before_path,
+        after_path,
     }
 }
 
-pub(crate) fn parse_args<'a, I, T>(args: I) -> CommandConfig
+fn parse_watch_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<WatchCommandConfig, Error> {
+    let query_config = parse_query_args(matches, profile)?;
+    let value_or = |name, profile_value: &Option<String>| {
+        matches
+            .value_of(name)
+            .map(|value| value.to_string())
+            .or_else(|| profile_value.clone())
+    };
+    let expected_stake_authority = value_or("expected_stake_authority", &profile.stake_authority);
+    let expected_withdraw_authority =
+        value_or("expected_withdraw_authority", &profile.withdraw_authority);
+    let expected_custodian = value_or("expected_custodian", &profile.custodian);
+    let interval_secs = value_t_or_exit!(matches, "interval", u64);
+    let log_file = matches.value_of("log_file").map(|value| value.to_string());
+    let alert_command = matches
+        .value_of("alert_command")
+        .map(|value| value.to_string());
+    Ok(WatchCommandConfig {
+        query_config,
+        expected_stake_authority,
+        expected_withdraw_authority,
+        expected_custodian,
+        interval_secs,
+        log_file,
+        alert_command,
+    })
+}
+
+fn parse_output_format(matches: &ArgMatches<'_>) -> OutputFormat {
+    match matches.value_of("output_format").unwrap() {
+        "json" => OutputFormat::Json,
+        "csv" => OutputFormat::Csv,
+        _ => OutputFormat::Table,
+    }
+}
+
+fn parse_history_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<HistoryCommandConfig, Error> {
+    let query_config = parse_query_args(matches, profile)?;
+    let start_slot = value_t_or_exit!(matches, "start_slot", Slot);
+    let end_slot = if matches.is_present("end_slot") {
+        Some(value_t_or_exit!(matches, "end_slot", Slot))
+    } else {
+        None
+    };
+    let output_format = parse_output_format(matches);
+    Ok(HistoryCommandConfig {
+        query_config,
+        start_slot,
+        end_slot,
+        output_format,
+    })
+}
+
+/// Parse a date as YYYY-MM-DD (midnight UTC), an RFC 3339 date and time, or
+/// seconds since the Unix epoch.
+pub(crate) fn parse_unix_timestamp(value: &str) -> Result<UnixTimestamp, String> {
+    if let Ok(unix_timestamp) = value.parse::<UnixTimestamp>() {
+        return Ok(unix_timestamp);
+    }
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date_time.timestamp());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms(0, 0, 0).timestamp())
+        .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD", value))
+}
+
+fn parse_distribute_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<DistributeCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    let sender_keypair = value_t_or_exit!(matches, "sender_keypair", String);
+    let base_keypair = value_t_or_exit!(matches, "base_keypair", String);
+    let allocations_path = value_t_or_exit!(matches, "allocations_path", String);
+    let db_path = value_t_or_exit!(matches, "db_path", String);
+    Ok(DistributeCommandConfig {
+        fee_payer,
+        sender_keypair,
+        base_keypair,
+        allocations_path,
+        db_path,
+    })
+}
+
+fn parse_vest_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<VestCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    let sender_keypair = value_t_or_exit!(matches, "sender_keypair", String);
+    let base_keypair = value_t_or_exit!(matches, "base_keypair", String);
+    let lamports = sol_to_lamports(value_t_or_exit!(matches, "amount", f64));
+    let stake_authority = value_or_profile(matches, "stake_authority", &profile.stake_authority)?;
+    let withdraw_authority =
+        value_or_profile(matches, "withdraw_authority", &profile.withdraw_authority)?;
+    let custodian = matches
+        .value_of("custodian")
+        .map(|value| value.to_string())
+        .or_else(|| profile.custodian.clone());
+    let start = parse_unix_timestamp(matches.value_of("start").unwrap()).unwrap();
+    let period = value_t_or_exit!(matches, "period", Period);
+    let num_tranches = value_t_or_exit!(matches, "num_tranches", usize);
+    if num_tranches == 0 {
+        return Err(Error::Config("--tranches must be at least 1".to_string()));
+    }
+    let cliff = if matches.is_present("cliff") {
+        Some(value_t_or_exit!(matches, "cliff", Period))
+    } else {
+        None
+    };
+    Ok(VestCommandConfig {
+        fee_payer,
+        sender_keypair,
+        base_keypair,
+        lamports,
+        stake_authority,
+        withdraw_authority,
+        custodian,
+        start,
+        period,
+        num_tranches,
+        cliff,
+    })
+}
+
+fn parse_clawback_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<ClawbackCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    let base_pubkey = value_or_profile(matches, "base_pubkey", &profile.base_pubkey)?;
+    let withdraw_authority =
+        value_or_profile(matches, "withdraw_authority", &profile.withdraw_authority)?;
+    let custodian = value_or_profile(matches, "custodian", &profile.custodian)?;
+    let treasury = value_t_or_exit!(matches, "treasury", String);
+    let indices = indices_or_profile(matches, profile)?;
+    Ok(ClawbackCommandConfig {
+        fee_payer,
+        base_pubkey,
+        withdraw_authority,
+        custodian,
+        treasury,
+        indices,
+    })
+}
+
+fn parse_unlocks_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<UnlocksCommandConfig, Error> {
+    let query_config = parse_query_args(matches, profile)?;
+    let output_format = parse_output_format(matches);
+    Ok(UnlocksCommandConfig {
+        query_config,
+        output_format,
+    })
+}
+
+fn parse_audit_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<AuditCommandConfig, Error> {
+    let query_config = parse_query_args(matches, profile)?;
+    let policy_path = value_t_or_exit!(matches, "policy_path", String);
+    let output_format = parse_output_format(matches);
+    Ok(AuditCommandConfig {
+        query_config,
+        policy_path,
+        output_format,
+    })
+}
+
+fn parse_delegate_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<DelegateCommandConfig, Error> {
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    // The profile's base is a public key, which can't sign for splits.
+    let base_keypair = value_t_or_exit!(matches, "base_keypair", String);
+    let stake_authority = value_or_profile(matches, "stake_authority", &profile.stake_authority)?;
+    let weights_path = value_t_or_exit!(matches, "weights_path", String);
+    let indices = indices_or_profile(matches, profile)?;
+    Ok(DelegateCommandConfig {
+        fee_payer,
+        base_keypair,
+        stake_authority,
+        weights_path,
+        indices,
+    })
+}
+
+fn parse_rebalance_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<RebalanceCommandConfig, Error> {
+    let delegate_config = parse_delegate_args(matches, profile)?;
+    let state_path = value_t_or_exit!(matches, "state_path", String);
+    Ok(RebalanceCommandConfig {
+        delegate_config,
+        state_path,
+    })
+}
+
+fn parse_run_plan_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<RunPlanCommandConfig, Error> {
+    let value_or = |name, profile_value: &Option<String>| {
+        matches
+            .value_of(name)
+            .map(|value| value.to_string())
+            .or_else(|| profile_value.clone())
+    };
+    let fee_payer = value_or_profile(matches, "fee_payer", &profile.fee_payer)?;
+    let stake_authority = value_or("stake_authority", &profile.stake_authority);
+    let withdraw_authority = value_or("withdraw_authority", &profile.withdraw_authority);
+    let plan_path = value_t_or_exit!(matches, "plan_path", String);
+    let state_path = value_t_or_exit!(matches, "state_path", String);
+    let interval_secs = value_t_or_exit!(matches, "interval", u64);
+    Ok(RunPlanCommandConfig {
+        fee_payer,
+        stake_authority,
+        withdraw_authority,
+        plan_path,
+        state_path,
+        interval_secs,
+    })
+}
+
+fn parse_plan_args(
+    matches: &ArgMatches<'_>,
+    profile: &Profile,
+) -> Result<PlanCommandConfig, Error> {
+    let (command, matches) = match matches.subcommand() {
+        ("authorize", Some(matches)) => (
+            PlannedCommand::Authorize(parse_authorize_args(matches, profile)?),
+            matches,
+        ),
+        ("rebase", Some(matches)) => (
+            PlannedCommand::Rebase(parse_rebase_args(matches, profile)?),
+            matches,
+        ),
+        ("move", Some(matches)) => (
+            PlannedCommand::Move(parse_move_args(matches, profile)?),
+            matches,
+        ),
+        _ => return Err(Error::Config(matches.usage().to_string())),
+    };
+    let output_path = value_t_or_exit!(matches, "output_path", String);
+    let nonce_accounts = values_t_or_exit!(matches, "nonce", String);
+    let fee_payer = match &command {
+        PlannedCommand::Authorize(config) => &config.fee_payer,
+        PlannedCommand::Rebase(config) => &config.fee_payer,
+        PlannedCommand::Move(config) => &config.authorize_config.fee_payer,
+    };
+    let nonce_authority = matches
+        .value_of("nonce_authority")
+        .map_or_else(|| fee_payer.clone(), |value| value.to_string());
+    Ok(PlanCommandConfig {
+        command,
+        output_path,
+        nonce_accounts,
+        nonce_authority,
+    })
+}
+
+fn parse_sign_args(matches: &ArgMatches<'_>) -> SignCommandConfig {
+    let plan_path = value_t_or_exit!(matches, "plan_path", String);
+    let signer = value_t_or_exit!(matches, "signer", String);
+    SignCommandConfig { plan_path, signer }
+}
+
+fn parse_submit_args(matches: &ArgMatches<'_>) -> SubmitCommandConfig {
+    let plan_path = value_t_or_exit!(matches, "plan_path", String);
+    SubmitCommandConfig { plan_path }
+}
+
+fn parse_commitment(value: &str) -> CommitmentConfig {
+    let commitment = match value {
+        "recent" => CommitmentLevel::Recent,
+        "single" => CommitmentLevel::Single,
+        "root" => CommitmentLevel::Root,
+        _ => CommitmentLevel::Max,
+    };
+    CommitmentConfig { commitment }
+}
+
+fn parse_status_args(matches: &ArgMatches<'_>) -> StatusCommandConfig {
+    let receipt_path = value_t_or_exit!(matches, "receipt_path", String);
+    StatusCommandConfig { receipt_path }
+}
+
+fn parse_locate_args(
+    matches: &ArgMatches<'_>,
+    config_file: &str,
+) -> Result<LocateCommandConfig, Error> {
+    let address = value_t_or_exit!(matches, "address", String);
+    // Bases given as arguments are searched under every seed scheme, and the
+    // base of each profile in the profile file under the profile's scheme.
+    let mut bases: Vec<_> = base_pubkeys_of(matches)?
+        .into_iter()
+        .flat_map(|base_pubkey| {
+            SeedScheme::ALL
+                .iter()
+                .map(move |seed_scheme| (base_pubkey.clone(), *seed_scheme))
+        })
+        .collect();
+    let path = profile_file(matches, config_file);
+    if matches.is_present("profile_file") || Path::new(&path).exists() {
+        let mut profiles: Vec<_> = load_profiles(&path)?.into_iter().collect();
+        profiles.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (_, profile) in profiles {
+            if let Some(base_pubkey) = profile.base_pubkey {
+                bases.push((base_pubkey, profile.seed_scheme));
+            }
+        }
+    }
+    if bases.is_empty() {
+        return Err(not_provided("base_pubkey"));
+    }
+    let search_limit = value_t_or_exit!(matches, "search_limit", usize);
+    Ok(LocateCommandConfig {
+        address,
+        bases,
+        search_limit,
+    })
+}
+
+// Return the path of the profile file given by --profile-file, or else the
+// one next to the config file.
+fn profile_file(matches: &ArgMatches<'_>, config_file: &str) -> String {
+    matches
+        .value_of("profile_file")
+        .map(|path| path.to_string())
+        .unwrap_or_else(|| default_profile_file(config_file))
+}
+
+fn parse_profile(matches: &ArgMatches<'_>, config_file: &str) -> Result<Profile, Error> {
+    let name = match matches.value_of("profile") {
+        Some(name) => name,
+        None => return Ok(Profile::default()),
+    };
+    load_profile(&profile_file(matches, config_file), name)
+}
+
+pub(crate) fn parse_args<'a, I, T>(args: I) -> Result<CommandConfig, Error>
 where
     I: IntoIterator<Item = T>,
     T: Into<OsString> + Clone,
 {
     let matches = get_matches(args);
     let config_file = matches.value_of("config_file").unwrap().to_string();
-    let url = matches.value_of("url").map(|x| x.to_string());
+    let (_, sub_matches) = matches.subcommand();
+    let profile = parse_profile(sub_matches.unwrap_or(&matches), &config_file)?;
+    let mut urls: Vec<_> = sub_matches
+        .and_then(|matches| matches.values_of("url"))
+        .map(|values| values.map(|x| x.to_string()).collect())
+        .unwrap_or_default();
+    if urls.is_empty() {
+        urls = profile.url.iter().chain(&profile.urls).cloned().collect();
+    }
+    let requests_per_second = sub_matches
+        .and_then(|matches| matches.value_of("requests_per_second"))
+        .map(|value| value.parse().unwrap());
+    let commitment = sub_matches
+        .and_then(|matches| matches.value_of("commitment"))
+        .map(parse_commitment)
+        .unwrap_or_default();
+    let finalized = sub_matches.map_or(false, |matches| matches.is_present("finalized"));
+    let receipt_path = sub_matches
+        .and_then(|matches| matches.value_of("receipt"))
+        .map(|x| x.to_string());
 
     let command = match matches.subcommand() {
-        ("new", Some(matches)) => Command::New(parse_new_args(matches)),
-        ("count", Some(matches)) => Command::Count(parse_count_args(matches)),
-        ("pubkeys", Some(matches)) => Command::Pubkeys(parse_query_args(matches)),
-        ("balance", Some(matches)) => Command::Balance(parse_query_args(matches)),
-        ("authorize", Some(matches)) => Command::Authorize(parse_authorize_args(matches)),
-        ("rebase", Some(matches)) => Command::Rebase(parse_rebase_args(matches)),
-        ("move", Some(matches)) => Command::Move(parse_move_args(matches)),
-        _ => {
-            eprintln!("{}", matches.usage());
-            exit(1);
+        ("new", Some(matches)) => Command::New(parse_new_args(matches, &profile)?),
+        ("count", Some(matches)) => Command::Count(parse_count_args(matches, &profile)?),
+        ("pubkeys", Some(matches)) => Command::Pubkeys(parse_query_args(matches, &profile)?),
+        ("balance", Some(matches)) => Command::Balance(parse_query_args(matches, &profile)?),
+        ("authorize", Some(matches)) => {
+            Command::Authorize(parse_authorize_args(matches, &profile)?)
+        }
+        ("rebase", Some(matches)) => Command::Rebase(parse_rebase_args(matches, &profile)?),
+        ("move", Some(matches)) => Command::Move(parse_move_args(matches, &profile)?),
+        ("snapshot", Some(matches)) => Command::Snapshot(parse_snapshot_args(matches, &profile)?),
+        ("diff", Some(matches)) => Command::Diff(parse_diff_args(matches)),
+        ("watch", Some(matches)) => Command::Watch(parse_watch_args(matches, &profile)?),
+        ("history", Some(matches)) => Command::History(parse_history_args(matches, &profile)?),
+        ("distribute", Some(matches)) => {
+            Command::Distribute(parse_distribute_args(matches, &profile)?)
         }
+        ("vest", Some(matches)) => Command::Vest(parse_vest_args(matches, &profile)?),
+        ("clawback", Some(matches)) => Command::Clawback(parse_clawback_args(matches, &profile)?),
+        ("unlocks", Some(matches)) => Command::Unlocks(parse_unlocks_args(matches, &profile)?),
+        ("audit", Some(matches)) => Command::Audit(parse_audit_args(matches, &profile)?),
+        ("delegate", Some(matches)) => Command::Delegate(parse_delegate_args(matches, &profile)?),
+        ("rebalance", Some(matches)) => {
+            Command::Rebalance(parse_rebalance_args(matches, &profile)?)
+        }
+        ("run-plan", Some(matches)) => Command::RunPlan(parse_run_plan_args(matches, &profile)?),
+        ("plan", Some(matches)) => Command::Plan(parse_plan_args(matches, &profile)?),
+        ("sign", Some(matches)) => Command::Sign(parse_sign_args(matches)),
+        ("submit", Some(matches)) => Command::Submit(parse_submit_args(matches)),
+        ("status", Some(matches)) => Command::Status(parse_status_args(matches)),
+        ("locate", Some(matches)) => Command::Locate(parse_locate_args(matches, &config_file)?),
+        _ => return Err(Error::Config(matches.usage().to_string())),
     };
-    CommandConfig {
+    Ok(CommandConfig {
         config_file,
-        url,
+        urls,
+        requests_per_second,
+        commitment,
+        finalized,
+        receipt_path,
         command,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+
+    #[test]
+    fn test_parse_indices() {
+        assert_eq!(parse_indices("3,7,42").unwrap(), vec![3, 7, 42]);
+        assert_eq!(parse_indices("10-12").unwrap(), vec![10, 11, 12]);
+        assert_eq!(parse_indices("5").unwrap(), vec![5]);
+
+        // Lists and ranges combine in order, without repeats.
+        assert_eq!(parse_indices("10-12, 3,11,3").unwrap(), vec![3, 10, 11, 12]);
+
+        assert!(parse_indices("12-10").is_err());
+        assert!(parse_indices("").is_err());
+        assert!(parse_indices("1,,2").is_err());
+        assert!(parse_indices("a").is_err());
+        assert!(parse_indices("1-").is_err());
+        assert!(parse_indices("-1").is_err());
+        assert!(parse_indices("0-10000000000").is_err());
+        assert!(parse_indices(&format!("0-{}", MAX_INDICES - 1)).is_ok());
+        assert!(parse_indices(&format!("0-{},{}", MAX_INDICES - 1, MAX_INDICES)).is_err());
+    }
+
+    #[test]
+    fn test_parse_base_pubkeys_file() {
+        let pubkeys = [Pubkey::new_rand(), Pubkey::new_rand()];
+        let contents = format!(
+            "# Treasury bases\n{}\n\n  {}  \n   # retired\n",
+            pubkeys[0], pubkeys[1]
+        );
+        assert_eq!(
+            parse_base_pubkeys_file(&contents).unwrap(),
+            vec![pubkeys[0].to_string(), pubkeys[1].to_string()]
+        );
+        assert_eq!(parse_base_pubkeys_file("").unwrap(), Vec::<String>::new());
+
+        let contents = format!("{}\n# next\nnot-a-pubkey\n", pubkeys[0]);
+        let err = parse_base_pubkeys_file(&contents).unwrap_err();
+        assert!(err.starts_with("line 3: "));
+    }
+
+    #[test]
+    fn test_is_period() {
+        assert!(is_period("30d".to_string()).is_ok());
+        assert!(is_period("4y".to_string()).is_ok());
+        assert!(is_period("0mo".to_string()).is_err());
+        assert!(is_period("4294967295mo".to_string()).is_err());
+        assert!(is_period("4294967295y".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_args_override_profile() {
+        let profile = Profile {
+            base_pubkey: Some(Pubkey::new_rand().to_string()),
+            num_accounts: Some(12),
+            ..Profile::default()
+        };
+        let base_pubkey = Pubkey::new_rand().to_string();
+        let args = vec![
+            "solana-stake-accounts",
+            "balance",
+            &base_pubkey,
+            "--num-accounts",
+            "3",
+        ];
+        let matches = get_matches(args);
+        let matches = matches.subcommand_matches("balance").unwrap();
+        assert_eq!(
+            value_or_profile(matches, "base_pubkey", &profile.base_pubkey).unwrap(),
+            base_pubkey
+        );
+        assert_eq!(num_accounts_or_profile(matches, &profile).unwrap(), 3);
+        assert_eq!(
+            indices_or_profile(matches, &profile).unwrap(),
+            vec![0, 1, 2]
+        );
+
+        // Absent arguments fall back to the profile's values.
+        let matches = get_matches(vec!["solana-stake-accounts", "balance"]);
+        let matches = matches.subcommand_matches("balance").unwrap();
+        assert_eq!(
+            value_or_profile(matches, "base_pubkey", &profile.base_pubkey).unwrap(),
+            profile.base_pubkey.clone().unwrap()
+        );
+        assert_eq!(num_accounts_or_profile(matches, &profile).unwrap(), 12);
+
+        // With neither, the argument is reported missing.
+        assert!(value_or_profile(matches, "base_pubkey", &None).is_err());
+        assert!(num_accounts_or_profile(matches, &Profile::default()).is_err());
     }
 }
@@ -0,0 +1,508 @@
+use crate::args::{parse_unix_timestamp, OutputFormat};
+use crate::error::Error;
+use crate::lockup::{format_unix_timestamp, get_lockup, DATE_TIME_FORMAT};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, UnixTimestamp},
+    native_token::{lamports_to_sol, sol_to_lamports},
+    pubkey::Pubkey,
+};
+use solana_stake_program::stake_state::StakeState;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::u64;
+
+/// The policy file, in YAML. Checks whose values are absent are skipped.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyRecord {
+    staker: Option<String>,
+    withdrawer: Option<String>,
+    custodian: Option<String>,
+    min_lockup_date: Option<String>,
+    min_lockup_epoch: Option<Epoch>,
+    allowed_vote_accounts: Option<Vec<String>>,
+    min_balance: Option<f64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Policy {
+    pub staker: Option<Pubkey>,
+    pub withdrawer: Option<Pubkey>,
+    pub custodian: Option<Pubkey>,
+    pub min_lockup_unix_timestamp: Option<UnixTimestamp>,
+    pub min_lockup_epoch: Option<Epoch>,
+    pub allowed_vote_accounts: Option<Vec<Pubkey>>,
+    pub min_lamports: Option<u64>,
+}
+
+/// How bad a finding is. A wrong authority or custodian is critical, since
+/// someone else may control the account.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    Pass,
+    Warn,
+    Fail,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Pass => "PASS",
+            Severity::Warn => "WARN",
+            Severity::Fail => "FAIL",
+            Severity::Critical => "CRITICAL",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct Finding {
+    pub check: &'static str,
+    pub severity: Severity,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct AccountAudit {
+    pub base_pubkey: String,
+    pub index: usize,
+    pub address: String,
+    pub findings: Vec<Finding>,
+}
+
+impl AccountAudit {
+    pub fn severity(&self) -> Severity {
+        self.findings
+            .iter()
+            .map(|finding| finding.severity)
+            .max()
+            .unwrap_or(Severity::Pass)
+    }
+}
+
+/// An authority and the indexes of the accounts of a base pubkey it holds a role in.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct AuthorityEntry {
+    pub authority: String,
+    pub role: &'static str,
+    pub base_pubkey: String,
+    pub indexes: Vec<usize>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct AuditReport {
+    pub severity: Severity,
+    pub accounts: Vec<AccountAudit>,
+    pub authorities: Vec<AuthorityEntry>,
+}
+
+fn parse_pubkey(name: &str, value: &str) -> Result<Pubkey, String> {
+    Pubkey::from_str(value).map_err(|err| format!("invalid {} {}: {:?}", name, value, err))
+}
+
+fn parse_optional_pubkey(name: &str, value: Option<String>) -> Result<Option<Pubkey>, String> {
+    value.map(|value| parse_pubkey(name, &value)).transpose()
+}
+
+pub(crate) fn read_policy<R: Read>(reader: R) -> Result<Policy, String> {
+    let record: PolicyRecord = serde_yaml::from_reader(reader).map_err(|err| err.to_string())?;
+    let allowed_vote_accounts = record
+        .allowed_vote_accounts
+        .map(|vote_accounts| {
+            vote_accounts
+                .iter()
+                .map(|vote_account| parse_pubkey("vote account", vote_account))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    Ok(Policy {
+        staker: parse_optional_pubkey("staker", record.staker)?,
+        withdrawer: parse_optional_pubkey("withdrawer", record.withdrawer)?,
+        custodian: parse_optional_pubkey("custodian", record.custodian)?,
+        min_lockup_unix_timestamp: record
+            .min_lockup_date
+            .map(|date| parse_unix_timestamp(&date))
+            .transpose()?,
+        min_lockup_epoch: record.min_lockup_epoch,
+        allowed_vote_accounts,
+        min_lamports: record.min_balance.map(sol_to_lamports),
+    })
+}
+
+pub(crate) fn read_policy_file(path: &str) -> Result<Policy, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    read_policy(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+fn finding(check: &'static str, severity: Severity, detail: String) -> Finding {
+    Finding {
+        check,
+        severity,
+        detail,
+    }
+}
+
+fn check_pubkey(check: &'static str, actual: &Pubkey, expected: Option<Pubkey>) -> Option<Finding> {
+    let expected = expected?;
+    Some(if *actual == expected {
+        finding(check, Severity::Pass, actual.to_string())
+    } else {
+        finding(
+            check,
+            Severity::Critical,
+            format!("{}, expected {}", actual, expected),
+        )
+    })
+}
+
+/// Check a derived stake account against the policy, one finding per check.
+/// An account that is missing or not an initialized stake account fails, and
+/// is not checked further.
+pub(crate) fn audit_account(account: Option<&Account>, policy: &Policy) -> Vec<Finding> {
+    let state = account.and_then(StakeState::from);
+    let (account, meta, stake) = match (account, state) {
+        (Some(account), Some(StakeState::Initialized(meta))) => (account, meta, None),
+        (Some(account), Some(StakeState::Stake(meta, stake))) => (account, meta, Some(stake)),
+        (None, _) => {
+            return vec![finding(
+                "initialized",
+                Severity::Fail,
+                "missing".to_string(),
+            )];
+        }
+        _ => {
+            return vec![finding(
+                "initialized",
+                Severity::Fail,
+                "not an initialized stake account".to_string(),
+            )];
+        }
+    };
+    let mut findings = vec![finding("initialized", Severity::Pass, String::new())];
+    findings.extend(check_pubkey(
+        "staker",
+        &meta.authorized.staker,
+        policy.staker,
+    ));
+    findings.extend(check_pubkey(
+        "withdrawer",
+        &meta.authorized.withdrawer,
+        policy.withdrawer,
+    ));
+    findings.extend(check_pubkey(
+        "custodian",
+        &meta.lockup.custodian,
+        policy.custodian,
+    ));
+
+    let lockup = &meta.lockup;
+    let min_unix_timestamp = policy.min_lockup_unix_timestamp;
+    let min_epoch = policy.min_lockup_epoch;
+    if min_unix_timestamp.is_some() || min_epoch.is_some() {
+        let detail = format!(
+            "until {} or epoch {}",
+            format_unix_timestamp(lockup.unix_timestamp, DATE_TIME_FORMAT),
+            lockup.epoch
+        );
+        let too_short = min_unix_timestamp.map_or(false, |min| lockup.unix_timestamp < min)
+            || min_epoch.map_or(false, |min| lockup.epoch < min);
+        let severity = if too_short {
+            Severity::Fail
+        } else {
+            Severity::Pass
+        };
+        findings.push(finding("lockup", severity, detail));
+    }
+
+    if let Some(allowed_vote_accounts) = &policy.allowed_vote_accounts {
+        let delegation = stake
+            .map(|stake| stake.delegation)
+            .filter(|delegation| delegation.deactivation_epoch == u64::MAX);
+        findings.push(match delegation {
+            None => finding("vote account", Severity::Pass, "not delegated".to_string()),
+            Some(delegation) if allowed_vote_accounts.contains(&delegation.voter_pubkey) => {
+                finding(
+                    "vote account",
+                    Severity::Pass,
+                    delegation.voter_pubkey.to_string(),
+                )
+            }
+            Some(delegation) => finding(
+                "vote account",
+                Severity::Fail,
+                format!("{} is not allowed", delegation.voter_pubkey),
+            ),
+        });
+    }
+
+    if let Some(min_lamports) = policy.min_lamports {
+        let detail = format!("{} SOL", lamports_to_sol(account.lamports));
+        let severity = if account.lamports < min_lamports {
+            Severity::Warn
+        } else {
+            Severity::Pass
+        };
+        findings.push(finding("balance", severity, detail));
+    }
+    findings
+}
+
+/// Audit each account, and list which indexes each distinct authority holds a
+/// role in.
+pub(crate) fn audit(
+    accounts: &[(Pubkey, usize, Pubkey, Option<Account>)],
+    policy: &Policy,
+) -> AuditReport {
+    let mut authorities: Vec<AuthorityEntry> = vec![];
+    let mut add_authority = |authority: &Pubkey, role, base_pubkey: &Pubkey, index| {
+        let authority = authority.to_string();
+        let base_pubkey = base_pubkey.to_string();
+        match authorities.iter_mut().find(|entry| {
+            entry.authority == authority && entry.role == role && entry.base_pubkey == base_pubkey
+        }) {
+            Some(entry) => entry.indexes.push(index),
+            None => authorities.push(AuthorityEntry {
+                authority,
+                role,
+                base_pubkey,
+                indexes: vec![index],
+            }),
+        }
+    };
+
+    let mut audits = vec![];
+    for (base_pubkey, index, address, account) in accounts {
+        let account = account.as_ref();
+        if let Some(authorized) = account.and_then(StakeState::authorized_from) {
+            add_authority(&authorized.staker, "staker", base_pubkey, *index);
+            add_authority(&authorized.withdrawer, "withdrawer", base_pubkey, *index);
+        }
+        if let Some(lockup) = account.and_then(get_lockup) {
+            if lockup.custodian != Pubkey::default() {
+                add_authority(&lockup.custodian, "custodian", base_pubkey, *index);
+            }
+        }
+        audits.push(AccountAudit {
+            base_pubkey: base_pubkey.to_string(),
+            index: *index,
+            address: address.to_string(),
+            findings: audit_account(account, policy),
+        });
+    }
+    let severity = audits
+        .iter()
+        .map(|audit| audit.severity())
+        .max()
+        .unwrap_or(Severity::Pass);
+    AuditReport {
+        severity,
+        accounts: audits,
+        authorities,
+    }
+}
+
+fn write_table<W: Write>(writer: &mut W, report: &AuditReport) -> std::io::Result<()> {
+    for audit in &report.accounts {
+        for finding in &audit.findings {
+            writeln!(
+                writer,
+                "{:<8} {:>5} {:<44} {:<12} {}",
+                finding.severity, audit.index, audit.address, finding.check, finding.detail
+            )?;
+        }
+    }
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:<44} {:<10} {:<44} INDEXES",
+        "AUTHORITY", "ROLE", "BASE PUBKEY"
+    )?;
+    for entry in &report.authorities {
+        let indexes: Vec<_> = entry
+            .indexes
+            .iter()
+            .map(|index| index.to_string())
+            .collect();
+        writeln!(
+            writer,
+            "{:<44} {:<10} {:<44} {}",
+            entry.authority,
+            entry.role,
+            entry.base_pubkey,
+            indexes.join(",")
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct FindingRow<'a> {
+    base_pubkey: &'a str,
+    index: usize,
+    address: &'a str,
+    check: &'static str,
+    severity: Severity,
+    detail: &'a str,
+}
+
+fn write_error<E: fmt::Display>(err: E) -> Error {
+    Error::Config(format!("unable to write audit report: {}", err))
+}
+
+/// Write the report in the given format. CSV has one row per finding, and
+/// omits the authority inventory.
+pub(crate) fn write_audit_report<W: Write>(
+    mut writer: W,
+    report: &AuditReport,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Table => write_table(&mut writer, report).map_err(write_error),
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, report).map_err(write_error),
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for audit in &report.accounts {
+                for finding in &audit.findings {
+                    writer
+                        .serialize(FindingRow {
+                            base_pubkey: &audit.base_pubkey,
+                            index: audit.index,
+                            address: &audit.address,
+                            check: finding.check,
+                            severity: finding.severity,
+                            detail: &finding.detail,
+                        })
+                        .map_err(write_error)?;
+                }
+            }
+            writer.flush().map_err(write_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_stake_program::stake_state::{Authorized, Delegation, Lockup, Meta, Stake};
+
+    #[test]
+    fn test_audit() {
+        let staker = Pubkey::new_rand();
+        let withdrawer = Pubkey::new_rand();
+        let vote_pubkey = Pubkey::new_rand();
+        let yaml = format!(
+            "
+staker: {}
+withdrawer: {}
+min_lockup_date: 2021-01-01
+allowed_vote_accounts: [{}]
+min_balance: 1.0
+",
+            staker, withdrawer, vote_pubkey
+        );
+        let policy = read_policy(yaml.as_bytes()).unwrap();
+        assert_eq!(policy.min_lamports, Some(sol_to_lamports(1.0)));
+
+        let meta = Meta {
+            authorized: Authorized { staker, withdrawer },
+            lockup: Lockup {
+                unix_timestamp: parse_unix_timestamp("2021-06-01").unwrap(),
+                ..Lockup::default()
+            },
+            ..Meta::default()
+        };
+        let state = StakeState::Initialized(meta);
+        let passing =
+            Account::new_data(sol_to_lamports(2.0), &state, &solana_stake_program::id()).unwrap();
+
+        // Delegated to a vote account the policy doesn't allow, with too
+        // little in it, and a stranger as staker.
+        let stake = Stake {
+            delegation: Delegation {
+                voter_pubkey: Pubkey::new_rand(),
+                deactivation_epoch: u64::MAX,
+                ..Delegation::default()
+            },
+            ..Stake::default()
+        };
+        let stranger = Pubkey::new_rand();
+        let meta = Meta {
+            authorized: Authorized {
+                staker: stranger,
+                withdrawer,
+            },
+            ..meta
+        };
+        let state = StakeState::Stake(meta, stake);
+        let failing = Account::new_data(1, &state, &solana_stake_program::id()).unwrap();
+
+        let base_pubkey = Pubkey::new_rand();
+        let accounts: Vec<_> = vec![Some(passing), Some(failing), None]
+            .into_iter()
+            .enumerate()
+            .map(|(i, account)| (base_pubkey, i, Pubkey::new_rand(), account))
+            .collect();
+        let report = audit(&accounts, &policy);
+        assert_eq!(report.severity, Severity::Critical);
+
+        let severities: Vec<_> = report
+            .accounts
+            .iter()
+            .map(|audit| {
+                audit
+                    .findings
+                    .iter()
+                    .map(|finding| (finding.check, finding.severity))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            severities[0],
+            vec![
+                ("initialized", Severity::Pass),
+                ("staker", Severity::Pass),
+                ("withdrawer", Severity::Pass),
+                ("lockup", Severity::Pass),
+                ("vote account", Severity::Pass),
+                ("balance", Severity::Pass),
+            ]
+        );
+        assert_eq!(
+            severities[1],
+            vec![
+                ("initialized", Severity::Pass),
+                ("staker", Severity::Critical),
+                ("withdrawer", Severity::Pass),
+                ("lockup", Severity::Pass),
+                ("vote account", Severity::Fail),
+                ("balance", Severity::Warn),
+            ]
+        );
+        assert_eq!(severities[2], vec![("initialized", Severity::Fail)]);
+        assert_eq!(
+            report.accounts[0].findings[3].detail,
+            "until 2021-06-01 00:00:00 or epoch 0"
+        );
+
+        let inventory: Vec<_> = report
+            .authorities
+            .iter()
+            .map(|entry| (entry.authority.clone(), entry.role, entry.indexes.clone()))
+            .collect();
+        assert_eq!(
+            inventory,
+            vec![
+                (staker.to_string(), "staker", vec![0]),
+                (withdrawer.to_string(), "withdrawer", vec![0, 1]),
+                (stranger.to_string(), "staker", vec![1]),
+            ]
+        );
+    }
+}
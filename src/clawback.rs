@@ -0,0 +1,133 @@
+use crate::activation::get_stake_balance;
+use crate::lockup::{get_lockup, is_locked};
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, UnixTimestamp},
+    sysvar::{clock::Clock, stake_history::StakeHistory},
+};
+use std::fmt;
+
+/// What clawback does with a derived stake account.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Disposition {
+    /// There is no account at the derived address.
+    Missing,
+    /// The account is not an initialized stake account.
+    NotStake,
+    /// The lockup has expired, so the account belongs to the grantee.
+    Vested,
+    /// The account is locked but its stake is delegated, and lamports can't be
+    /// withdrawn until it is deactivated.
+    Delegated,
+    /// The account is locked and all its lamports can be withdrawn.
+    Reclaim { lamports: u64 },
+}
+
+impl fmt::Display for Disposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Disposition::Missing => write!(f, "missing"),
+            Disposition::NotStake => write!(f, "not a stake account"),
+            Disposition::Vested => write!(f, "vested, untouched"),
+            Disposition::Delegated => write!(f, "locked but delegated, deactivate first"),
+            Disposition::Reclaim { .. } => write!(f, "reclaim"),
+        }
+    }
+}
+
+/// Decide whether an account is clawed back, and return its lockup's unix
+/// timestamp, if any, for the report.
+pub(crate) fn plan_clawback(
+    account: Option<&Account>,
+    clock: &Clock,
+    epoch: Epoch,
+    stake_history: &StakeHistory,
+) -> (Disposition, Option<UnixTimestamp>) {
+    let account = match account {
+        Some(account) => account,
+        None => return (Disposition::Missing, None),
+    };
+    let lockup = match get_lockup(account) {
+        Some(lockup) => lockup,
+        None => return (Disposition::NotStake, None),
+    };
+    let disposition = if !is_locked(&lockup, clock) {
+        Disposition::Vested
+    } else {
+        let balance = get_stake_balance(account, epoch, stake_history);
+        if balance.effective + balance.activating > 0 {
+            Disposition::Delegated
+        } else {
+            Disposition::Reclaim {
+                lamports: account.lamports,
+            }
+        }
+    };
+    (disposition, Some(lockup.unix_timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_stake_program::stake_state::{Delegation, Lockup, Meta, Stake, StakeState};
+
+    #[test]
+    fn test_plan_clawback() {
+        let clock = Clock {
+            epoch: 10,
+            unix_timestamp: 1_000,
+            ..Clock::default()
+        };
+        let stake_history = StakeHistory::default();
+        let meta = |unix_timestamp| Meta {
+            lockup: Lockup {
+                unix_timestamp,
+                ..Lockup::default()
+            },
+            ..Meta::default()
+        };
+        let program_id = solana_stake_program::id();
+
+        assert_eq!(
+            plan_clawback(None, &clock, 10, &stake_history),
+            (Disposition::Missing, None)
+        );
+
+        let account = Account::new(42, 0, &Pubkey::new_rand());
+        assert_eq!(
+            plan_clawback(Some(&account), &clock, 10, &stake_history).0,
+            Disposition::NotStake
+        );
+
+        let state = StakeState::Initialized(meta(999));
+        let account = Account::new_data(42, &state, &program_id).unwrap();
+        assert_eq!(
+            plan_clawback(Some(&account), &clock, 10, &stake_history),
+            (Disposition::Vested, Some(999))
+        );
+
+        let state = StakeState::Initialized(meta(1_001));
+        let account = Account::new_data(42, &state, &program_id).unwrap();
+        assert_eq!(
+            plan_clawback(Some(&account), &clock, 10, &stake_history),
+            (Disposition::Reclaim { lamports: 42 }, Some(1_001))
+        );
+
+        let stake = Stake {
+            delegation: Delegation {
+                voter_pubkey: Pubkey::new_rand(),
+                stake: 40,
+                activation_epoch: 0,
+                ..Delegation::default()
+            },
+            ..Stake::default()
+        };
+        let state = StakeState::Stake(meta(1_001), stake);
+        let account = Account::new_data(42, &state, &program_id).unwrap();
+        assert_eq!(
+            plan_clawback(Some(&account), &clock, 10, &stake_history).0,
+            Disposition::Delegated
+        );
+    }
+}
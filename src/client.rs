@@ -0,0 +1,502 @@
+use solana_client::{
+    client_error::ClientError,
+    rpc_client::RpcClient,
+    rpc_response::{ConfirmedTransaction, TransactionEncoding},
+};
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, Slot},
+    commitment_config::CommitmentConfig,
+    fee_calculator::FeeCalculator,
+    hash::Hash,
+    message::Message,
+    pubkey::Pubkey,
+    signature::Signature,
+    signers::Signers,
+    transaction::{self, Transaction},
+};
+use std::io;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a sent transaction to reach the requested commitment.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// The cluster queries and transactions that commands depend on, so that
+/// commands can run against a bank in tests.
+pub(crate) trait Client {
+    /// Return the account at the given address, or None if there is none.
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError>;
+
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError>;
+
+    fn get_slot(&self) -> Result<Slot, ClientError>;
+
+    fn get_epoch(&self) -> Result<Epoch, ClientError>;
+
+    fn get_recent_blockhash(&self) -> Result<(Hash, FeeCalculator), ClientError>;
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, ClientError>;
+
+    /// Return the transaction's status once it reaches the given commitment,
+    /// or None if the cluster has no record of it at that commitment.
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<transaction::Result<()>>, ClientError>;
+
+    /// Return the signatures of the confirmed transactions that touched the
+    /// address in the slot range.
+    fn get_confirmed_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        start_slot: Slot,
+        end_slot: Slot,
+    ) -> Result<Vec<Signature>, ClientError>;
+
+    fn get_confirmed_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<ConfirmedTransaction, ClientError>;
+
+    /// The commitment a sent transaction must reach before
+    /// `send_and_confirm_message` and `send_and_confirm_transaction` return.
+    fn confirm_commitment(&self) -> CommitmentConfig;
+
+    /// Sign the message with a recent blockhash, send it, and wait for it to
+    /// be confirmed. Returns the transaction's signature.
+    fn send_and_confirm_message<S: Signers>(
+        &self,
+        message: Message,
+        signers: &S,
+    ) -> Result<String, ClientError>;
+
+    /// Send a transaction that is already signed, and wait for it to be
+    /// confirmed. Returns the transaction's signature.
+    fn send_and_confirm_transaction(&self, transaction: Transaction)
+        -> Result<String, ClientError>;
+}
+
+/// An RPC client that reads at the given commitment, and once a transaction
+/// is sent, waits for it to reach that commitment, or to be finalized if
+/// `finalized` is set.
+pub(crate) struct CommitmentRpcClient {
+    rpc_client: RpcClient,
+    commitment: CommitmentConfig,
+    confirm_commitment: CommitmentConfig,
+}
+
+impl CommitmentRpcClient {
+    pub fn new(rpc_client: RpcClient, commitment: CommitmentConfig, finalized: bool) -> Self {
+        let confirm_commitment = if finalized {
+            CommitmentConfig::max()
+        } else {
+            commitment
+        };
+        Self {
+            rpc_client,
+            commitment,
+            confirm_commitment,
+        }
+    }
+
+    /// Send the transaction once, without waiting for it to be confirmed.
+    pub fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, ClientError> {
+        let signature = self.rpc_client.send_transaction(transaction)?;
+        Signature::from_str(&signature).map_err(|err| {
+            ClientError::from(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid signature {}: {:?}", signature, err),
+            ))
+        })
+    }
+}
+
+/// Sign the message with a recent blockhash from the client.
+pub(crate) fn sign_message<C: Client, S: Signers>(
+    client: &C,
+    message: Message,
+    signers: &S,
+) -> Result<Transaction, ClientError> {
+    let (blockhash, _fee_calculator) = client.get_recent_blockhash()?;
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction
+        .try_sign(signers, blockhash)
+        .map_err(ClientError::SigningError)?;
+    Ok(transaction)
+}
+
+/// Poll the client until the transaction reaches the given commitment.
+/// Returns the transaction's signature.
+pub(crate) fn wait_for_commitment<C: Client>(
+    client: &C,
+    signature: &Signature,
+    commitment: CommitmentConfig,
+) -> Result<String, ClientError> {
+    let start = Instant::now();
+    while start.elapsed() < CONFIRMATION_TIMEOUT {
+        match client.get_signature_status(signature, commitment)? {
+            Some(Ok(())) => return Ok(signature.to_string()),
+            Some(Err(err)) => return Err(err.into()),
+            None => sleep(Duration::from_millis(500)),
+        }
+    }
+    Err(ClientError::from(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!(
+            "transaction {} did not reach commitment {:?}",
+            signature, commitment.commitment
+        ),
+    )))
+}
+
+impl Client for CommitmentRpcClient {
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError> {
+        self.rpc_client
+            .get_account_with_commitment(pubkey, self.commitment)
+            .map(|response| response.value)
+    }
+
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+        self.rpc_client
+            .get_balance_with_commitment(pubkey, self.commitment)
+            .map(|response| response.value)
+    }
+
+    fn get_slot(&self) -> Result<Slot, ClientError> {
+        self.rpc_client.get_slot_with_commitment(self.commitment)
+    }
+
+    fn get_epoch(&self) -> Result<Epoch, ClientError> {
+        self.rpc_client
+            .get_epoch_info_with_commitment(self.commitment)
+            .map(|epoch_info| epoch_info.epoch)
+    }
+
+    fn get_recent_blockhash(&self) -> Result<(Hash, FeeCalculator), ClientError> {
+        self.rpc_client
+            .get_recent_blockhash_with_commitment(self.commitment)
+            .map(|response| response.value)
+    }
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, ClientError> {
+        self.rpc_client
+            .get_minimum_balance_for_rent_exemption(data_len)
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<transaction::Result<()>>, ClientError> {
+        self.rpc_client
+            .get_signature_status_with_commitment(signature, commitment)
+    }
+
+    fn get_confirmed_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        start_slot: Slot,
+        end_slot: Slot,
+    ) -> Result<Vec<Signature>, ClientError> {
+        self.rpc_client
+            .get_confirmed_signatures_for_address(address, start_slot, end_slot)
+    }
+
+    fn get_confirmed_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<ConfirmedTransaction, ClientError> {
+        self.rpc_client
+            .get_confirmed_transaction(signature, TransactionEncoding::Binary)
+    }
+
+    fn confirm_commitment(&self) -> CommitmentConfig {
+        self.confirm_commitment
+    }
+
+    fn send_and_confirm_message<S: Signers>(
+        &self,
+        message: Message,
+        signers: &S,
+    ) -> Result<String, ClientError> {
+        let transaction = sign_message(self, message, signers)?;
+        self.send_and_confirm_transaction(transaction)
+    }
+
+    // A transaction whose blockhash expires times out rather than being
+    // signed again; the send retries sign it anew.
+    fn send_and_confirm_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<String, ClientError> {
+        let signature = self.send_transaction(&transaction)?;
+        wait_for_commitment(self, &signature, self.confirm_commitment)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod bank_client {
+    use super::*;
+    use solana_client::rpc_response::{EncodedTransaction, TransactionWithStatusMeta};
+    use solana_runtime::bank_client::BankClient;
+    use solana_sdk::{
+        client::{AsyncClient, SyncClient},
+        sysvar::{self, clock::Clock, rent::Rent, Sysvar},
+        transport::TransportError,
+    };
+    use std::cell::{Cell, RefCell};
+
+    fn client_error(err: TransportError) -> ClientError {
+        match err {
+            TransportError::IoError(err) => ClientError::from(err),
+            TransportError::TransactionError(err) => ClientError::from(err),
+        }
+    }
+
+    fn invalid_sysvar(name: &str) -> ClientError {
+        ClientError::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid {} sysvar", name),
+        ))
+    }
+
+    fn no_history() -> ClientError {
+        ClientError::from(io::Error::new(
+            io::ErrorKind::Other,
+            "a bank keeps no transaction history",
+        ))
+    }
+
+    impl Client for BankClient {
+        fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError> {
+            SyncClient::get_account(self, pubkey).map_err(client_error)
+        }
+
+        fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+            SyncClient::get_balance(self, pubkey).map_err(client_error)
+        }
+
+        fn get_slot(&self) -> Result<Slot, ClientError> {
+            SyncClient::get_slot(self).map_err(client_error)
+        }
+
+        fn get_epoch(&self) -> Result<Epoch, ClientError> {
+            Client::get_account(self, &sysvar::clock::id())?
+                .and_then(|account| Clock::from_account(&account))
+                .map(|clock| clock.epoch)
+                .ok_or_else(|| invalid_sysvar("clock"))
+        }
+
+        fn get_recent_blockhash(&self) -> Result<(Hash, FeeCalculator), ClientError> {
+            SyncClient::get_recent_blockhash(self).map_err(client_error)
+        }
+
+        fn get_minimum_balance_for_rent_exemption(
+            &self,
+            data_len: usize,
+        ) -> Result<u64, ClientError> {
+            Client::get_account(self, &sysvar::rent::id())?
+                .and_then(|account| Rent::from_account(&account))
+                .map(|rent| rent.minimum_balance(data_len))
+                .ok_or_else(|| invalid_sysvar("rent"))
+        }
+
+        // Every transaction a bank processes is final.
+        fn get_signature_status(
+            &self,
+            signature: &Signature,
+            _commitment: CommitmentConfig,
+        ) -> Result<Option<transaction::Result<()>>, ClientError> {
+            SyncClient::get_signature_status(self, signature).map_err(client_error)
+        }
+
+        fn get_confirmed_signatures_for_address(
+            &self,
+            _address: &Pubkey,
+            _start_slot: Slot,
+            _end_slot: Slot,
+        ) -> Result<Vec<Signature>, ClientError> {
+            Err(no_history())
+        }
+
+        fn get_confirmed_transaction(
+            &self,
+            _signature: &Signature,
+        ) -> Result<ConfirmedTransaction, ClientError> {
+            Err(no_history())
+        }
+
+        // Nothing waits for a bank's transactions to be finalized.
+        fn confirm_commitment(&self) -> CommitmentConfig {
+            CommitmentConfig::recent()
+        }
+
+        fn send_and_confirm_message<S: Signers>(
+            &self,
+            message: Message,
+            signers: &S,
+        ) -> Result<String, ClientError> {
+            SyncClient::send_message(self, signers, message)
+                .map(|signature| signature.to_string())
+                .map_err(client_error)
+        }
+
+        fn send_and_confirm_transaction(
+            &self,
+            transaction: Transaction,
+        ) -> Result<String, ClientError> {
+            let signature = AsyncClient::async_send_transaction(self, transaction)?;
+            // Transactions the bank rejects before executing never get a status.
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(5) {
+                if let Some(status) =
+                    SyncClient::get_signature_status(self, &signature).map_err(client_error)?
+                {
+                    return status
+                        .map(|()| signature.to_string())
+                        .map_err(ClientError::from);
+                }
+                sleep(Duration::from_millis(10));
+            }
+            Err(ClientError::from(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("transaction {} was not confirmed", signature),
+            )))
+        }
+    }
+
+    /// Wraps a client, remembering the transactions it confirms, so that
+    /// transaction history can be queried from a bank. The commitment it
+    /// reports confirming at can be set, as `--finalized` sets an RPC
+    /// client's.
+    pub(crate) struct HistoryClient<C> {
+        client: C,
+        transactions: RefCell<Vec<(Slot, Signature, Transaction)>>,
+        confirm_commitment: Cell<CommitmentConfig>,
+    }
+
+    impl<C: Client> HistoryClient<C> {
+        pub fn new(client: C) -> Self {
+            let confirm_commitment = Cell::new(client.confirm_commitment());
+            Self {
+                client,
+                transactions: RefCell::new(vec![]),
+                confirm_commitment,
+            }
+        }
+
+        pub fn set_confirm_commitment(&self, confirm_commitment: CommitmentConfig) {
+            self.confirm_commitment.set(confirm_commitment);
+        }
+    }
+
+    impl<C: Client> Client for HistoryClient<C> {
+        fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError> {
+            self.client.get_account(pubkey)
+        }
+
+        fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+            self.client.get_balance(pubkey)
+        }
+
+        fn get_slot(&self) -> Result<Slot, ClientError> {
+            self.client.get_slot()
+        }
+
+        fn get_epoch(&self) -> Result<Epoch, ClientError> {
+            self.client.get_epoch()
+        }
+
+        fn get_recent_blockhash(&self) -> Result<(Hash, FeeCalculator), ClientError> {
+            self.client.get_recent_blockhash()
+        }
+
+        fn get_minimum_balance_for_rent_exemption(
+            &self,
+            data_len: usize,
+        ) -> Result<u64, ClientError> {
+            self.client.get_minimum_balance_for_rent_exemption(data_len)
+        }
+
+        fn get_signature_status(
+            &self,
+            signature: &Signature,
+            commitment: CommitmentConfig,
+        ) -> Result<Option<transaction::Result<()>>, ClientError> {
+            self.client.get_signature_status(signature, commitment)
+        }
+
+        fn get_confirmed_signatures_for_address(
+            &self,
+            address: &Pubkey,
+            start_slot: Slot,
+            end_slot: Slot,
+        ) -> Result<Vec<Signature>, ClientError> {
+            Ok(self
+                .transactions
+                .borrow()
+                .iter()
+                .filter(|(slot, _, transaction)| {
+                    *slot >= start_slot
+                        && *slot <= end_slot
+                        && transaction.message.account_keys.contains(address)
+                })
+                .map(|(_, signature, _)| *signature)
+                .collect())
+        }
+
+        fn get_confirmed_transaction(
+            &self,
+            signature: &Signature,
+        ) -> Result<ConfirmedTransaction, ClientError> {
+            let transactions = self.transactions.borrow();
+            let (slot, _, transaction) = transactions
+                .iter()
+                .find(|(_, recorded, _)| recorded == signature)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("transaction {} not found", signature),
+                    )
+                })?;
+            let encoded = bs58::encode(bincode::serialize(transaction).unwrap()).into_string();
+            Ok(ConfirmedTransaction {
+                slot: *slot,
+                transaction: TransactionWithStatusMeta {
+                    transaction: EncodedTransaction::Binary(encoded),
+                    meta: None,
+                },
+            })
+        }
+
+        fn confirm_commitment(&self) -> CommitmentConfig {
+            self.confirm_commitment.get()
+        }
+
+        fn send_and_confirm_message<S: Signers>(
+            &self,
+            message: Message,
+            signers: &S,
+        ) -> Result<String, ClientError> {
+            let transaction = sign_message(&self.client, message, signers)?;
+            self.send_and_confirm_transaction(transaction)
+        }
+
+        fn send_and_confirm_transaction(
+            &self,
+            transaction: Transaction,
+        ) -> Result<String, ClientError> {
+            let signature = self
+                .client
+                .send_and_confirm_transaction(transaction.clone())?;
+            let slot = self.client.get_slot()?;
+            self.transactions
+                .borrow_mut()
+                .push((slot, transaction.signatures[0], transaction));
+            Ok(signature)
+        }
+    }
+}
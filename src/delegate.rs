@@ -0,0 +1,287 @@
+use crate::error::Error;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+/// A row of the weights CSV.
+#[derive(Deserialize, Debug)]
+struct Record {
+    vote_account: String,
+    weight: f64,
+}
+
+/// A vote account and its share of the stake, relative to the other targets.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Target {
+    pub vote_pubkey: Pubkey,
+    pub weight: f64,
+}
+
+/// A derived stake account that may be delegated. `voter_pubkey` is None if
+/// the account is not yet delegated.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StakeAccountInfo {
+    pub index: usize,
+    pub lamports: u64,
+    pub voter_pubkey: Option<Pubkey>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum DelegateAction {
+    /// Delegate all of the account at `index`.
+    Delegate { index: usize, vote_pubkey: Pubkey },
+    /// Split `lamports` from the account at `index` into a new account at
+    /// `new_index`, and delegate the new account.
+    SplitAndDelegate {
+        index: usize,
+        new_index: usize,
+        lamports: u64,
+        vote_pubkey: Pubkey,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct DelegationPlan {
+    pub actions: Vec<DelegateAction>,
+    /// The lamports each target should have, in the order of the targets.
+    pub goals: Vec<u64>,
+    /// The lamports each target would have once the actions are sent.
+    pub achieved: Vec<u64>,
+    pub total_lamports: u64,
+}
+
+/// Read targets from CSV with the columns vote_account and weight.
+pub(crate) fn read_targets<R: Read>(reader: R) -> Result<Vec<Target>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let targets = reader
+        .deserialize()
+        .enumerate()
+        .map(|(row, record)| {
+            let record: Record = record.map_err(|err| err.to_string())?;
+            let vote_pubkey = Pubkey::from_str(&record.vote_account)
+                .map_err(|err| format!("row {}: invalid vote account: {:?}", row, err))?;
+            if record.weight.is_nan() || record.weight <= 0.0 {
+                return Err(format!("row {}: weight must be positive", row));
+            }
+            Ok(Target {
+                vote_pubkey,
+                weight: record.weight,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if targets.is_empty() {
+        return Err("no vote accounts".to_string());
+    }
+    Ok(targets)
+}
+
+pub(crate) fn read_targets_file(path: &str) -> Result<Vec<Target>, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    read_targets(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+/// Assign undelegated accounts to targets, largest first, each to the target
+/// furthest below its goal. Where an account is larger than that target's
+/// shortfall, the shortfall is split into a new account at the next free index,
+/// provided both parts are nonempty and keep at least `min_lamports`. Accounts already delegated
+/// to a target count toward it and are left alone; accounts delegated elsewhere
+/// are ignored.
+pub(crate) fn plan_delegation(
+    accounts: &[StakeAccountInfo],
+    targets: &[Target],
+    mut next_index: usize,
+    min_lamports: u64,
+) -> DelegationPlan {
+    let target_position = |vote_pubkey: &Pubkey| {
+        targets
+            .iter()
+            .position(|target| target.vote_pubkey == *vote_pubkey)
+    };
+    let mut achieved = vec![0; targets.len()];
+    let mut undelegated = vec![];
+    for account in accounts {
+        match &account.voter_pubkey {
+            Some(voter_pubkey) => {
+                if let Some(position) = target_position(voter_pubkey) {
+                    achieved[position] += account.lamports;
+                }
+            }
+            None => undelegated.push(account),
+        }
+    }
+    let total_lamports = achieved.iter().sum::<u64>()
+        + undelegated
+            .iter()
+            .map(|account| account.lamports)
+            .sum::<u64>();
+    let total_weight: f64 = targets.iter().map(|target| target.weight).sum();
+    let goals: Vec<u64> = targets
+        .iter()
+        .map(|target| (total_lamports as f64 * target.weight / total_weight) as u64)
+        .collect();
+
+    undelegated.sort_by(|a, b| b.lamports.cmp(&a.lamports).then(a.index.cmp(&b.index)));
+    let mut actions = vec![];
+    for account in undelegated {
+        let mut remaining = account.lamports;
+        loop {
+            let (position, shortfall) = goals
+                .iter()
+                .zip(&achieved)
+                .map(|(goal, achieved)| goal.saturating_sub(*achieved))
+                .enumerate()
+                .fold((0, 0), |best, (position, shortfall)| {
+                    if shortfall > best.1 {
+                        (position, shortfall)
+                    } else {
+                        best
+                    }
+                });
+            let vote_pubkey = targets[position].vote_pubkey;
+            // With every goal met, a zero shortfall would split nothing off
+            // forever.
+            let splittable = shortfall > 0
+                && shortfall >= min_lamports
+                && remaining > shortfall
+                && remaining - shortfall >= min_lamports;
+            if splittable {
+                actions.push(DelegateAction::SplitAndDelegate {
+                    index: account.index,
+                    new_index: next_index,
+                    lamports: shortfall,
+                    vote_pubkey,
+                });
+                next_index += 1;
+                achieved[position] += shortfall;
+                remaining -= shortfall;
+            } else {
+                actions.push(DelegateAction::Delegate {
+                    index: account.index,
+                    vote_pubkey,
+                });
+                achieved[position] += remaining;
+                break;
+            }
+        }
+    }
+    DelegationPlan {
+        actions,
+        goals,
+        achieved,
+        total_lamports,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_delegation() {
+        let vote0 = Pubkey::new_rand();
+        let vote1 = Pubkey::new_rand();
+        let csv = format!("vote_account,weight\n{},3\n{},1\n", vote0, vote1);
+        let targets = read_targets(csv.as_bytes()).unwrap();
+        assert_eq!(targets[0].weight, 3.0);
+
+        let accounts = vec![
+            StakeAccountInfo {
+                index: 0,
+                lamports: 100,
+                voter_pubkey: None,
+            },
+            StakeAccountInfo {
+                index: 1,
+                lamports: 60,
+                voter_pubkey: None,
+            },
+            StakeAccountInfo {
+                index: 2,
+                lamports: 40,
+                voter_pubkey: Some(vote1),
+            },
+            StakeAccountInfo {
+                index: 3,
+                lamports: 1000,
+                voter_pubkey: Some(Pubkey::new_rand()),
+            },
+        ];
+
+        // Stake delegated elsewhere doesn't count. Of the 200 lamports, vote0
+        // should have 150 and vote1, which already has 40, should have 50. The
+        // first account and 50 split from the second go to vote0, and the
+        // remaining 10 to vote1.
+        let plan = plan_delegation(&accounts, &targets, 4, 10);
+        assert_eq!(plan.total_lamports, 200);
+        assert_eq!(plan.goals, vec![150, 50]);
+        assert_eq!(plan.achieved, vec![150, 50]);
+        assert_eq!(
+            plan.actions,
+            vec![
+                DelegateAction::Delegate {
+                    index: 0,
+                    vote_pubkey: vote0
+                },
+                DelegateAction::SplitAndDelegate {
+                    index: 1,
+                    new_index: 4,
+                    lamports: 50,
+                    vote_pubkey: vote0
+                },
+                DelegateAction::Delegate {
+                    index: 1,
+                    vote_pubkey: vote1
+                },
+            ]
+        );
+
+        // Accounts that can't be split and keep the minimum are delegated whole.
+        let plan = plan_delegation(&accounts, &targets, 4, 20);
+        assert_eq!(plan.achieved, vec![160, 40]);
+        assert_eq!(
+            plan.actions[1],
+            DelegateAction::Delegate {
+                index: 1,
+                vote_pubkey: vote0
+            }
+        );
+
+        // Once every goal is met, what rounding leaves over is delegated
+        // rather than split off in empty accounts.
+        let csv = format!("vote_account,weight\n{},1\n{},1\n", vote0, vote1);
+        let targets = read_targets(csv.as_bytes()).unwrap();
+        let accounts = vec![StakeAccountInfo {
+            index: 0,
+            lamports: 101,
+            voter_pubkey: None,
+        }];
+        let plan = plan_delegation(&accounts, &targets, 1, 0);
+        assert_eq!(plan.goals, vec![50, 50]);
+        assert_eq!(plan.achieved, vec![51, 50]);
+        assert_eq!(
+            plan.actions,
+            vec![
+                DelegateAction::SplitAndDelegate {
+                    index: 0,
+                    new_index: 1,
+                    lamports: 50,
+                    vote_pubkey: vote0
+                },
+                DelegateAction::SplitAndDelegate {
+                    index: 0,
+                    new_index: 2,
+                    lamports: 50,
+                    vote_pubkey: vote1
+                },
+                DelegateAction::Delegate {
+                    index: 0,
+                    vote_pubkey: vote0
+                },
+            ]
+        );
+    }
+}
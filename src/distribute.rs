@@ -0,0 +1,169 @@
+use crate::args::parse_unix_timestamp;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{native_token::sol_to_lamports, pubkey::Pubkey};
+use solana_stake_program::stake_state::Lockup;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+/// A row of the distribution CSV.
+#[derive(Deserialize, Debug)]
+struct Record {
+    recipient: String,
+    amount: f64,
+    #[serde(default)]
+    lockup_date: Option<String>,
+    #[serde(default)]
+    custodian: Option<String>,
+}
+
+/// A stake account to create for a recipient, who becomes its stake and
+/// withdraw authority.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Allocation {
+    pub recipient: Pubkey,
+    pub lamports: u64,
+    pub lockup: Lockup,
+}
+
+/// A stake account created by a previous run. The signature is None if the
+/// account was found on-chain without a record of the transaction that created it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct SentRecord {
+    pub index: usize,
+    pub recipient: String,
+    pub lamports: u64,
+    pub address: String,
+    pub signature: Option<String>,
+}
+
+/// What has been sent for a distribution, so that reruns only send what's left.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct DistributionDb {
+    pub base_pubkey: String,
+    pub sent: Vec<SentRecord>,
+}
+
+impl DistributionDb {
+    pub fn get(&self, index: usize) -> Option<&SentRecord> {
+        self.sent.iter().find(|record| record.index == index)
+    }
+}
+
+fn parse_record(row: usize, record: Record) -> Result<Allocation, String> {
+    let recipient = Pubkey::from_str(&record.recipient)
+        .map_err(|err| format!("row {}: invalid recipient: {:?}", row, err))?;
+    let custodian = match &record.custodian {
+        Some(custodian) => Pubkey::from_str(custodian)
+            .map_err(|err| format!("row {}: invalid custodian: {:?}", row, err))?,
+        None => Pubkey::default(),
+    };
+    let unix_timestamp = match &record.lockup_date {
+        Some(date) => parse_unix_timestamp(date).map_err(|err| format!("row {}: {}", row, err))?,
+        None => 0,
+    };
+    Ok(Allocation {
+        recipient,
+        lamports: sol_to_lamports(record.amount),
+        lockup: Lockup {
+            unix_timestamp,
+            epoch: 0,
+            custodian,
+        },
+    })
+}
+
+/// Read allocations from CSV with the columns recipient, amount (in SOL), and
+/// optionally lockup_date and custodian. Row N is given the stake account
+/// derived at index N.
+pub(crate) fn read_allocations<R: Read>(reader: R) -> Result<Vec<Allocation>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    reader
+        .deserialize()
+        .enumerate()
+        .map(|(row, record)| {
+            let record = record.map_err(|err| err.to_string())?;
+            parse_record(row, record)
+        })
+        .collect()
+}
+
+pub(crate) fn read_allocations_file(path: &str) -> Result<Vec<Allocation>, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    read_allocations(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+/// Return the index and allocation of each row not yet sent. A row that was
+/// sent with a different recipient or amount is an error, since the CSV must
+/// not change between runs.
+pub(crate) fn unsent_allocations<'a>(
+    allocations: &'a [Allocation],
+    db: &DistributionDb,
+) -> Result<Vec<(usize, &'a Allocation)>, Error> {
+    let mut unsent = vec![];
+    for (index, allocation) in allocations.iter().enumerate() {
+        match db.get(index) {
+            Some(record) => {
+                if record.recipient != allocation.recipient.to_string()
+                    || record.lamports != allocation.lamports
+                {
+                    return Err(Error::Config(format!(
+                        "row {} was sent to {} for {} lamports but is now {} for {} lamports",
+                        index,
+                        record.recipient,
+                        record.lamports,
+                        allocation.recipient,
+                        allocation.lamports
+                    )));
+                }
+            }
+            None => unsent.push((index, allocation)),
+        }
+    }
+    Ok(unsent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsent_allocations() {
+        let alice = Pubkey::new_rand();
+        let bob = Pubkey::new_rand();
+        let custodian = Pubkey::new_rand();
+        let csv = format!(
+            "recipient,amount,lockup_date,custodian\n{},1.5,,\n{},2,2021-01-01,{}\n",
+            alice, bob, custodian
+        );
+        let allocations = read_allocations(csv.as_bytes()).unwrap();
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[0].lamports, sol_to_lamports(1.5));
+        assert_eq!(allocations[0].lockup, Lockup::default());
+        assert_eq!(allocations[1].lockup.unix_timestamp, 1_609_459_200);
+        assert_eq!(allocations[1].lockup.custodian, custodian);
+
+        let mut db = DistributionDb::default();
+        assert_eq!(unsent_allocations(&allocations, &db).unwrap().len(), 2);
+
+        db.sent.push(SentRecord {
+            index: 0,
+            recipient: alice.to_string(),
+            lamports: sol_to_lamports(1.5),
+            address: Pubkey::new_rand().to_string(),
+            signature: None,
+        });
+        let unsent = unsent_allocations(&allocations, &db).unwrap();
+        assert_eq!(unsent, vec![(1, &allocations[1])]);
+
+        // A row that changed since it was sent is an error.
+        db.sent[0].lamports += 1;
+        assert!(unsent_allocations(&allocations, &db).is_err());
+
+        let csv = format!("recipient,amount\n{},x\n", alice);
+        assert!(read_allocations(csv.as_bytes()).is_err());
+    }
+}
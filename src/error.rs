@@ -0,0 +1,332 @@
+use crate::audit::Severity;
+use solana_client::client_error::ClientError;
+use solana_sdk::{
+    instruction::InstructionError, message::Message, native_token::lamports_to_sol, pubkey::Pubkey,
+    system_program, transaction::TransactionError,
+};
+use solana_stake_program::{stake_instruction::StakeInstruction, stake_state::StakeAuthorize};
+use std::fmt;
+use thiserror::Error;
+
+/// The step of a command in which an error occurred.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Phase {
+    Resolve,
+    Fetch,
+    Plan,
+    Sign,
+    Send,
+    Confirm,
+}
+
+impl Phase {
+    fn exit_code(self) -> i32 {
+        match self {
+            Phase::Resolve => 3,
+            Phase::Fetch => 4,
+            Phase::Plan => 5,
+            Phase::Sign => 6,
+            Phase::Send => 7,
+            Phase::Confirm => 8,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Resolve => "resolve",
+            Phase::Fetch => "fetch",
+            Phase::Plan => "plan",
+            Phase::Sign => "sign",
+            Phase::Send => "send",
+            Phase::Confirm => "confirm",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    #[error("unable to resolve {name}: {reason}")]
+    Resolve { name: String, reason: String },
+
+    #[error("{phase} failed: {source}")]
+    Client { phase: Phase, source: ClientError },
+
+    #[error("fetch failed: unable to decode {name} sysvar")]
+    InvalidSysvar { name: &'static str },
+
+    #[error(
+        "{name} {address} has {} SOL but needs {} SOL, short {} SOL",
+        lamports_to_sol(*.balance),
+        lamports_to_sol(*.required),
+        lamports_to_sol(.required - .balance)
+    )]
+    InsufficientFunds {
+        name: String,
+        address: Pubkey,
+        balance: u64,
+        required: u64,
+    },
+
+    #[error(
+        "{} SOL is below the rent-exempt minimum of {} SOL, short {} SOL",
+        lamports_to_sol(*.lamports),
+        lamports_to_sol(*.minimum),
+        lamports_to_sol(.minimum - .lamports)
+    )]
+    BelowRentExemption { lamports: u64, minimum: u64 },
+
+    #[error("stake account {index} of {base_pubkey}: unable to derive address: {reason}")]
+    Derive {
+        base_pubkey: Pubkey,
+        index: usize,
+        reason: String,
+    },
+
+    #[error("stake account {index} ({address}): {phase} failed: {reason}")]
+    Account {
+        index: usize,
+        address: Pubkey,
+        phase: Phase,
+        reason: String,
+    },
+
+    #[error(
+        "audit found {num_accounts} accounts in breach of the policy, worst finding {severity}"
+    )]
+    AuditFailed {
+        severity: Severity,
+        num_accounts: usize,
+    },
+}
+
+impl Error {
+    pub fn resolve<E: fmt::Display>(name: &str, err: E) -> Self {
+        Error::Resolve {
+            name: name.to_string(),
+            reason: err.to_string(),
+        }
+    }
+
+    pub fn fetch(err: ClientError) -> Self {
+        Error::Client {
+            phase: Phase::Fetch,
+            source: err,
+        }
+    }
+
+    pub fn account<E: fmt::Display>(index: usize, address: &Pubkey, phase: Phase, err: E) -> Self {
+        Error::Account {
+            index,
+            address: *address,
+            phase,
+            reason: err.to_string(),
+        }
+    }
+
+    /// Error from sending `message` on behalf of the stake account at `index`.
+    pub fn send(index: usize, address: &Pubkey, err: &ClientError, message: &Message) -> Self {
+        let phase = match err {
+            ClientError::SigningError(_) => Phase::Sign,
+            ClientError::TransactionError(_) => Phase::Confirm,
+            _ => Phase::Send,
+        };
+        Self::account(index, address, phase, client_error_reason(err, message))
+    }
+
+    /// Whether the error came from reading the cluster or reaching the node,
+    /// and so may clear up if the command tries again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Client {
+                phase: Phase::Fetch,
+                ..
+            } => true,
+            Error::Client { source, .. } => match source {
+                ClientError::Io(_) | ClientError::Reqwest(_) | ClientError::RpcError(_) => true,
+                _ => false,
+            },
+            // Error::send assigns the send phase to transport failures only.
+            Error::Account {
+                phase: Phase::Send, ..
+            } => true,
+            _ => false,
+        }
+    }
+
+    /// The process exit code, distinct for each category of error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Resolve { .. } => Phase::Resolve.exit_code(),
+            Error::InvalidSysvar { .. } => Phase::Fetch.exit_code(),
+            Error::Derive { .. } | Error::BelowRentExemption { .. } => Phase::Plan.exit_code(),
+            Error::InsufficientFunds { .. } => 9,
+            Error::Client { phase, .. } | Error::Account { phase, .. } => phase.exit_code(),
+            Error::AuditFailed { severity, .. } => match severity {
+                Severity::Pass => 0,
+                Severity::Warn => 10,
+                Severity::Fail => 11,
+                Severity::Critical => 12,
+            },
+        }
+    }
+}
+
+// Return the kind of the instruction at `index` and the name of the key expected to sign it.
+pub(crate) fn describe_instruction(
+    message: &Message,
+    index: usize,
+) -> Option<(&'static str, &'static str)> {
+    let instruction = message.instructions.get(index)?;
+    let program_id = instruction.program_id(&message.account_keys);
+    if system_program::check_id(program_id) {
+        return Some(("create", "sender or base"));
+    }
+    if !solana_stake_program::check_id(program_id) {
+        return None;
+    }
+    let description = match bincode::deserialize(&instruction.data).ok()? {
+        StakeInstruction::Initialize(..) => ("initialize", "sender"),
+        StakeInstruction::Authorize(_, StakeAuthorize::Staker) => ("authorize", "stake authority"),
+        StakeInstruction::Authorize(_, StakeAuthorize::Withdrawer) => {
+            ("authorize", "withdraw authority")
+        }
+        StakeInstruction::DelegateStake => ("delegate", "stake authority"),
+        StakeInstruction::Split(_) => ("split", "stake authority"),
+        StakeInstruction::Withdraw(_) => ("withdraw", "withdraw authority"),
+        StakeInstruction::Deactivate => ("deactivate", "stake authority"),
+        _ => ("stake", "stake authority"),
+    };
+    Some(description)
+}
+
+fn system_error_reason(code: u32) -> Option<&'static str> {
+    let reason = match code {
+        0 => "stake account already exists",
+        1 => "insufficient funds to fund the stake account",
+        4 => "seed exceeds maximum length",
+        5 => "address does not match the seed",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+fn stake_error_reason(code: u32) -> Option<&'static str> {
+    let reason = match code {
+        0 => "not enough credits to redeem",
+        1 => "lockup has not yet expired",
+        2 => "stake already deactivated",
+        3 => "one re-delegation permitted per epoch",
+        4 => "split amount is more than is staked",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+fn instruction_error_reason(err: &InstructionError, message: &Message, index: usize) -> String {
+    let description = describe_instruction(message, index);
+    let (kind, signer) = description.unwrap_or(("instruction", "required"));
+    match err {
+        InstructionError::InsufficientFunds => format!("insufficient funds for {}", kind),
+        InstructionError::MissingRequiredSignature => format!("missing {} signature", signer),
+        InstructionError::InvalidAccountData => "not a stake account".to_string(),
+        InstructionError::AccountAlreadyInitialized => "stake account already exists".to_string(),
+        InstructionError::CustomError(code) => {
+            let reason = if kind == "create" {
+                system_error_reason(*code)
+            } else {
+                stake_error_reason(*code)
+            };
+            reason
+                .map(|reason| reason.to_string())
+                .unwrap_or_else(|| format!("{} failed with error code {}", kind, code))
+        }
+        err => format!("{} failed: {:?}", kind, err),
+    }
+}
+
+/// Return a readable reason for a transaction error, decoding stake program errors.
+pub(crate) fn transaction_error_reason(err: &TransactionError, message: &Message) -> String {
+    match err {
+        TransactionError::InstructionError(index, err) => {
+            instruction_error_reason(err, message, *index as usize)
+        }
+        TransactionError::InsufficientFundsForFee => "insufficient funds for fee".to_string(),
+        TransactionError::AccountNotFound => "fee payer account not found".to_string(),
+        TransactionError::BlockhashNotFound => "blockhash expired".to_string(),
+        err => format!("{:?}", err),
+    }
+}
+
+pub(crate) fn client_error_reason(err: &ClientError, message: &Message) -> String {
+    match err {
+        ClientError::TransactionError(err) => transaction_error_reason(err, message),
+        err => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_stake_program::stake_instruction;
+    use std::io;
+
+    #[test]
+    fn test_transaction_error_reason() {
+        let stake_account_address = Pubkey::new_rand();
+        let stake_authority_pubkey = Pubkey::new_rand();
+        let instructions = stake_instruction::split_with_seed(
+            &stake_account_address,
+            &stake_authority_pubkey,
+            42,
+            &Pubkey::new_rand(),
+            &Pubkey::new_rand(),
+            "0",
+        );
+        let message = Message::new(&instructions);
+        let split_index = instructions.len() as u8 - 1;
+
+        let err =
+            TransactionError::InstructionError(split_index, InstructionError::InsufficientFunds);
+        assert_eq!(
+            transaction_error_reason(&err, &message),
+            "insufficient funds for split"
+        );
+
+        let err = TransactionError::InstructionError(
+            split_index,
+            InstructionError::MissingRequiredSignature,
+        );
+        assert_eq!(
+            transaction_error_reason(&err, &message),
+            "missing stake authority signature"
+        );
+
+        let err = TransactionError::InstructionError(0, InstructionError::CustomError(0));
+        assert_eq!(
+            transaction_error_reason(&err, &message),
+            "stake account already exists"
+        );
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let address = Pubkey::new_rand();
+        let timeout = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        assert!(Error::fetch(ClientError::from(timeout)).is_transient());
+        assert!(Error::account(0, &address, Phase::Send, "connection refused").is_transient());
+        assert!(!Error::account(0, &address, Phase::Confirm, "insufficient funds").is_transient());
+        assert!(!Error::Config("bad plan".to_string()).is_transient());
+        let err = Error::Client {
+            phase: Phase::Send,
+            source: ClientError::from(TransactionError::AccountNotFound),
+        };
+        assert!(!err.is_transient());
+    }
+}
@@ -0,0 +1,300 @@
+use crate::client::{sign_message, wait_for_commitment, Client, CommitmentRpcClient};
+use solana_client::{client_error::ClientError, rpc_response::ConfirmedTransaction};
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, Slot},
+    commitment_config::CommitmentConfig,
+    fee_calculator::FeeCalculator,
+    hash::Hash,
+    message::Message,
+    pubkey::Pubkey,
+    signature::Signature,
+    signers::Signers,
+    transaction::{self, Transaction},
+};
+use std::cell::Cell;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How many times to try each node before a query fails.
+const ATTEMPTS_PER_NODE: usize = 2;
+
+/// How long to wait before retrying a query a node refused for exceeding its
+/// rate limit. The wait doubles with each refusal, up to MAX_BACKOFF.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Spaces requests so no more than a given number are made per second.
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next: Cell<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second,
+            next: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request is allowed.
+    pub fn wait(&self) {
+        let now = Instant::now();
+        let next = self.next.get();
+        if next > now {
+            sleep(next - now);
+        }
+        self.next.set(next.max(now) + self.interval);
+    }
+}
+
+// Errors from the cluster rejecting a transaction are final, all others may be
+// the node's fault.
+fn is_transport_error(err: &ClientError) -> bool {
+    match err {
+        ClientError::TransactionError(_) | ClientError::SigningError(_) => false,
+        _ => true,
+    }
+}
+
+// Whether the node refused the request with 429 Too Many Requests.
+fn is_rate_limited(err: &ClientError) -> bool {
+    match err {
+        ClientError::Reqwest(err) => err.status().map_or(false, |status| status.as_u16() == 429),
+        _ => false,
+    }
+}
+
+/// A client that spreads over several RPC nodes, moving on to the next when
+/// one fails. Queries are retried on the other nodes, backing off when a node
+/// reports it is rate limited. Transactions are not, since the failed node may
+/// have forwarded one before failing; the send retries recognize whether it
+/// landed. Every request, including the status polls while a transaction is
+/// confirmed, goes through the rate limiter.
+pub(crate) struct FailoverClient {
+    clients: Vec<CommitmentRpcClient>,
+    current: Cell<usize>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl FailoverClient {
+    pub fn new(clients: Vec<CommitmentRpcClient>, rate_limiter: Option<RateLimiter>) -> Self {
+        assert!(!clients.is_empty());
+        Self {
+            clients,
+            current: Cell::new(0),
+            rate_limiter,
+        }
+    }
+
+    fn failover(&self) {
+        self.current
+            .set((self.current.get() + 1) % self.clients.len());
+    }
+
+    fn send<T, F>(&self, f: F) -> Result<T, ClientError>
+    where
+        F: Fn(&CommitmentRpcClient) -> Result<T, ClientError>,
+    {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.wait();
+        }
+        let result = f(&self.clients[self.current.get()]);
+        if let Err(err) = &result {
+            if is_transport_error(err) {
+                self.failover();
+            }
+        }
+        result
+    }
+
+    fn query<T, F>(&self, f: F) -> Result<T, ClientError>
+    where
+        F: Fn(&CommitmentRpcClient) -> Result<T, ClientError>,
+    {
+        let mut attempts = ATTEMPTS_PER_NODE * self.clients.len();
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match self.send(&f) {
+                Err(err) if is_transport_error(&err) && attempts > 1 => {
+                    attempts -= 1;
+                    if is_rate_limited(&err) {
+                        sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Client for FailoverClient {
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError> {
+        self.query(|client| client.get_account(pubkey))
+    }
+
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+        self.query(|client| client.get_balance(pubkey))
+    }
+
+    fn get_slot(&self) -> Result<Slot, ClientError> {
+        self.query(|client| client.get_slot())
+    }
+
+    fn get_epoch(&self) -> Result<Epoch, ClientError> {
+        self.query(|client| client.get_epoch())
+    }
+
+    fn get_recent_blockhash(&self) -> Result<(Hash, FeeCalculator), ClientError> {
+        self.query(|client| client.get_recent_blockhash())
+    }
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, ClientError> {
+        self.query(|client| client.get_minimum_balance_for_rent_exemption(data_len))
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<transaction::Result<()>>, ClientError> {
+        self.query(|client| client.get_signature_status(signature, commitment))
+    }
+
+    fn get_confirmed_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        start_slot: Slot,
+        end_slot: Slot,
+    ) -> Result<Vec<Signature>, ClientError> {
+        self.query(|client| {
+            client.get_confirmed_signatures_for_address(address, start_slot, end_slot)
+        })
+    }
+
+    fn get_confirmed_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<ConfirmedTransaction, ClientError> {
+        self.query(|client| client.get_confirmed_transaction(signature))
+    }
+
+    // Every node is configured alike.
+    fn confirm_commitment(&self) -> CommitmentConfig {
+        self.clients[0].confirm_commitment()
+    }
+
+    fn send_and_confirm_message<S: Signers>(
+        &self,
+        message: Message,
+        signers: &S,
+    ) -> Result<String, ClientError> {
+        let transaction = sign_message(self, message, signers)?;
+        self.send_and_confirm_transaction(transaction)
+    }
+
+    fn send_and_confirm_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<String, ClientError> {
+        let signature = self.send(|client| client.send_transaction(&transaction))?;
+        wait_for_commitment(self, &signature, self.confirm_commitment())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_client::RpcClient;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serve `num_requests` JSON-RPC requests, answering each with `result`,
+    // after refusing the first `num_rate_limited` with 429 Too Many Requests.
+    fn spawn_rpc_server(
+        num_rate_limited: usize,
+        num_requests: usize,
+        result: &'static str,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let incoming = listener.incoming().take(num_rate_limited + num_requests);
+            for (i, stream) in incoming.enumerate() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let header = "content-length:";
+                    if line.starts_with(header) {
+                        content_length = line[header.len()..].trim().parse().unwrap();
+                    }
+                }
+                let mut request = vec![0; content_length];
+                reader.read_exact(&mut request).unwrap();
+                let body = format!(r#"{{"jsonrpc":"2.0","result":{},"id":1}}"#, result);
+                let status = if i < num_rate_limited {
+                    "429 Too Many Requests"
+                } else {
+                    "200 OK"
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    fn create_client(url: String) -> CommitmentRpcClient {
+        CommitmentRpcClient::new(RpcClient::new(url), CommitmentConfig::default(), false)
+    }
+
+    #[test]
+    fn test_failover() {
+        // Nothing listens on the first node's port.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let down_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let up_url = spawn_rpc_server(0, 2, r#"{"context":{"slot":1},"value":42}"#);
+        let client =
+            FailoverClient::new(vec![create_client(down_url), create_client(up_url)], None);
+
+        assert_eq!(client.get_balance(&Pubkey::new_rand()).unwrap(), 42);
+        // The working node is used from then on.
+        assert_eq!(client.current.get(), 1);
+        assert_eq!(client.get_balance(&Pubkey::new_rand()).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_rate_limited_query_backs_off() {
+        let url = spawn_rpc_server(1, 1, r#"{"context":{"slot":1},"value":42}"#);
+        let client = FailoverClient::new(vec![create_client(url)], None);
+
+        let start = Instant::now();
+        assert_eq!(client.get_balance(&Pubkey::new_rand()).unwrap(), 42);
+        assert!(start.elapsed() >= INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let rate_limiter = RateLimiter::new(20);
+        let start = Instant::now();
+        for _ in 0..3 {
+            rate_limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::args::OutputFormat;
+use crate::client::Client;
+use crate::error::{Error, Phase};
+use crate::stake_accounts::derive_stake_account_addresses;
+use serde::Serialize;
+use solana_client::{client_error::ClientError, rpc_response::ConfirmedTransaction};
+use solana_sdk::{
+    clock::Slot, message::Message, native_token::lamports_to_sol, pubkey::Pubkey,
+    signature::Signature, system_instruction::SystemInstruction, system_program,
+};
+use solana_stake_program::{stake_instruction::StakeInstruction, stake_state::StakeAuthorize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+/// The widest slot range the RPC server will search for signatures in one request.
+const MAX_SLOT_RANGE: Slot = 10_000;
+
+/// One instruction that touched a derived stake account.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct HistoryEntry {
+    pub slot: Slot,
+    pub signature: String,
+    pub base_pubkey: String,
+    pub index: usize,
+    pub address: String,
+    pub instruction: String,
+    pub lamports: Option<u64>,
+    pub detail: String,
+    pub success: bool,
+}
+
+/// An instruction decoded from a transaction: its kind, the lamports it
+/// moved, if any, and a description of its arguments.
+#[derive(Debug, PartialEq)]
+pub(crate) struct DecodedInstruction {
+    pub kind: &'static str,
+    pub lamports: Option<u64>,
+    pub detail: String,
+}
+
+impl DecodedInstruction {
+    fn new(kind: &'static str, lamports: Option<u64>, detail: String) -> Self {
+        Self {
+            kind,
+            lamports,
+            detail,
+        }
+    }
+}
+
+/// Decode each instruction in the message that references the given address.
+pub(crate) fn decode_instructions(message: &Message, address: &Pubkey) -> Vec<DecodedInstruction> {
+    message
+        .instructions
+        .iter()
+        .filter(|instruction| {
+            instruction
+                .accounts
+                .iter()
+                .any(|i| message.account_keys.get(*i as usize) == Some(address))
+        })
+        .map(|instruction| {
+            // Name the account at a position in the instruction's account list.
+            let account = |position: usize| {
+                instruction
+                    .accounts
+                    .get(position)
+                    .and_then(|i| message.account_keys.get(*i as usize))
+                    .map(|pubkey| pubkey.to_string())
+                    .unwrap_or_else(|| "?".to_string())
+            };
+            let program_id = instruction.program_id(&message.account_keys);
+            if system_program::check_id(program_id) {
+                match bincode::deserialize(&instruction.data) {
+                    Ok(SystemInstruction::CreateAccount { lamports, .. })
+                    | Ok(SystemInstruction::CreateAccountWithSeed { lamports, .. }) => {
+                        DecodedInstruction::new("create", Some(lamports), account(0))
+                    }
+                    Ok(SystemInstruction::Transfer { lamports }) => DecodedInstruction::new(
+                        "transfer",
+                        Some(lamports),
+                        format!("{} to {}", account(0), account(1)),
+                    ),
+                    _ => DecodedInstruction::new("system", None, String::new()),
+                }
+            } else if solana_stake_program::check_id(program_id) {
+                match bincode::deserialize(&instruction.data) {
+                    Ok(StakeInstruction::Initialize(authorized, _)) => DecodedInstruction::new(
+                        "initialize",
+                        None,
+                        format!(
+                            "staker {}, withdrawer {}",
+                            authorized.staker, authorized.withdrawer
+                        ),
+                    ),
+                    Ok(StakeInstruction::Authorize(pubkey, StakeAuthorize::Staker)) => {
+                        DecodedInstruction::new("authorize", None, format!("staker {}", pubkey))
+                    }
+                    Ok(StakeInstruction::Authorize(pubkey, StakeAuthorize::Withdrawer)) => {
+                        DecodedInstruction::new("authorize", None, format!("withdrawer {}", pubkey))
+                    }
+                    Ok(StakeInstruction::DelegateStake) => {
+                        DecodedInstruction::new("delegate", None, format!("to {}", account(1)))
+                    }
+                    Ok(StakeInstruction::Split(lamports)) => DecodedInstruction::new(
+                        "split",
+                        Some(lamports),
+                        format!("{} to {}", account(0), account(1)),
+                    ),
+                    Ok(StakeInstruction::Withdraw(lamports)) => DecodedInstruction::new(
+                        "withdraw",
+                        Some(lamports),
+                        format!("to {}", account(1)),
+                    ),
+                    Ok(StakeInstruction::Deactivate) => {
+                        DecodedInstruction::new("deactivate", None, String::new())
+                    }
+                    _ => DecodedInstruction::new("stake", None, String::new()),
+                }
+            } else {
+                DecodedInstruction::new("other", None, format!("program {}", program_id))
+            }
+        })
+        .collect()
+}
+
+// Return the signatures of every transaction that touched the address in the
+// slot range, requesting at most MAX_SLOT_RANGE slots at a time.
+fn get_signatures<C: Client>(
+    client: &C,
+    address: &Pubkey,
+    start_slot: Slot,
+    end_slot: Slot,
+) -> Result<Vec<Signature>, ClientError> {
+    let mut signatures = vec![];
+    let mut page_start = start_slot;
+    while page_start <= end_slot {
+        let page_end = end_slot.min(page_start + MAX_SLOT_RANGE - 1);
+        let page = client.get_confirmed_signatures_for_address(address, page_start, page_end)?;
+        signatures.extend(page);
+        page_start = page_end + 1;
+    }
+    Ok(signatures)
+}
+
+/// Return every instruction that touched a derived stake account in the slot
+/// range, in slot order.
+pub(crate) fn fetch_history<C: Client>(
+    client: &C,
+    base_pubkeys: &[Pubkey],
+    indices: &[usize],
+    start_slot: Slot,
+    end_slot: Slot,
+) -> Result<Vec<HistoryEntry>, Error> {
+    // A transaction touching several derived accounts is only fetched once.
+    let mut transactions: HashMap<Signature, ConfirmedTransaction> = HashMap::new();
+    let mut entries = vec![];
+    for base_pubkey in base_pubkeys {
+        let addresses = derive_stake_account_addresses(base_pubkey, indices)?;
+        for (&index, address) in indices.iter().zip(&addresses) {
+            let signatures = get_signatures(client, address, start_slot, end_slot)
+                .map_err(|err| Error::account(index, address, Phase::Fetch, err))?;
+            for signature in signatures {
+                if !transactions.contains_key(&signature) {
+                    let transaction = client
+                        .get_confirmed_transaction(&signature)
+                        .map_err(|err| Error::account(index, address, Phase::Fetch, err))?;
+                    transactions.insert(signature, transaction);
+                }
+                let confirmed = &transactions[&signature];
+                let transaction = confirmed.transaction.transaction.decode().ok_or_else(|| {
+                    let reason = format!("unable to decode transaction {}", signature);
+                    Error::account(index, address, Phase::Fetch, reason)
+                })?;
+                let success = confirmed
+                    .transaction
+                    .meta
+                    .as_ref()
+                    .map(|meta| meta.status.is_ok())
+                    .unwrap_or(true);
+                for decoded in decode_instructions(&transaction.message, address) {
+                    entries.push(HistoryEntry {
+                        slot: confirmed.slot,
+                        signature: signature.to_string(),
+                        base_pubkey: base_pubkey.to_string(),
+                        index,
+                        address: address.to_string(),
+                        instruction: decoded.kind.to_string(),
+                        lamports: decoded.lamports,
+                        detail: decoded.detail,
+                        success,
+                    });
+                }
+            }
+        }
+    }
+    entries.sort_by(|a, b| {
+        (a.slot, &a.signature, &a.base_pubkey, a.index).cmp(&(
+            b.slot,
+            &b.signature,
+            &b.base_pubkey,
+            b.index,
+        ))
+    });
+    Ok(entries)
+}
+
+fn write_table<W: Write>(writer: &mut W, entries: &[HistoryEntry]) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "{:<10} {:<88} {:<44} {:>5} {:<12} {:>14} {:<7} DETAIL",
+        "SLOT", "SIGNATURE", "ADDRESS", "INDEX", "INSTRUCTION", "SOL", "STATUS"
+    )?;
+    for entry in entries {
+        let sol = entry
+            .lamports
+            .map(|lamports| lamports_to_sol(lamports).to_string())
+            .unwrap_or_default();
+        let status = if entry.success { "ok" } else { "failed" };
+        writeln!(
+            writer,
+            "{:<10} {:<88} {:<44} {:>5} {:<12} {:>14} {:<7} {}",
+            entry.slot,
+            entry.signature,
+            entry.address,
+            entry.index,
+            entry.instruction,
+            sol,
+            status,
+            entry.detail
+        )?;
+    }
+    Ok(())
+}
+
+fn write_error<E: fmt::Display>(err: E) -> Error {
+    Error::Config(format!("unable to write history: {}", err))
+}
+
+/// Write the history entries in the given format.
+pub(crate) fn write_history<W: Write>(
+    mut writer: W,
+    entries: &[HistoryEntry],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Table => write_table(&mut writer, entries).map_err(write_error),
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, entries).map_err(write_error),
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for entry in entries {
+                writer.serialize(entry).map_err(write_error)?;
+            }
+            writer.flush().map_err(write_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stake_accounts::{
+        authorize_stake_accounts, derive_stake_account_address, new_stake_account,
+    };
+
+    #[test]
+    fn test_decode_instructions() {
+        let fee_payer_pubkey = Pubkey::new_rand();
+        let base_pubkey = Pubkey::new_rand();
+        let stake_authority_pubkey = Pubkey::new_rand();
+        let withdraw_authority_pubkey = Pubkey::new_rand();
+        let address = derive_stake_account_address(&base_pubkey, 0).unwrap();
+
+        let message = new_stake_account(
+            &fee_payer_pubkey,
+            &fee_payer_pubkey,
+            &base_pubkey,
+            42,
+            &stake_authority_pubkey,
+            &withdraw_authority_pubkey,
+        )
+        .unwrap();
+        let decoded = decode_instructions(&message, &address);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].kind, "create");
+        assert_eq!(decoded[0].lamports, Some(42));
+        assert_eq!(decoded[1].kind, "initialize");
+        assert_eq!(
+            decoded[1].detail,
+            format!(
+                "staker {}, withdrawer {}",
+                stake_authority_pubkey, withdraw_authority_pubkey
+            )
+        );
+
+        let new_stake_authority_pubkey = Pubkey::new_rand();
+        let messages = authorize_stake_accounts(
+            &fee_payer_pubkey,
+            &base_pubkey,
+            &stake_authority_pubkey,
+            &withdraw_authority_pubkey,
+            &new_stake_authority_pubkey,
+            &withdraw_authority_pubkey,
+            &[0],
+        )
+        .unwrap();
+        let decoded = decode_instructions(&messages[0], &address);
+        assert_eq!(
+            decoded,
+            vec![
+                DecodedInstruction::new(
+                    "authorize",
+                    None,
+                    format!("staker {}", new_stake_authority_pubkey)
+                ),
+                DecodedInstruction::new(
+                    "authorize",
+                    None,
+                    format!("withdrawer {}", withdraw_authority_pubkey)
+                ),
+            ]
+        );
+
+        // Instructions that don't reference the address are skipped.
+        assert!(decode_instructions(&messages[0], &Pubkey::new_rand()).is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::client::Client;
+use crate::error::Error;
+use crate::profile::SeedScheme;
+use crate::stake_accounts::derive_stake_account_address_with_scheme;
+use solana_sdk::pubkey::Pubkey;
+use solana_stake_program::stake_state::StakeState;
+use std::fmt;
+use std::io::Write;
+
+/// Where a derived stake account address comes from.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Location {
+    pub base_pubkey: Pubkey,
+    pub seed_scheme: SeedScheme,
+    pub seed: String,
+    pub index: usize,
+}
+
+/// Search the first `search_limit` indices of each base, under the base's
+/// seed scheme, for the one that derives `address`.
+pub(crate) fn locate_stake_account(
+    address: &Pubkey,
+    bases: &[(Pubkey, SeedScheme)],
+    search_limit: usize,
+) -> Option<Location> {
+    for (base_pubkey, seed_scheme) in bases {
+        for index in 0..search_limit {
+            let derived =
+                derive_stake_account_address_with_scheme(base_pubkey, *seed_scheme, index);
+            if derived.ok().as_ref() == Some(address) {
+                return Some(Location {
+                    base_pubkey: *base_pubkey,
+                    seed_scheme: *seed_scheme,
+                    seed: seed_scheme.seed(index),
+                    index,
+                });
+            }
+        }
+    }
+    None
+}
+
+fn write_error<E: fmt::Display>(err: E) -> Error {
+    Error::Config(format!("unable to write location: {}", err))
+}
+
+/// Write the base, seed and index that derive `address`, or if no base does
+/// within `search_limit` indices, the authorities of the stake account there.
+pub(crate) fn write_location<C: Client, W: Write>(
+    client: &C,
+    mut writer: W,
+    address: &Pubkey,
+    bases: &[(Pubkey, SeedScheme)],
+    search_limit: usize,
+) -> Result<(), Error> {
+    if let Some(location) = locate_stake_account(address, bases, search_limit) {
+        writeln!(writer, "Base:  {}", location.base_pubkey).map_err(write_error)?;
+        writeln!(writer, "Seed:  {}", location.seed).map_err(write_error)?;
+        return writeln!(writer, "Index: {}", location.index).map_err(write_error);
+    }
+
+    // Not one of ours, so identify who controls it instead.
+    writeln!(
+        writer,
+        "{} is not derived from any known base within {} indices",
+        address, search_limit
+    )
+    .map_err(write_error)?;
+    let account = client.get_account(address).map_err(Error::fetch)?;
+    match account.as_ref().and_then(StakeState::authorized_from) {
+        Some(authorized) => {
+            writeln!(writer, "Staker:     {}", authorized.staker).map_err(write_error)?;
+            writeln!(writer, "Withdrawer: {}", authorized.withdrawer).map_err(write_error)
+        }
+        None if account.is_some() => {
+            writeln!(writer, "It is not an initialized stake account").map_err(write_error)
+        }
+        None => writeln!(writer, "It has no account").map_err(write_error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stake_accounts::derive_stake_account_address;
+
+    #[test]
+    fn test_locate_stake_account() {
+        let base_pubkeys = [Pubkey::new_rand(), Pubkey::new_rand()];
+        let bases: Vec<_> = base_pubkeys
+            .iter()
+            .map(|base_pubkey| (*base_pubkey, SeedScheme::Index))
+            .collect();
+        let address = derive_stake_account_address(&base_pubkeys[1], 5).unwrap();
+        assert_eq!(
+            locate_stake_account(&address, &bases, 10),
+            Some(Location {
+                base_pubkey: base_pubkeys[1],
+                seed_scheme: SeedScheme::Index,
+                seed: "5".to_string(),
+                index: 5,
+            })
+        );
+
+        // The index is beyond the search bound.
+        assert_eq!(locate_stake_account(&address, &bases, 5), None);
+        assert_eq!(locate_stake_account(&Pubkey::new_rand(), &bases, 10), None);
+    }
+}
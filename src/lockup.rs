@@ -0,0 +1,44 @@
+use chrono::NaiveDateTime;
+use solana_sdk::{account::Account, clock::UnixTimestamp, sysvar::clock::Clock};
+use solana_stake_program::stake_state::{Lockup, StakeState};
+
+pub(crate) const DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Return the lockup of a stake account, or None if it isn't an initialized stake account.
+pub(crate) fn get_lockup(account: &Account) -> Option<Lockup> {
+    match StakeState::from(account)? {
+        StakeState::Initialized(meta) | StakeState::Stake(meta, _) => Some(meta.lockup),
+        _ => None,
+    }
+}
+
+/// Return true if the lockup has not yet expired at the given clock.
+pub(crate) fn is_locked(lockup: &Lockup, clock: &Clock) -> bool {
+    lockup.unix_timestamp > clock.unix_timestamp || lockup.epoch > clock.epoch
+}
+
+/// Format a lockup time in UTC, or as the raw number if it's beyond the calendar.
+pub(crate) fn format_unix_timestamp(unix_timestamp: UnixTimestamp, format: &str) -> String {
+    NaiveDateTime::from_timestamp_opt(unix_timestamp, 0).map_or_else(
+        || unix_timestamp.to_string(),
+        |date_time| date_time.format(format).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_unix_timestamp() {
+        assert_eq!(
+            format_unix_timestamp(0, DATE_TIME_FORMAT),
+            "1970-01-01 00:00:00"
+        );
+        assert_eq!(format_unix_timestamp(1_590_969_600, "%Y-%m"), "2020-06");
+        assert_eq!(
+            format_unix_timestamp(i64::MAX, DATE_TIME_FORMAT),
+            i64::MAX.to_string()
+        );
+    }
+}
@@ -1,79 +1,190 @@
+mod activation;
 mod args;
+mod audit;
+mod clawback;
+mod client;
+mod delegate;
+mod distribute;
+mod error;
+mod failover;
+mod history;
+mod locate;
+mod lockup;
+mod profile;
+mod progress;
+mod rebalance;
+mod receipt;
+mod retry;
+mod scheduler;
+mod snapshot;
 mod stake_accounts;
+mod state_file;
+mod transaction_plan;
+mod unlocks;
+mod vest;
+mod watch;
 
+use crate::activation::{get_stake_balance, StakeBalance};
 use crate::args::{
-    parse_args, AuthorizeCommandConfig, Command, MoveCommandConfig, NewCommandConfig,
-    RebaseCommandConfig,
+    parse_args, AuditCommandConfig, AuthorizeCommandConfig, ClawbackCommandConfig, Command,
+    CountCommandConfig, DelegateCommandConfig, DiffCommandConfig, DistributeCommandConfig,
+    HistoryCommandConfig, LocateCommandConfig, MoveCommandConfig, NewCommandConfig,
+    PlanCommandConfig, PlannedCommand, QueryCommandConfig, RebalanceCommandConfig,
+    RebaseCommandConfig, RunPlanCommandConfig, SignCommandConfig, SnapshotCommandConfig,
+    StatusCommandConfig, SubmitCommandConfig, UnlocksCommandConfig, VestCommandConfig,
+    WatchCommandConfig,
 };
+use crate::audit::{audit, read_policy_file, write_audit_report, Severity};
+use crate::clawback::{plan_clawback, Disposition};
+use crate::client::{Client, CommitmentRpcClient};
+use crate::delegate::{
+    plan_delegation, read_targets_file, DelegateAction, DelegationPlan, StakeAccountInfo, Target,
+};
+use crate::distribute::{read_allocations_file, unsent_allocations, DistributionDb, SentRecord};
+use crate::error::{Error, Phase};
+use crate::failover::{FailoverClient, RateLimiter};
+use crate::history::{fetch_history, write_history};
+use crate::locate::write_location;
+use crate::lockup::{format_unix_timestamp, get_lockup, DATE_TIME_FORMAT};
+use crate::progress::Batch;
+use crate::rebalance::{
+    is_cooling_down, plan_deactivations, stake_account_info, RebalanceAction, RebalanceState,
+    RebalanceStep,
+};
+use crate::receipt::{update_receipt, Receipt, ReceiptFile};
+use crate::retry::{send_with_retries, Applied};
+use crate::scheduler::{
+    read_plan_file, readiness, DoneStep, Plan, PlanProgress, Readiness, SentStep, Step, StepAction,
+};
+use crate::snapshot::{
+    fetch_account_snapshots, read_snapshot, write_diff, write_snapshot, Snapshot, SNAPSHOT_VERSION,
+};
+use crate::state_file::{load_state, save_state};
+use crate::transaction_plan::{
+    message_with_nonce, nonce_data, read_transaction_plan_file, sign_plan, PlannedTransaction,
+    TransactionPlan,
+};
+use crate::unlocks::{unlock_schedule, write_unlock_schedule};
+use crate::vest::vesting_schedule;
+use crate::watch::{report_alert, ExpectedAuthorities, Watcher};
 use clap::ArgMatches;
 use solana_clap_utils::keypair::{pubkey_from_path, signer_from_path};
 use solana_cli_config::Config;
-use solana_client::client_error::ClientError;
 use solana_client::rpc_client::RpcClient;
 use solana_remote_wallet::remote_wallet::{maybe_wallet_manager, RemoteWalletManager};
 use solana_sdk::{
-    message::Message, native_token::lamports_to_sol, pubkey::Pubkey, signature::Signer,
-    signers::Signers, transaction::Transaction,
+    account::Account,
+    clock::{Epoch, Slot, MAX_RECENT_BLOCKHASHES},
+    commitment_config::CommitmentConfig,
+    message::Message,
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+    signers::Signers,
+    sysvar::{self, clock::Clock, stake_history::StakeHistory, Sysvar},
+    transaction::Transaction,
 };
+use solana_stake_program::stake_state::{Authorized, Lockup, StakeState};
+use std::collections::HashMap;
 use std::env;
-use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::process::exit;
+use std::slice;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+use std::u64;
 
 fn resolve_stake_authority(
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     key_url: &str,
-) -> Result<Box<dyn Signer>, Box<dyn Error>> {
+) -> Result<Box<dyn Signer>, Error> {
     let matches = ArgMatches::default();
     signer_from_path(&matches, key_url, "stake authority", wallet_manager)
+        .map_err(|err| Error::resolve("stake authority", err))
 }
 
 fn resolve_withdraw_authority(
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     key_url: &str,
-) -> Result<Box<dyn Signer>, Box<dyn Error>> {
+) -> Result<Box<dyn Signer>, Error> {
     let matches = ArgMatches::default();
     signer_from_path(&matches, key_url, "withdraw authority", wallet_manager)
+        .map_err(|err| Error::resolve("withdraw authority", err))
 }
 
 fn resolve_new_stake_authority(
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     key_url: &str,
-) -> Result<Pubkey, Box<dyn Error>> {
+) -> Result<Pubkey, Error> {
     let matches = ArgMatches::default();
     pubkey_from_path(&matches, key_url, "new stake authority", wallet_manager)
+        .map_err(|err| Error::resolve("new stake authority", err))
 }
 
 fn resolve_new_withdraw_authority(
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     key_url: &str,
-) -> Result<Pubkey, Box<dyn Error>> {
+) -> Result<Pubkey, Error> {
     let matches = ArgMatches::default();
     pubkey_from_path(&matches, key_url, "new withdraw authority", wallet_manager)
+        .map_err(|err| Error::resolve("new withdraw authority", err))
 }
 
 fn resolve_fee_payer(
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     key_url: &str,
-) -> Result<Box<dyn Signer>, Box<dyn Error>> {
+) -> Result<Box<dyn Signer>, Error> {
     let matches = ArgMatches::default();
     signer_from_path(&matches, key_url, "fee-payer", wallet_manager)
+        .map_err(|err| Error::resolve("fee-payer", err))
 }
 
 fn resolve_base_pubkey(
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     key_url: &str,
-) -> Result<Pubkey, Box<dyn Error>> {
+) -> Result<Pubkey, Error> {
     let matches = ArgMatches::default();
     pubkey_from_path(&matches, key_url, "base pubkey", wallet_manager)
+        .map_err(|err| Error::resolve("base pubkey", err))
+}
+
+fn resolve_new_base_keypair(
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    key_url: &str,
+) -> Result<Box<dyn Signer>, Error> {
+    let matches = ArgMatches::default();
+    signer_from_path(&matches, key_url, "new base keypair", wallet_manager)
+        .map_err(|err| Error::resolve("new base keypair", err))
+}
+
+fn resolve_base_pubkeys(
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    key_urls: &[String],
+) -> Result<Vec<Pubkey>, Error> {
+    key_urls
+        .iter()
+        .map(|key_url| resolve_base_pubkey(wallet_manager, key_url))
+        .collect()
+}
+
+fn get_balance_at<C: Client>(client: &C, pubkey: &Pubkey, i: usize) -> Result<u64, Error> {
+    let address = stake_accounts::derive_stake_account_address(pubkey, i)?;
+    client
+        .get_balance(&address)
+        .map_err(|err| Error::account(i, &address, Phase::Fetch, err))
 }
 
-fn get_balance_at(client: &RpcClient, pubkey: &Pubkey, i: usize) -> Result<u64, ClientError> {
-    let address = stake_accounts::derive_stake_account_address(pubkey, i);
-    client.get_balance(&address)
+// Return the index past the last of the given indices
+fn end_index(indices: &[usize]) -> usize {
+    indices.iter().max().map_or(0, |index| index + 1)
 }
 
 // Return the number of derived stake accounts with balances
-fn count_stake_accounts(client: &RpcClient, base_pubkey: &Pubkey) -> Result<usize, ClientError> {
+fn count_stake_accounts<C: Client>(client: &C, base_pubkey: &Pubkey) -> Result<usize, Error> {
     let mut i = 0;
     while get_balance_at(client, base_pubkey, i)? > 0 {
         i += 1;
@@ -81,21 +192,405 @@ fn count_stake_accounts(client: &RpcClient, base_pubkey: &Pubkey) -> Result<usiz
     Ok(i)
 }
 
-fn get_balances(
-    client: &RpcClient,
+// Return the balance of the derived stake account at each index
+fn get_balances<C: Client>(
+    client: &C,
+    indices: &[usize],
     pubkeys: Vec<Pubkey>,
-) -> Result<Vec<(Pubkey, u64)>, ClientError> {
-    pubkeys
-        .into_iter()
-        .map(|pubkey| client.get_balance(&pubkey).map(|bal| (pubkey, bal)))
+) -> Result<Vec<(Pubkey, u64)>, Error> {
+    indices
+        .iter()
+        .zip(pubkeys)
+        .map(|(&i, pubkey)| {
+            client
+                .get_balance(&pubkey)
+                .map(|bal| (pubkey, bal))
+                .map_err(|err| Error::account(i, &pubkey, Phase::Fetch, err))
+        })
+        .collect()
+}
+
+fn process_count_stake_accounts<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    count_config: &CountCommandConfig,
+) -> Result<(), Error> {
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &count_config.base_pubkeys)?;
+    write_counts(client, io::stdout(), &base_pubkeys)
+}
+
+// Write the number of stake accounts derived from a single base, or from each
+// of several bases followed by their total.
+fn write_counts<C: Client, W: Write>(
+    client: &C,
+    mut writer: W,
+    base_pubkeys: &[Pubkey],
+) -> Result<(), Error> {
+    let write_error = |err: io::Error| Error::Config(format!("unable to write count: {}", err));
+    if let [base_pubkey] = base_pubkeys {
+        let num_accounts = count_stake_accounts(client, base_pubkey)?;
+        return writeln!(writer, "{}", num_accounts).map_err(write_error);
+    }
+    let mut total = 0;
+    for base_pubkey in base_pubkeys {
+        let num_accounts = count_stake_accounts(client, base_pubkey)?;
+        writeln!(writer, "{}: {}", base_pubkey, num_accounts).map_err(write_error)?;
+        total += num_accounts;
+    }
+    writeln!(writer, "Total: {}", total).map_err(write_error)
+}
+
+fn process_pubkeys(
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    query_config: &QueryCommandConfig,
+) -> Result<(), Error> {
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    for base_pubkey in &base_pubkeys {
+        let pubkeys =
+            stake_accounts::derive_stake_account_addresses(base_pubkey, &query_config.indices)?;
+        if base_pubkeys.len() > 1 {
+            println!("{}:", base_pubkey);
+        }
+        for pubkey in pubkeys {
+            println!("{:?}", pubkey);
+        }
+    }
+    Ok(())
+}
+
+// Return the account at each address, or None if there is no account at that address
+fn get_accounts<C: Client>(client: &C, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>, Error> {
+    let indices: Vec<_> = (0..pubkeys.len()).collect();
+    get_indexed_accounts(client, &indices, pubkeys)
+}
+
+// Return the account of the derived stake account at each index
+fn get_indexed_accounts<C: Client>(
+    client: &C,
+    indices: &[usize],
+    pubkeys: &[Pubkey],
+) -> Result<Vec<Option<Account>>, Error> {
+    indices
+        .iter()
+        .zip(pubkeys)
+        .map(|(&i, pubkey)| {
+            client
+                .get_account(pubkey)
+                .map_err(|err| Error::account(i, pubkey, Phase::Fetch, err))
+        })
         .collect()
 }
 
-fn process_new_stake_account(
-    client: &RpcClient,
+// Return the base pubkey, index, address and account of each derived stake account
+fn get_derived_accounts<C: Client>(
+    client: &C,
+    base_pubkeys: &[Pubkey],
+    indices: &[usize],
+) -> Result<Vec<(Pubkey, usize, Pubkey, Option<Account>)>, Error> {
+    let mut accounts = vec![];
+    for base_pubkey in base_pubkeys {
+        let addresses = stake_accounts::derive_stake_account_addresses(base_pubkey, indices)?;
+        let base_accounts = get_indexed_accounts(client, indices, &addresses)?;
+        for ((&i, address), account) in indices.iter().zip(addresses).zip(base_accounts) {
+            accounts.push((*base_pubkey, i, address, account));
+        }
+    }
+    Ok(accounts)
+}
+
+// Return the current epoch and the cluster's stake history
+fn get_stake_history<C: Client>(client: &C) -> Result<(Epoch, StakeHistory), Error> {
+    let epoch = client.get_epoch().map_err(Error::fetch)?;
+    let stake_history = client
+        .get_account(&sysvar::stake_history::id())
+        .map_err(Error::fetch)?
+        .and_then(|account| StakeHistory::from_account(&account))
+        .ok_or(Error::InvalidSysvar {
+            name: "stake history",
+        })?;
+    Ok((epoch, stake_history))
+}
+
+// Return the cluster's clock
+fn get_clock<C: Client>(client: &C) -> Result<Clock, Error> {
+    client
+        .get_account(&sysvar::clock::id())
+        .map_err(Error::fetch)?
+        .and_then(|account| Clock::from_account(&account))
+        .ok_or(Error::InvalidSysvar { name: "clock" })
+}
+
+fn print_stake_balance(index: &str, address: &str, balance: &StakeBalance) {
+    println!(
+        "{:<6} {:<44} {:>16} {:>16} {:>16} {:>16} {:>16}",
+        index,
+        address,
+        lamports_to_sol(balance.effective),
+        lamports_to_sol(balance.activating),
+        lamports_to_sol(balance.deactivating),
+        lamports_to_sol(balance.inactive),
+        lamports_to_sol(balance.undelegated),
+    );
+}
+
+fn process_balance<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    query_config: &QueryCommandConfig,
+) -> Result<(), Error> {
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    let (epoch, stake_history) = get_stake_history(client)?;
+    println!(
+        "{:<6} {:<44} {:>16} {:>16} {:>16} {:>16} {:>16}",
+        "Index", "Address", "Effective", "Activating", "Deactivating", "Inactive", "Undelegated",
+    );
+    let mut total = StakeBalance::default();
+    for base_pubkey in &base_pubkeys {
+        let pubkeys =
+            stake_accounts::derive_stake_account_addresses(base_pubkey, &query_config.indices)?;
+        let accounts = get_indexed_accounts(client, &query_config.indices, &pubkeys)?;
+        if base_pubkeys.len() > 1 {
+            println!("{}:", base_pubkey);
+        }
+        let mut base_total = StakeBalance::default();
+        for ((i, pubkey), account) in query_config.indices.iter().zip(&pubkeys).zip(accounts) {
+            let balance = account
+                .map(|account| get_stake_balance(&account, epoch, &stake_history))
+                .unwrap_or_default();
+            print_stake_balance(&i.to_string(), &pubkey.to_string(), &balance);
+            base_total += balance;
+        }
+        if base_pubkeys.len() > 1 {
+            print_stake_balance("", "Subtotal", &base_total);
+        }
+        total += base_total;
+    }
+    print_stake_balance("", "Total", &total);
+    println!("{} SOL", lamports_to_sol(total.lamports()));
+    Ok(())
+}
+
+fn process_snapshot<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    snapshot_config: &SnapshotCommandConfig,
+) -> Result<(), Error> {
+    let query_config = &snapshot_config.query_config;
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    let slot = client.get_slot().map_err(Error::fetch)?;
+    let epoch = client.get_epoch().map_err(Error::fetch)?;
+    let accounts = fetch_account_snapshots(client, &base_pubkeys, &query_config.indices)?;
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        slot,
+        epoch,
+        accounts,
+    };
+    write_snapshot(&snapshot_config.output_path, &snapshot)
+}
+
+fn process_diff(diff_config: &DiffCommandConfig) -> Result<(), Error> {
+    let before = read_snapshot(&diff_config.before_path)?;
+    let after = read_snapshot(&diff_config.after_path)?;
+    write_diff(io::stdout(), &before, &after)
+}
+
+fn resolve_pubkey(
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    key_url: &str,
+    name: &str,
+) -> Result<Pubkey, Error> {
+    let matches = ArgMatches::default();
+    pubkey_from_path(&matches, key_url, name, wallet_manager)
+        .map_err(|err| Error::resolve(name, err))
+}
+
+fn resolve_optional_pubkey(
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    key_url: &Option<String>,
+    name: &str,
+) -> Result<Option<Pubkey>, Error> {
+    key_url
+        .as_ref()
+        .map(|key_url| resolve_pubkey(wallet_manager, key_url, name))
+        .transpose()
+}
+
+fn process_watch<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    watch_config: &WatchCommandConfig,
+) -> Result<(), Error> {
+    let query_config = &watch_config.query_config;
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    let expected = ExpectedAuthorities {
+        staker: resolve_optional_pubkey(
+            wallet_manager,
+            &watch_config.expected_stake_authority,
+            "expected stake authority",
+        )?,
+        withdrawer: resolve_optional_pubkey(
+            wallet_manager,
+            &watch_config.expected_withdraw_authority,
+            "expected withdraw authority",
+        )?,
+        custodian: resolve_optional_pubkey(
+            wallet_manager,
+            &watch_config.expected_custodian,
+            "expected custodian",
+        )?,
+    };
+    let mut log_file = match &watch_config.log_file {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| Error::Config(format!("{}: {}", path, err)))?,
+        ),
+        None => None,
+    };
+
+    let mut watcher = Watcher::new(client, base_pubkeys, query_config.indices.clone(), expected);
+    loop {
+        match watcher.poll() {
+            Ok(alerts) => {
+                for alert in alerts {
+                    report_alert(
+                        &alert,
+                        log_file.as_mut(),
+                        watch_config.alert_command.as_deref(),
+                    )?;
+                }
+            }
+            // Keep watching through transient RPC failures only.
+            Err(err) if err.is_transient() => eprintln!("error: {}", err),
+            Err(err) => return Err(err),
+        }
+        sleep(Duration::from_secs(watch_config.interval_secs));
+    }
+}
+
+fn process_unlocks<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    unlocks_config: &UnlocksCommandConfig,
+) -> Result<(), Error> {
+    let query_config = &unlocks_config.query_config;
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    let clock = get_clock(client)?;
+    let accounts = get_derived_accounts(client, &base_pubkeys, &query_config.indices)?;
+    let schedule = unlock_schedule(&accounts, &clock);
+    write_unlock_schedule(io::stdout(), &schedule, unlocks_config.output_format)
+}
+
+fn process_audit<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    audit_config: &AuditCommandConfig,
+) -> Result<(), Error> {
+    let query_config = &audit_config.query_config;
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    let policy = read_policy_file(&audit_config.policy_path)?;
+    let accounts = get_derived_accounts(client, &base_pubkeys, &query_config.indices)?;
+    let report = audit(&accounts, &policy);
+    write_audit_report(io::stdout(), &report, audit_config.output_format)?;
+    if report.severity == Severity::Pass {
+        return Ok(());
+    }
+    let num_accounts = report
+        .accounts
+        .iter()
+        .filter(|audit| audit.severity() != Severity::Pass)
+        .count();
+    Err(Error::AuditFailed {
+        severity: report.severity,
+        num_accounts,
+    })
+}
+
+fn process_history<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    history_config: &HistoryCommandConfig,
+) -> Result<(), Error> {
+    let query_config = &history_config.query_config;
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &query_config.base_pubkeys)?;
+    let end_slot = match history_config.end_slot {
+        Some(end_slot) => end_slot,
+        None => client.get_slot().map_err(Error::fetch)?,
+    };
+    let entries = fetch_history(
+        client,
+        &base_pubkeys,
+        &query_config.indices,
+        history_config.start_slot,
+        end_slot,
+    )?;
+    write_history(io::stdout(), &entries, history_config.output_format)
+}
+
+fn check_balance<C: Client>(
+    client: &C,
+    name: &str,
+    pubkey: &Pubkey,
+    required: u64,
+) -> Result<(), Error> {
+    let balance = client.get_balance(pubkey).map_err(Error::fetch)?;
+    if balance < required {
+        return Err(Error::InsufficientFunds {
+            name: name.to_string(),
+            address: *pubkey,
+            balance,
+            required,
+        });
+    }
+    Ok(())
+}
+
+// Ensure the fee payer can pay the fees of every message, and that the
+// sender, if any, can fund the given number of lamports.
+fn check_payer_balances<C: Client>(
+    client: &C,
+    messages: &[Message],
+    fee_payer_pubkey: &Pubkey,
+    sender: Option<(&Pubkey, u64)>,
+) -> Result<(), Error> {
+    let (_, fee_calculator) = client.get_recent_blockhash().map_err(Error::fetch)?;
+    // A total past u64::MAX can't be funded, so it saturates and the balance
+    // check reports it.
+    let fees = messages.iter().fold(0u64, |fees, message| {
+        fees.saturating_add(fee_calculator.calculate_fee(message))
+    });
+    match sender {
+        Some((sender_pubkey, lamports)) if sender_pubkey == fee_payer_pubkey => {
+            let required = fees.checked_add(lamports).unwrap_or(u64::MAX);
+            check_balance(client, "fee payer", fee_payer_pubkey, required)
+        }
+        Some((sender_pubkey, lamports)) => {
+            check_balance(client, "fee payer", fee_payer_pubkey, fees)?;
+            check_balance(client, "sender", sender_pubkey, lamports)
+        }
+        None => check_balance(client, "fee payer", fee_payer_pubkey, fees),
+    }
+}
+
+fn check_rent_exemption<C: Client>(client: &C, lamports: u64) -> Result<(), Error> {
+    let minimum = client
+        .get_minimum_balance_for_rent_exemption(size_of::<StakeState>())
+        .map_err(Error::fetch)?;
+    if lamports < minimum {
+        return Err(Error::BelowRentExemption { lamports, minimum });
+    }
+    Ok(())
+}
+
+fn process_new_stake_account<C: Client>(
+    client: &C,
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
     new_config: &NewCommandConfig,
-) -> Result<String, Box<dyn Error>> {
+    receipt_path: Option<&str>,
+) -> Result<Option<String>, Error> {
     let matches = ArgMatches::default();
     let fee_payer_keypair = resolve_fee_payer(wallet_manager, &new_config.fee_payer)?;
     let sender_keypair = signer_from_path(
@@ -103,25 +598,31 @@ fn process_new_stake_account(
         &new_config.sender_keypair,
         "sender keypair",
         wallet_manager,
-    )?;
+    )
+    .map_err(|err| Error::resolve("sender keypair", err))?;
     let base_keypair = signer_from_path(
         &matches,
         &new_config.base_keypair,
         "base keypair",
         wallet_manager,
-    )?;
+    )
+    .map_err(|err| Error::resolve("base keypair", err))?;
     let stake_authority_pubkey = pubkey_from_path(
         &matches,
         &new_config.stake_authority,
         "stake authority",
         wallet_manager,
-    )?;
+    )
+    .map_err(|err| Error::resolve("stake authority", err))?;
     let withdraw_authority_pubkey = pubkey_from_path(
         &matches,
         &new_config.withdraw_authority,
         "withdraw authority",
         wallet_manager,
-    )?;
+    )
+    .map_err(|err| Error::resolve("withdraw authority", err))?;
+    let stake_account_address =
+        stake_accounts::derive_stake_account_address(&base_keypair.pubkey(), 0)?;
     let message = stake_accounts::new_stake_account(
         &fee_payer_keypair.pubkey(),
         &sender_keypair.pubkey(),
@@ -129,173 +630,3121 @@ fn process_new_stake_account(
         new_config.lamports,
         &stake_authority_pubkey,
         &withdraw_authority_pubkey,
-    );
+    )?;
+    check_rent_exemption(client, new_config.lamports)?;
+    check_payer_balances(
+        client,
+        slice::from_ref(&message),
+        &fee_payer_keypair.pubkey(),
+        Some((&sender_keypair.pubkey(), new_config.lamports)),
+    )?;
     let signers = vec![&*fee_payer_keypair, &*sender_keypair, &*base_keypair];
-    let signature = send_message(client, message, &signers)?;
+    let applied = Applied::Funded {
+        address: stake_account_address,
+        lamports: new_config.lamports,
+    };
+    let signature = send_message(
+        client,
+        0,
+        &stake_account_address,
+        &message,
+        &signers,
+        Some(applied),
+    )?;
+    if let Some(receipt_path) = receipt_path {
+        let mut receipt = ReceiptFile::open(receipt_path)?;
+        receipt.record(
+            client,
+            0,
+            &stake_account_address,
+            &message,
+            Ok(signature.clone()),
+        )?;
+    }
     Ok(signature)
 }
 
-fn process_authorize_stake_accounts(
-    client: &RpcClient,
+fn process_distribute<C: Client>(
+    client: &C,
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
-    authorize_config: &AuthorizeCommandConfig,
-) -> Result<(), Box<dyn Error>> {
-    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &authorize_config.fee_payer)?;
-    let base_pubkey = resolve_base_pubkey(wallet_manager, &authorize_config.base_pubkey)?;
-    let stake_authority_keypair =
-        resolve_stake_authority(wallet_manager, &authorize_config.stake_authority)?;
-    let withdraw_authority_keypair =
-        resolve_withdraw_authority(wallet_manager, &authorize_config.withdraw_authority)?;
-    let new_stake_authority_pubkey =
-        resolve_new_stake_authority(wallet_manager, &authorize_config.new_stake_authority)?;
-    let new_withdraw_authority_pubkey =
-        resolve_new_withdraw_authority(wallet_manager, &authorize_config.new_withdraw_authority)?;
-    let messages = stake_accounts::authorize_stake_accounts(
+    distribute_config: &DistributeCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let matches = ArgMatches::default();
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &distribute_config.fee_payer)?;
+    let sender_keypair = signer_from_path(
+        &matches,
+        &distribute_config.sender_keypair,
+        "sender keypair",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("sender keypair", err))?;
+    let base_keypair = signer_from_path(
+        &matches,
+        &distribute_config.base_keypair,
+        "base keypair",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("base keypair", err))?;
+    let base_pubkey = base_keypair.pubkey();
+
+    let allocations = read_allocations_file(&distribute_config.allocations_path)?;
+    let db_path = &distribute_config.db_path;
+    let mut db: DistributionDb = load_state(db_path)?;
+    if db.base_pubkey.is_empty() {
+        db.base_pubkey = base_pubkey.to_string();
+    } else if db.base_pubkey != base_pubkey.to_string() {
+        return Err(Error::Config(format!(
+            "{}: distribution was sent from base {}, not {}",
+            db_path, db.base_pubkey, base_pubkey
+        )));
+    }
+
+    let unsent = unsent_allocations(&allocations, &db)?;
+    let addresses = unsent
+        .iter()
+        .map(|(index, _)| stake_accounts::derive_stake_account_address(&base_pubkey, *index))
+        .collect::<Result<Vec<_>, _>>()?;
+    let accounts = get_accounts(client, &addresses)?;
+    let mut pending = vec![];
+    for (((index, allocation), address), account) in unsent.into_iter().zip(addresses).zip(accounts)
+    {
+        let record = SentRecord {
+            index,
+            recipient: allocation.recipient.to_string(),
+            lamports: allocation.lamports,
+            address: address.to_string(),
+            signature: None,
+        };
+        // An existing account was created by a run that stopped before recording
+        // it, so record it rather than paying the recipient again.
+        if let Some(account) = account {
+            let staker = StakeState::authorized_from(&account).map(|authorized| authorized.staker);
+            if staker != Some(allocation.recipient) {
+                let reason = "account exists but is not staked by the recipient";
+                return Err(Error::account(index, &address, Phase::Plan, reason));
+            }
+            db.sent.push(record);
+            continue;
+        }
+        let authorized = Authorized {
+            staker: allocation.recipient,
+            withdrawer: allocation.recipient,
+        };
+        let message = stake_accounts::new_derived_stake_account(
+            &fee_payer_keypair.pubkey(),
+            &sender_keypair.pubkey(),
+            &base_pubkey,
+            index,
+            allocation.lamports,
+            &authorized,
+            &allocation.lockup,
+        )?;
+        pending.push((record, address, message));
+    }
+    save_state(db_path, &db)?;
+
+    if let Some(lamports) = pending.iter().map(|(record, ..)| record.lamports).min() {
+        check_rent_exemption(client, lamports)?;
+    }
+    let messages: Vec<_> = pending
+        .iter()
+        .map(|(.., message)| message.clone())
+        .collect();
+    let total_lamports: u64 = pending.iter().map(|(record, ..)| record.lamports).sum();
+    check_payer_balances(
+        client,
+        &messages,
         &fee_payer_keypair.pubkey(),
-        &base_pubkey,
-        &stake_authority_keypair.pubkey(),
-        &withdraw_authority_keypair.pubkey(),
-        &new_stake_authority_pubkey,
-        &new_withdraw_authority_pubkey,
-        authorize_config.num_accounts,
-    );
-    let signers = vec![
-        &*fee_payer_keypair,
-        &*stake_authority_keypair,
-        &*withdraw_authority_keypair,
-    ];
-    for message in messages {
-        let signature = send_message(client, message, &signers)?;
-        println!("{}", signature);
+        Some((&sender_keypair.pubkey(), total_lamports)),
+    )?;
+
+    let signers = vec![&*fee_payer_keypair, &*sender_keypair, &*base_keypair];
+    let mut batch = Batch::new(client, pending.len(), receipt_path)?;
+    for (mut record, address, message) in pending {
+        let applied = Applied::Funded {
+            address,
+            lamports: record.lamports,
+        };
+        let result = send_message(
+            client,
+            record.index,
+            &address,
+            &message,
+            &signers,
+            Some(applied),
+        );
+        let signature = batch.record(record.index, &address, &message, record.lamports, result)?;
+        batch.println(&format!(
+            "{:<5} {:<44} {:>14} SOL {}",
+            record.index,
+            record.recipient,
+            lamports_to_sol(record.lamports),
+            format_signature(&signature)
+        ));
+        record.signature = signature;
+        db.sent.push(record);
+        save_state(db_path, &db)?;
     }
+    batch.finish();
     Ok(())
 }
 
-fn process_rebase_stake_accounts(
-    client: &RpcClient,
+fn process_vest<C: Client>(
+    client: &C,
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
-    rebase_config: &RebaseCommandConfig,
-) -> Result<(), Box<dyn Error>> {
-    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &rebase_config.fee_payer)?;
-    let base_pubkey = resolve_base_pubkey(wallet_manager, &rebase_config.base_pubkey)?;
-    let stake_authority_keypair =
-        resolve_stake_authority(wallet_manager, &rebase_config.stake_authority)?;
-    let pubkeys =
-        stake_accounts::derive_stake_account_addresses(&base_pubkey, rebase_config.num_accounts);
-    let balances = get_balances(&client, pubkeys)?;
+    vest_config: &VestCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let matches = ArgMatches::default();
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &vest_config.fee_payer)?;
+    let sender_keypair = signer_from_path(
+        &matches,
+        &vest_config.sender_keypair,
+        "sender keypair",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("sender keypair", err))?;
+    let base_keypair = signer_from_path(
+        &matches,
+        &vest_config.base_keypair,
+        "base keypair",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("base keypair", err))?;
+    let base_pubkey = base_keypair.pubkey();
+    let stake_authority_pubkey = pubkey_from_path(
+        &matches,
+        &vest_config.stake_authority,
+        "stake authority",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("stake authority", err))?;
+    let withdraw_authority_pubkey = pubkey_from_path(
+        &matches,
+        &vest_config.withdraw_authority,
+        "withdraw authority",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("withdraw authority", err))?;
+    let custodian_pubkey =
+        resolve_optional_pubkey(wallet_manager, &vest_config.custodian, "custodian")?
+            .unwrap_or_default();
+    let authorized = Authorized {
+        staker: stake_authority_pubkey,
+        withdrawer: withdraw_authority_pubkey,
+    };
 
-    let messages = stake_accounts::rebase_stake_accounts(
+    let schedule = vesting_schedule(
+        vest_config.lamports,
+        vest_config.start,
+        vest_config.period,
+        vest_config.num_tranches,
+        vest_config.cliff,
+    )
+    .map_err(Error::Config)?;
+    let indices: Vec<_> = (0..schedule.len()).collect();
+    let addresses = stake_accounts::derive_stake_account_addresses(&base_pubkey, &indices)?;
+    let accounts = get_accounts(client, &addresses)?;
+    let mut pending = vec![];
+    for (i, ((tranche, address), account)) in
+        schedule.iter().zip(&addresses).zip(accounts).enumerate()
+    {
+        let lockup = Lockup {
+            unix_timestamp: tranche.unix_timestamp,
+            epoch: 0,
+            custodian: custodian_pubkey,
+        };
+        // Tranches created by an earlier, interrupted run are skipped.
+        if let Some(account) = account {
+            if get_lockup(&account) != Some(lockup) {
+                let reason = "account exists with a different lockup";
+                return Err(Error::account(i, address, Phase::Plan, reason));
+            }
+            continue;
+        }
+        let message = stake_accounts::new_derived_stake_account(
+            &fee_payer_keypair.pubkey(),
+            &sender_keypair.pubkey(),
+            &base_pubkey,
+            i,
+            tranche.lamports,
+            &authorized,
+            &lockup,
+        )?;
+        pending.push((i, address, tranche, message));
+    }
+
+    if let Some(lamports) = pending
+        .iter()
+        .map(|(_, _, tranche, _)| tranche.lamports)
+        .min()
+    {
+        check_rent_exemption(client, lamports)?;
+    }
+    let messages: Vec<_> = pending
+        .iter()
+        .map(|(.., message)| message.clone())
+        .collect();
+    let total_lamports: u64 = pending
+        .iter()
+        .map(|(_, _, tranche, _)| tranche.lamports)
+        .sum();
+    check_payer_balances(
+        client,
+        &messages,
         &fee_payer_keypair.pubkey(),
-        &base_pubkey,
-        &stake_authority_keypair.pubkey(),
-        &balances,
-    );
-    let signers = vec![&*fee_payer_keypair, &*stake_authority_keypair];
-    for message in messages {
-        let signature = send_message(client, message, &signers)?;
-        println!("{}", signature);
+        Some((&sender_keypair.pubkey(), total_lamports)),
+    )?;
+
+    let signers = vec![&*fee_payer_keypair, &*sender_keypair, &*base_keypair];
+    let mut batch = Batch::new(client, pending.len(), receipt_path)?;
+    for (i, address, tranche, message) in pending {
+        let applied = Applied::Funded {
+            address: *address,
+            lamports: tranche.lamports,
+        };
+        let result = send_message(client, i, address, &message, &signers, Some(applied));
+        let signature = batch.record(i, address, &message, tranche.lamports, result)?;
+        batch.println(&format!(
+            "{:<5} {:<44} {:>14} SOL unlocks {} {}",
+            i,
+            address,
+            lamports_to_sol(tranche.lamports),
+            format_unix_timestamp(tranche.unix_timestamp, DATE_TIME_FORMAT),
+            format_signature(&signature)
+        ));
     }
+    batch.finish();
     Ok(())
 }
 
-fn process_move_stake_accounts(
-    client: &RpcClient,
+fn process_clawback<C: Client>(
+    client: &C,
     wallet_manager: Option<&Arc<RemoteWalletManager>>,
-    move_config: &MoveCommandConfig,
-) -> Result<(), Box<dyn Error>> {
-    let authorize_config = &move_config.authorize_config;
-    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &authorize_config.fee_payer)?;
-    let base_pubkey = resolve_base_pubkey(wallet_manager, &authorize_config.base_pubkey)?;
-    let stake_authority_keypair =
-        resolve_stake_authority(wallet_manager, &authorize_config.stake_authority)?;
+    clawback_config: &ClawbackCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let matches = ArgMatches::default();
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &clawback_config.fee_payer)?;
+    let base_pubkey = resolve_base_pubkey(wallet_manager, &clawback_config.base_pubkey)?;
     let withdraw_authority_keypair =
-        resolve_withdraw_authority(wallet_manager, &authorize_config.withdraw_authority)?;
-    let new_stake_authority_pubkey =
-        resolve_new_stake_authority(wallet_manager, &authorize_config.new_stake_authority)?;
-    let new_withdraw_authority_pubkey =
-        resolve_new_withdraw_authority(wallet_manager, &authorize_config.new_withdraw_authority)?;
-    let pubkeys =
-        stake_accounts::derive_stake_account_addresses(&base_pubkey, authorize_config.num_accounts);
-    let balances = get_balances(&client, pubkeys)?;
+        resolve_withdraw_authority(wallet_manager, &clawback_config.withdraw_authority)?;
+    let custodian_keypair = signer_from_path(
+        &matches,
+        &clawback_config.custodian,
+        "custodian",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("custodian", err))?;
+    let treasury_pubkey = pubkey_from_path(
+        &matches,
+        &clawback_config.treasury,
+        "treasury",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("treasury", err))?;
 
-    let messages = stake_accounts::move_stake_accounts(
-        &fee_payer_keypair.pubkey(),
-        &base_pubkey,
-        &stake_authority_keypair.pubkey(),
-        &withdraw_authority_keypair.pubkey(),
-        &new_stake_authority_pubkey,
-        &new_withdraw_authority_pubkey,
-        &balances,
+    let clock = get_clock(client)?;
+    let (epoch, stake_history) = get_stake_history(client)?;
+    let indices = &clawback_config.indices;
+    let addresses = stake_accounts::derive_stake_account_addresses(&base_pubkey, indices)?;
+    let accounts = get_indexed_accounts(client, indices, &addresses)?;
+    let plans: Vec<_> = accounts
+        .iter()
+        .map(|account| plan_clawback(account.as_ref(), &clock, epoch, &stake_history))
+        .collect();
+    let messages: Vec<_> = plans
+        .iter()
+        .zip(&addresses)
+        .filter_map(|((disposition, _), address)| match disposition {
+            Disposition::Reclaim { lamports } => Some(stake_accounts::withdraw_stake_account(
+                &fee_payer_keypair.pubkey(),
+                address,
+                &withdraw_authority_keypair.pubkey(),
+                Some(&custodian_keypair.pubkey()),
+                &treasury_pubkey,
+                *lamports,
+            )),
+            _ => None,
+        })
+        .collect();
+    check_payer_balances(client, &messages, &fee_payer_keypair.pubkey(), None)?;
+
+    println!(
+        "{:<5} {:<44} {:>14} {:<19} ACTION",
+        "INDEX", "ADDRESS", "SOL", "LOCKED UNTIL"
     );
     let signers = vec![
         &*fee_payer_keypair,
-        &*stake_authority_keypair,
         &*withdraw_authority_keypair,
+        &*custodian_keypair,
     ];
-    for message in messages {
-        let signature = send_message(client, message, &signers)?;
-        println!("{}", signature);
+    let mut batch = Batch::new(client, messages.len(), receipt_path)?;
+    let mut messages = messages.into_iter();
+    let mut total_reclaimed = 0;
+    for ((&i, (disposition, unix_timestamp)), (address, account)) in indices
+        .iter()
+        .zip(&plans)
+        .zip(addresses.iter().zip(&accounts))
+    {
+        let lamports = account
+            .as_ref()
+            .map(|account| account.lamports)
+            .unwrap_or(0);
+        let locked_until = unix_timestamp
+            .map(|unix_timestamp| format_unix_timestamp(unix_timestamp, DATE_TIME_FORMAT))
+            .unwrap_or_default();
+        let action = match disposition {
+            Disposition::Reclaim { lamports } => {
+                let message = messages.next().unwrap();
+                let applied = Applied::Withdrawn { address: *address };
+                let result = send_message(client, i, address, &message, &signers, Some(applied));
+                let signature = batch.record(i, address, &message, *lamports, result)?;
+                total_reclaimed += lamports;
+                format!("reclaimed {}", format_signature(&signature))
+            }
+            disposition => disposition.to_string(),
+        };
+        batch.println(&format!(
+            "{:<5} {:<44} {:>14} {:<19} {}",
+            i,
+            address,
+            lamports_to_sol(lamports),
+            locked_until,
+            action
+        ));
     }
+    batch.finish();
+    println!(
+        "Reclaimed {} SOL to {}",
+        lamports_to_sol(total_reclaimed),
+        treasury_pubkey
+    );
     Ok(())
 }
 
-fn send_message<S: Signers>(
-    client: &RpcClient,
-    message: Message,
-    signers: &S,
-) -> Result<String, ClientError> {
-    let mut transaction = Transaction::new_unsigned(message);
-    client.resign_transaction(&mut transaction, signers)?;
-    client.send_and_confirm_transaction_with_spinner(&mut transaction, signers)
+fn print_delegation_plan(targets: &[Target], plan: &DelegationPlan) {
+    let total_weight: f64 = targets.iter().map(|target| target.weight).sum();
+    println!(
+        "{:<44} {:>8} {:>14} {:>8} {:>14}",
+        "VOTE ACCOUNT", "TARGET", "TARGET SOL", "ACHIEVED", "ACHIEVED SOL"
+    );
+    for ((target, goal), achieved) in targets.iter().zip(&plan.goals).zip(&plan.achieved) {
+        let achieved_percent = if plan.total_lamports == 0 {
+            0.0
+        } else {
+            *achieved as f64 * 100.0 / plan.total_lamports as f64
+        };
+        println!(
+            "{:<44} {:>7.2}% {:>14} {:>7.2}% {:>14}",
+            target.vote_pubkey,
+            target.weight * 100.0 / total_weight,
+            lamports_to_sol(*goal),
+            achieved_percent,
+            lamports_to_sol(*achieved)
+        );
+    }
+    for action in &plan.actions {
+        match action {
+            DelegateAction::Delegate { index, vote_pubkey } => {
+                println!("Delegate account {} to {}", index, vote_pubkey)
+            }
+            DelegateAction::SplitAndDelegate {
+                index,
+                new_index,
+                lamports,
+                vote_pubkey,
+            } => println!(
+                "Split {} SOL from account {} into account {} and delegate it to {}",
+                lamports_to_sol(*lamports),
+                index,
+                new_index,
+                vote_pubkey
+            ),
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let command_config = parse_args(env::args_os());
-    let config = Config::load(&command_config.config_file)?;
-    let json_rpc_url = command_config.url.unwrap_or(config.json_rpc_url);
-    let client = RpcClient::new(json_rpc_url);
+fn process_delegate<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    delegate_config: &DelegateCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &delegate_config.fee_payer)?;
+    let base_keypair = signer_from_path(
+        &ArgMatches::default(),
+        &delegate_config.base_keypair,
+        "base keypair",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("base keypair", err))?;
+    let base_pubkey = base_keypair.pubkey();
+    let stake_authority_keypair =
+        resolve_stake_authority(wallet_manager, &delegate_config.stake_authority)?;
+    let targets = read_targets_file(&delegate_config.weights_path)?;
 
-    let wallet_manager = maybe_wallet_manager()?;
-    let wallet_manager = wallet_manager.as_ref();
-    match command_config.command {
-        Command::New(new_config) => {
-            process_new_stake_account(&client, wallet_manager, &new_config)?;
-        }
-        Command::Count(count_config) => {
-            let base_pubkey = resolve_base_pubkey(wallet_manager, &count_config.base_pubkey)?;
-            let num_accounts = count_stake_accounts(&client, &base_pubkey)?;
-            println!("{}", num_accounts);
-        }
-        Command::Pubkeys(query_config) => {
-            let base_pubkey = resolve_base_pubkey(wallet_manager, &query_config.base_pubkey)?;
-            let pubkeys = stake_accounts::derive_stake_account_addresses(
+    let indices = &delegate_config.indices;
+    let addresses = stake_accounts::derive_stake_account_addresses(&base_pubkey, indices)?;
+    let accounts = get_indexed_accounts(client, indices, &addresses)?;
+    let infos: Vec<_> = indices
+        .iter()
+        .zip(&accounts)
+        .filter_map(|(&index, account)| {
+            let account = account.as_ref()?;
+            let voter_pubkey = match StakeState::from(account)? {
+                StakeState::Initialized(_) => None,
+                StakeState::Stake(_, stake) => Some(stake.delegation.voter_pubkey),
+                _ => return None,
+            };
+            Some(StakeAccountInfo {
+                index,
+                lamports: account.lamports,
+                voter_pubkey,
+            })
+        })
+        .collect();
+    let addresses: HashMap<_, _> = indices.iter().cloned().zip(addresses).collect();
+    let next_index = count_stake_accounts(client, &base_pubkey)?.max(end_index(indices));
+    let min_lamports = client
+        .get_minimum_balance_for_rent_exemption(size_of::<StakeState>())
+        .map_err(Error::fetch)?;
+    let plan = plan_delegation(&infos, &targets, next_index, min_lamports);
+    print_delegation_plan(&targets, &plan);
+
+    let fee_payer_pubkey = fee_payer_keypair.pubkey();
+    let stake_authority_pubkey = stake_authority_keypair.pubkey();
+    let messages = plan
+        .actions
+        .iter()
+        .map(|action| match action {
+            DelegateAction::Delegate { index, vote_pubkey } => {
+                Ok(stake_accounts::delegate_stake_account(
+                    &fee_payer_pubkey,
+                    &addresses[index],
+                    &stake_authority_pubkey,
+                    vote_pubkey,
+                ))
+            }
+            DelegateAction::SplitAndDelegate {
+                index,
+                new_index,
+                lamports,
+                vote_pubkey,
+            } => stake_accounts::split_and_delegate_stake_account(
+                &fee_payer_pubkey,
+                &addresses[index],
                 &base_pubkey,
-                query_config.num_accounts,
-            );
-            for pubkey in pubkeys {
-                println!("{:?}", pubkey);
+                *new_index,
+                &stake_authority_pubkey,
+                *lamports,
+                vote_pubkey,
+            ),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    check_payer_balances(client, &messages, &fee_payer_pubkey, None)?;
+
+    // Only splits create accounts, so only they need the base keypair's signature.
+    let delegate_signers = vec![&*fee_payer_keypair, &*stake_authority_keypair];
+    let split_signers = vec![
+        &*fee_payer_keypair,
+        &*stake_authority_keypair,
+        &*base_keypair,
+    ];
+    let mut batch = Batch::new(client, messages.len(), receipt_path)?;
+    for (action, message) in plan.actions.iter().zip(messages) {
+        match action {
+            DelegateAction::Delegate { index, vote_pubkey } => {
+                let applied = Applied::Delegated {
+                    address: addresses[index],
+                    vote_pubkey: *vote_pubkey,
+                };
+                let result = send_message(
+                    client,
+                    *index,
+                    &addresses[index],
+                    &message,
+                    &delegate_signers,
+                    Some(applied),
+                );
+                batch.record(*index, &addresses[index], &message, 0, result)?;
+            }
+            DelegateAction::SplitAndDelegate {
+                index,
+                new_index,
+                lamports,
+                vote_pubkey,
+            } => {
+                let applied = Applied::Delegated {
+                    address: stake_accounts::derive_stake_account_address(
+                        &base_pubkey,
+                        *new_index,
+                    )?,
+                    vote_pubkey: *vote_pubkey,
+                };
+                let result = send_message(
+                    client,
+                    *index,
+                    &addresses[index],
+                    &message,
+                    &split_signers,
+                    Some(applied),
+                );
+                batch.record(*index, &addresses[index], &message, *lamports, result)?;
             }
         }
-        Command::Balance(query_config) => {
-            let base_pubkey = resolve_base_pubkey(wallet_manager, &query_config.base_pubkey)?;
-            let pubkeys = stake_accounts::derive_stake_account_addresses(
-                &base_pubkey,
-                query_config.num_accounts,
+    }
+    batch.finish();
+    Ok(())
+}
+
+fn rebalance_message(
+    action: &RebalanceAction,
+    fee_payer_pubkey: &Pubkey,
+    base_pubkey: &Pubkey,
+    stake_authority_pubkey: &Pubkey,
+) -> Result<(Message, Applied), Error> {
+    let address = stake_accounts::derive_stake_account_address(base_pubkey, action.index())?;
+    // The account a split creates is the one the action leaves deactivated or delegated.
+    let applied_address = match action.new_index() {
+        Some(new_index) => stake_accounts::derive_stake_account_address(base_pubkey, new_index)?,
+        None => address,
+    };
+    let parse_vote_pubkey = |vote_pubkey: &str| {
+        Pubkey::from_str(vote_pubkey).map_err(|err| {
+            Error::Config(format!(
+                "invalid vote account {} in state file: {:?}",
+                vote_pubkey, err
+            ))
+        })
+    };
+    match action {
+        RebalanceAction::Deactivate { .. } => {
+            let message = stake_accounts::deactivate_stake_account(
+                fee_payer_pubkey,
+                &address,
+                stake_authority_pubkey,
             );
-            let balances = get_balances(&client, pubkeys)?;
-            let lamports: u64 = balances.into_iter().map(|(_, bal)| bal).sum();
-            let sol = lamports_to_sol(lamports);
-            println!("{} SOL", sol);
+            let applied = Applied::Deactivated {
+                address: applied_address,
+            };
+            Ok((message, applied))
         }
-        Command::Authorize(authorize_config) => {
-            process_authorize_stake_accounts(&client, wallet_manager, &authorize_config)?;
+        RebalanceAction::SplitAndDeactivate {
+            new_index,
+            lamports,
+            ..
+        } => {
+            let message = stake_accounts::split_and_deactivate_stake_account(
+                fee_payer_pubkey,
+                &address,
+                base_pubkey,
+                *new_index,
+                stake_authority_pubkey,
+                *lamports,
+            )?;
+            let applied = Applied::Deactivated {
+                address: applied_address,
+            };
+            Ok((message, applied))
         }
-        Command::Rebase(rebase_config) => {
-            process_rebase_stake_accounts(&client, wallet_manager, &rebase_config)?;
+        RebalanceAction::Delegate { vote_pubkey, .. } => {
+            let vote_pubkey = parse_vote_pubkey(vote_pubkey)?;
+            let message = stake_accounts::delegate_stake_account(
+                fee_payer_pubkey,
+                &address,
+                stake_authority_pubkey,
+                &vote_pubkey,
+            );
+            let applied = Applied::Delegated {
+                address: applied_address,
+                vote_pubkey,
+            };
+            Ok((message, applied))
         }
-        Command::Move(move_config) => {
-            process_move_stake_accounts(&client, wallet_manager, &move_config)?;
+        RebalanceAction::SplitAndDelegate {
+            new_index,
+            lamports,
+            vote_pubkey,
+            ..
+        } => {
+            let vote_pubkey = parse_vote_pubkey(vote_pubkey)?;
+            let message = stake_accounts::split_and_delegate_stake_account(
+                fee_payer_pubkey,
+                &address,
+                base_pubkey,
+                *new_index,
+                stake_authority_pubkey,
+                *lamports,
+                &vote_pubkey,
+            )?;
+            let applied = Applied::Delegated {
+                address: applied_address,
+                vote_pubkey,
+            };
+            Ok((message, applied))
+        }
+    }
+}
+
+// Send the steps not yet sent, saving the state after each so that a rerun
+// doesn't repeat them. Returns the number sent.
+fn send_rebalance_steps<C: Client>(
+    client: &C,
+    state: &mut RebalanceState,
+    state_path: &str,
+    redelegations: bool,
+    fee_payer_keypair: &dyn Signer,
+    base_keypair: &dyn Signer,
+    stake_authority_keypair: &dyn Signer,
+    receipt_path: Option<&str>,
+) -> Result<usize, Error> {
+    let fee_payer_pubkey = fee_payer_keypair.pubkey();
+    let base_pubkey = base_keypair.pubkey();
+    let stake_authority_pubkey = stake_authority_keypair.pubkey();
+    let steps = if redelegations {
+        &state.redelegations
+    } else {
+        &state.deactivations
+    };
+    let unsent: Vec<_> = steps
+        .iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, step)| !step.is_done())
+        .map(|(position, step)| {
+            let (message, applied) = rebalance_message(
+                &step.action,
+                &fee_payer_pubkey,
+                &base_pubkey,
+                &stake_authority_pubkey,
+            )?;
+            Ok((position, step.action.clone(), message, applied))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let messages: Vec<_> = unsent
+        .iter()
+        .map(|(_, _, message, _)| message.clone())
+        .collect();
+    check_payer_balances(client, &messages, &fee_payer_pubkey, None)?;
+
+    // Only splits create accounts, so only they need the base keypair's signature.
+    let signers = vec![fee_payer_keypair, stake_authority_keypair];
+    let split_signers = vec![fee_payer_keypair, stake_authority_keypair, base_keypair];
+    let num_sent = unsent.len();
+    let mut batch = Batch::new(client, num_sent, receipt_path)?;
+    for (position, action, message, applied) in unsent {
+        let index = action.index();
+        let address = stake_accounts::derive_stake_account_address(&base_pubkey, index)?;
+        let signers = if action.new_index().is_some() {
+            &split_signers
+        } else {
+            &signers
+        };
+        let result = send_message(client, index, &address, &message, signers, Some(applied));
+        let signature = batch.record(index, &address, &message, action.split_lamports(), result)?;
+        batch.println(format_signature(&signature));
+        let steps = if redelegations {
+            &mut state.redelegations
+        } else {
+            &mut state.deactivations
+        };
+        if let Some(steps) = steps {
+            steps[position].applied = signature.is_none();
+            steps[position].signature = signature;
+        }
+        save_state(state_path, state)?;
+    }
+    batch.finish();
+    Ok(num_sent)
+}
+
+fn process_rebalance<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    rebalance_config: &RebalanceCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let delegate_config = &rebalance_config.delegate_config;
+    let state_path = &rebalance_config.state_path;
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &delegate_config.fee_payer)?;
+    let base_keypair = signer_from_path(
+        &ArgMatches::default(),
+        &delegate_config.base_keypair,
+        "base keypair",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("base keypair", err))?;
+    let base_pubkey = base_keypair.pubkey();
+    let stake_authority_keypair =
+        resolve_stake_authority(wallet_manager, &delegate_config.stake_authority)?;
+    let targets = read_targets_file(&delegate_config.weights_path)?;
+
+    let mut state: RebalanceState = load_state(state_path)?;
+    if state.base_pubkey.is_empty() {
+        state.base_pubkey = base_pubkey.to_string();
+    } else if state.base_pubkey != base_pubkey.to_string() {
+        return Err(Error::Config(format!(
+            "{} records a rebalance of base pubkey {}, not {}",
+            state_path, state.base_pubkey, base_pubkey
+        )));
+    }
+
+    // Accounts split off by earlier runs are rebalanced too, though not selected.
+    let mut indices = delegate_config.indices.clone();
+    indices.extend(
+        state
+            .deactivations
+            .iter()
+            .chain(&state.redelegations)
+            .flatten()
+            .filter_map(|step| step.action.new_index()),
+    );
+    indices.sort();
+    indices.dedup();
+    let addresses = stake_accounts::derive_stake_account_addresses(&base_pubkey, &indices)?;
+    let accounts = get_indexed_accounts(client, &indices, &addresses)?;
+    let infos: Vec<_> = indices
+        .iter()
+        .zip(&accounts)
+        .filter_map(|(&index, account)| stake_account_info(index, account.as_ref()?))
+        .collect();
+    let next_index = count_stake_accounts(client, &base_pubkey)?.max(end_index(&indices));
+    let min_lamports = client
+        .get_minimum_balance_for_rent_exemption(size_of::<StakeState>())
+        .map_err(Error::fetch)?;
+
+    if state.deactivations.is_none() {
+        let actions = plan_deactivations(&infos, &targets, next_index, min_lamports);
+        for action in &actions {
+            println!("{}", action);
+        }
+        state.deactivations = Some(actions.into_iter().map(RebalanceStep::from).collect());
+        save_state(state_path, &state)?;
+    }
+    let num_deactivated = send_rebalance_steps(
+        client,
+        &mut state,
+        state_path,
+        false,
+        &*fee_payer_keypair,
+        &*base_keypair,
+        &*stake_authority_keypair,
+        receipt_path,
+    )?;
+
+    if state.redelegations.is_none() {
+        if num_deactivated > 0 {
+            println!("Run rebalance again once the deactivated stake is inactive");
+            return Ok(());
+        }
+        let (epoch, stake_history) = get_stake_history(client)?;
+        let cooling_lamports: u64 = accounts
+            .iter()
+            .flatten()
+            .filter(|account| is_cooling_down(account, epoch, &stake_history))
+            .map(|account| account.lamports)
+            .sum();
+        if cooling_lamports > 0 {
+            println!(
+                "{} SOL is still cooling down in epoch {}, run rebalance again in a later epoch",
+                lamports_to_sol(cooling_lamports),
+                epoch
+            );
+            return Ok(());
+        }
+        let plan = plan_delegation(&infos, &targets, next_index, min_lamports);
+        print_delegation_plan(&targets, &plan);
+        state.redelegations = Some(
+            plan.actions
+                .iter()
+                .map(|action| RebalanceStep::from(RebalanceAction::from(action)))
+                .collect(),
+        );
+        save_state(state_path, &state)?;
+    }
+    send_rebalance_steps(
+        client,
+        &mut state,
+        state_path,
+        true,
+        &*fee_payer_keypair,
+        &*base_keypair,
+        &*stake_authority_keypair,
+        receipt_path,
+    )?;
+    println!("Rebalance complete");
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn send_step<C: Client>(
+    client: &C,
+    step: &Step,
+    address: &Pubkey,
+    account: Option<&Account>,
+    fee_payer_keypair: &dyn Signer,
+    stake_authority_keypair: Option<&dyn Signer>,
+    withdraw_authority_keypair: Option<&dyn Signer>,
+    receipt: &mut Option<ReceiptFile>,
+    progress: &mut PlanProgress,
+    state_path: &str,
+    slot: Slot,
+) -> Result<Option<String>, Error> {
+    let fee_payer_pubkey = fee_payer_keypair.pubkey();
+    let stake_authority = || {
+        stake_authority_keypair
+            .ok_or_else(|| Error::Config("a stake authority is required".to_string()))
+    };
+    let address = *address;
+    let (message, authority_keypair, applied) = match &step.action {
+        StepAction::Deactivate => {
+            let stake_authority_keypair = stake_authority()?;
+            let message = stake_accounts::deactivate_stake_account(
+                &fee_payer_pubkey,
+                &address,
+                &stake_authority_keypair.pubkey(),
+            );
+            let applied = Applied::Deactivated { address };
+            (message, stake_authority_keypair, Some(applied))
+        }
+        StepAction::Delegate { vote_pubkey } => {
+            let stake_authority_keypair = stake_authority()?;
+            let message = stake_accounts::delegate_stake_account(
+                &fee_payer_pubkey,
+                &address,
+                &stake_authority_keypair.pubkey(),
+                vote_pubkey,
+            );
+            let applied = Applied::Delegated {
+                address,
+                vote_pubkey: *vote_pubkey,
+            };
+            (message, stake_authority_keypair, Some(applied))
+        }
+        StepAction::Withdraw {
+            recipient,
+            lamports,
+        } => {
+            let withdraw_authority_keypair = withdraw_authority_keypair
+                .ok_or_else(|| Error::Config("a withdraw authority is required".to_string()))?;
+            // Only a withdrawal of the whole balance can be recognized once applied.
+            let applied = match lamports {
+                Some(_) => None,
+                None => Some(Applied::Withdrawn { address }),
+            };
+            let lamports = lamports
+                .or_else(|| account.map(|account| account.lamports))
+                .unwrap_or(0);
+            let message = stake_accounts::withdraw_stake_account(
+                &fee_payer_pubkey,
+                &address,
+                &withdraw_authority_keypair.pubkey(),
+                None,
+                recipient,
+                lamports,
+            );
+            (message, withdraw_authority_keypair, applied)
+        }
+    };
+    check_payer_balances(client, slice::from_ref(&message), &fee_payer_pubkey, None)?;
+    let signers = vec![fee_payer_keypair, authority_keypair];
+    let signature = match applied {
+        Some(applied) => send_message(
+            client,
+            step.index,
+            &address,
+            &message,
+            &signers,
+            Some(applied),
+        )?,
+        None => {
+            // Nothing in the account shows whether the transaction landed, so
+            // save its signature before confirming, for a restart to check.
+            let (blockhash, _) = client.get_recent_blockhash().map_err(Error::fetch)?;
+            let mut transaction = Transaction::new_unsigned(message.clone());
+            transaction
+                .try_sign(&signers, blockhash)
+                .map_err(|err| Error::account(step.index, &address, Phase::Sign, err))?;
+            let signature = transaction.signatures[0].to_string();
+            progress.sent.push(SentStep {
+                name: step.name.clone(),
+                signature: signature.clone(),
+                slot,
+            });
+            save_state(state_path, progress)?;
+            client
+                .send_and_confirm_transaction(transaction)
+                .map_err(|err| Error::send(step.index, &address, &err, &message))?;
+            Some(signature)
+        }
+    };
+    if let Some(receipt) = receipt {
+        receipt.record(
+            client,
+            step.index,
+            &address,
+            &message,
+            Ok(signature.clone()),
+        )?;
+    }
+    Ok(signature)
+}
+
+// Send each pending step whose preconditions hold, recording it as done.
+// Returns why each step still pending is waiting.
+#[allow(clippy::too_many_arguments)]
+fn poll_plan<C: Client>(
+    client: &C,
+    plan: &Plan,
+    progress: &mut PlanProgress,
+    state_path: &str,
+    fee_payer_keypair: &dyn Signer,
+    stake_authority_keypair: Option<&dyn Signer>,
+    withdraw_authority_keypair: Option<&dyn Signer>,
+    receipt: &mut Option<ReceiptFile>,
+) -> Result<Vec<(String, String)>, Error> {
+    let clock = get_clock(client)?;
+    let (_, stake_history) = get_stake_history(client)?;
+    let pending: Vec<_> = plan
+        .steps
+        .iter()
+        .filter(|step| !progress.is_done(&step.name))
+        .collect();
+    let addresses = pending
+        .iter()
+        .map(|step| stake_accounts::derive_stake_account_address(&plan.base_pubkey, step.index))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let accounts = get_accounts(client, &addresses)?;
+
+    let mut waiting = vec![];
+    for ((step, address), account) in pending.into_iter().zip(&addresses).zip(&accounts) {
+        if let Some(after) = step.after.iter().find(|after| !progress.is_done(after)) {
+            waiting.push((step.name.clone(), format!("waiting for step {}", after)));
+            continue;
+        }
+        if let Some(epoch) = step.epoch.filter(|epoch| *epoch > clock.epoch) {
+            waiting.push((step.name.clone(), format!("waiting for epoch {}", epoch)));
+            continue;
+        }
+        // A transaction sent before a restart may have landed, or may yet.
+        let mut landed = None;
+        if let Some(sent) = progress.sent_step(&step.name).cloned() {
+            let signature = Signature::from_str(&sent.signature).map_err(|err| {
+                Error::Config(format!("{}: invalid signature: {:?}", state_path, err))
+            })?;
+            match client
+                .get_signature_status(&signature, CommitmentConfig::default())
+                .map_err(Error::fetch)?
+            {
+                Some(Ok(())) => landed = Some(sent.signature),
+                None if clock.slot <= sent.slot + MAX_RECENT_BLOCKHASHES as Slot => {
+                    let reason = format!("waiting for transaction {}", sent.signature);
+                    waiting.push((step.name.clone(), reason));
+                    continue;
+                }
+                // It failed, or its blockhash expired before it landed.
+                _ => {}
+            }
+            progress.sent.retain(|sent| sent.name != step.name);
+        }
+        let signature = if landed.is_some() {
+            landed
+        } else {
+            match readiness(&step.action, account.as_ref(), &clock, &stake_history) {
+                Readiness::Waiting(reason) => {
+                    waiting.push((step.name.clone(), reason));
+                    continue;
+                }
+                Readiness::Satisfied => None,
+                Readiness::Ready => send_step(
+                    client,
+                    step,
+                    address,
+                    account.as_ref(),
+                    fee_payer_keypair,
+                    stake_authority_keypair,
+                    withdraw_authority_keypair,
+                    receipt,
+                    progress,
+                    state_path,
+                    clock.slot,
+                )?,
+            }
+        };
+        match &signature {
+            Some(signature) => println!("{}: {}", step.name, signature),
+            None => println!("{}: already done", step.name),
+        }
+        progress.sent.retain(|sent| sent.name != step.name);
+        progress.done.push(DoneStep {
+            name: step.name.clone(),
+            epoch: clock.epoch,
+            signature,
+        });
+        save_state(state_path, progress)?;
+    }
+    Ok(waiting)
+}
+
+fn process_run_plan<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    run_plan_config: &RunPlanCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let plan = read_plan_file(&run_plan_config.plan_path)?;
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &run_plan_config.fee_payer)?;
+    let stake_authority_keypair = match &run_plan_config.stake_authority {
+        Some(stake_authority) => Some(resolve_stake_authority(wallet_manager, stake_authority)?),
+        None => None,
+    };
+    let withdraw_authority_keypair = match &run_plan_config.withdraw_authority {
+        Some(withdraw_authority) => Some(resolve_withdraw_authority(
+            wallet_manager,
+            withdraw_authority,
+        )?),
+        None => None,
+    };
+    // Fail now rather than when the first step needing the authority comes due.
+    for step in &plan.steps {
+        let missing = match step.action {
+            StepAction::Withdraw { .. } if withdraw_authority_keypair.is_none() => {
+                Some("withdraw authority")
+            }
+            StepAction::Deactivate | StepAction::Delegate { .. }
+                if stake_authority_keypair.is_none() =>
+            {
+                Some("stake authority")
+            }
+            _ => None,
+        };
+        if let Some(missing) = missing {
+            return Err(Error::Config(format!(
+                "step {} requires a {}",
+                step.name, missing
+            )));
+        }
+    }
+
+    let state_path = &run_plan_config.state_path;
+    let mut progress: PlanProgress = load_state(state_path)?;
+    let mut receipt = receipt_path.map(ReceiptFile::open).transpose()?;
+    let mut last_reasons = HashMap::new();
+    loop {
+        match poll_plan(
+            client,
+            &plan,
+            &mut progress,
+            state_path,
+            &*fee_payer_keypair,
+            stake_authority_keypair.as_deref(),
+            withdraw_authority_keypair.as_deref(),
+            &mut receipt,
+        ) {
+            Ok(waiting) => {
+                if waiting.is_empty() && plan.steps.iter().all(|step| progress.is_done(&step.name))
+                {
+                    println!("Plan complete");
+                    return Ok(());
+                }
+                // Only report why a step is waiting when the reason changes.
+                for (name, reason) in waiting {
+                    if last_reasons.get(&name) != Some(&reason) {
+                        println!("{}: {}", name, reason);
+                        last_reasons.insert(name, reason);
+                    }
+                }
+            }
+            // Keep polling through transient RPC failures only.
+            Err(err) if err.is_transient() => eprintln!("error: {}", err),
+            Err(err) => return Err(err),
+        }
+        sleep(Duration::from_secs(run_plan_config.interval_secs));
+    }
+}
+
+fn process_authorize_stake_accounts<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    authorize_config: &AuthorizeCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &authorize_config.fee_payer)?;
+    let base_pubkeys = resolve_base_pubkeys(wallet_manager, &authorize_config.base_pubkeys)?;
+    let stake_authority_keypair =
+        resolve_stake_authority(wallet_manager, &authorize_config.stake_authority)?;
+    let withdraw_authority_keypair =
+        resolve_withdraw_authority(wallet_manager, &authorize_config.withdraw_authority)?;
+    let new_stake_authority_pubkey =
+        resolve_new_stake_authority(wallet_manager, &authorize_config.new_stake_authority)?;
+    let new_withdraw_authority_pubkey =
+        resolve_new_withdraw_authority(wallet_manager, &authorize_config.new_withdraw_authority)?;
+    let mut batches = vec![];
+    for base_pubkey in &base_pubkeys {
+        let messages = stake_accounts::authorize_stake_accounts(
+            &fee_payer_keypair.pubkey(),
+            base_pubkey,
+            &stake_authority_keypair.pubkey(),
+            &withdraw_authority_keypair.pubkey(),
+            &new_stake_authority_pubkey,
+            &new_withdraw_authority_pubkey,
+            &authorize_config.indices,
+        )?;
+        let addresses =
+            stake_accounts::derive_stake_account_addresses(base_pubkey, &authorize_config.indices)?;
+        batches.push((base_pubkey, messages, addresses));
+    }
+    let all_messages: Vec<_> = batches
+        .iter()
+        .flat_map(|(_, messages, _)| messages.iter().cloned())
+        .collect();
+    check_payer_balances(client, &all_messages, &fee_payer_keypair.pubkey(), None)?;
+
+    let signers = vec![
+        &*fee_payer_keypair,
+        &*stake_authority_keypair,
+        &*withdraw_authority_keypair,
+    ];
+    let mut batch = Batch::new(client, all_messages.len(), receipt_path)?;
+    for (base_pubkey, messages, addresses) in batches {
+        if base_pubkeys.len() > 1 {
+            batch.println(&format!("{}:", base_pubkey));
+        }
+        for ((&i, message), address) in authorize_config.indices.iter().zip(messages).zip(addresses)
+        {
+            let applied = Applied::Authorized {
+                address,
+                staker: new_stake_authority_pubkey,
+                withdrawer: new_withdraw_authority_pubkey,
+            };
+            let result = send_message(client, i, &address, &message, &signers, Some(applied));
+            let signature = batch.record(i, &address, &message, 0, result)?;
+            batch.println(format_signature(&signature));
+        }
+    }
+    batch.finish();
+    Ok(())
+}
+
+fn process_rebase_stake_accounts<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    rebase_config: &RebaseCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &rebase_config.fee_payer)?;
+    let base_pubkey = resolve_base_pubkey(wallet_manager, &rebase_config.base_pubkey)?;
+    let new_base_keypair =
+        resolve_new_base_keypair(wallet_manager, &rebase_config.new_base_keypair)?;
+    let stake_authority_keypair =
+        resolve_stake_authority(wallet_manager, &rebase_config.stake_authority)?;
+    let indices = &rebase_config.indices;
+    let pubkeys = stake_accounts::derive_stake_account_addresses(&base_pubkey, indices)?;
+    let balances = get_balances(&client, indices, pubkeys)?;
+
+    let messages = stake_accounts::rebase_stake_accounts(
+        &fee_payer_keypair.pubkey(),
+        &new_base_keypair.pubkey(),
+        &stake_authority_keypair.pubkey(),
+        indices,
+        &balances,
+    )?;
+    check_payer_balances(client, &messages, &fee_payer_keypair.pubkey(), None)?;
+    let signers = vec![
+        &*fee_payer_keypair,
+        &*new_base_keypair,
+        &*stake_authority_keypair,
+    ];
+    let mut batch = Batch::new(client, messages.len(), receipt_path)?;
+    for ((&i, message), (address, lamports)) in indices.iter().zip(messages).zip(balances) {
+        let applied = Applied::Funded {
+            address: stake_accounts::derive_stake_account_address(&new_base_keypair.pubkey(), i)?,
+            lamports,
+        };
+        let result = send_message(client, i, &address, &message, &signers, Some(applied));
+        let signature = batch.record(i, &address, &message, lamports, result)?;
+        batch.println(format_signature(&signature));
+    }
+    batch.finish();
+    Ok(())
+}
+
+fn process_move_stake_accounts<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    move_config: &MoveCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let authorize_config = &move_config.authorize_config;
+    let fee_payer_keypair = resolve_fee_payer(wallet_manager, &authorize_config.fee_payer)?;
+    let rebase_config = &move_config.rebase_config;
+    let base_pubkey = resolve_base_pubkey(wallet_manager, &rebase_config.base_pubkey)?;
+    let new_base_keypair =
+        resolve_new_base_keypair(wallet_manager, &rebase_config.new_base_keypair)?;
+    let stake_authority_keypair =
+        resolve_stake_authority(wallet_manager, &authorize_config.stake_authority)?;
+    let withdraw_authority_keypair =
+        resolve_withdraw_authority(wallet_manager, &authorize_config.withdraw_authority)?;
+    let new_stake_authority_pubkey =
+        resolve_new_stake_authority(wallet_manager, &authorize_config.new_stake_authority)?;
+    let new_withdraw_authority_pubkey =
+        resolve_new_withdraw_authority(wallet_manager, &authorize_config.new_withdraw_authority)?;
+    let indices = &authorize_config.indices;
+    let pubkeys = stake_accounts::derive_stake_account_addresses(&base_pubkey, indices)?;
+    let balances = get_balances(&client, indices, pubkeys)?;
+
+    let messages = stake_accounts::move_stake_accounts(
+        &fee_payer_keypair.pubkey(),
+        &new_base_keypair.pubkey(),
+        &stake_authority_keypair.pubkey(),
+        &withdraw_authority_keypair.pubkey(),
+        &new_stake_authority_pubkey,
+        &new_withdraw_authority_pubkey,
+        indices,
+        &balances,
+    )?;
+    check_payer_balances(client, &messages, &fee_payer_keypair.pubkey(), None)?;
+    let signers = vec![
+        &*fee_payer_keypair,
+        &*new_base_keypair,
+        &*stake_authority_keypair,
+        &*withdraw_authority_keypair,
+    ];
+    let mut batch = Batch::new(client, messages.len(), receipt_path)?;
+    for ((&i, message), (address, lamports)) in indices.iter().zip(messages).zip(balances) {
+        let applied = Applied::Authorized {
+            address: stake_accounts::derive_stake_account_address(&new_base_keypair.pubkey(), i)?,
+            staker: new_stake_authority_pubkey,
+            withdrawer: new_withdraw_authority_pubkey,
+        };
+        let result = send_message(client, i, &address, &message, &signers, Some(applied));
+        let signature = batch.record(i, &address, &message, lamports, result)?;
+        batch.println(format_signature(&signature));
+    }
+    batch.finish();
+    Ok(())
+}
+
+// Return the messages of a planned command, each with the index and address of
+// the stake account it is sent on behalf of
+fn planned_messages<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    command: &PlannedCommand,
+) -> Result<Vec<(usize, Pubkey, Message)>, Error> {
+    let planned = match command {
+        PlannedCommand::Authorize(authorize_config) => {
+            let fee_payer_pubkey =
+                resolve_pubkey(wallet_manager, &authorize_config.fee_payer, "fee-payer")?;
+            let base_pubkeys =
+                resolve_base_pubkeys(wallet_manager, &authorize_config.base_pubkeys)?;
+            let stake_authority_pubkey = resolve_pubkey(
+                wallet_manager,
+                &authorize_config.stake_authority,
+                "stake authority",
+            )?;
+            let withdraw_authority_pubkey = resolve_pubkey(
+                wallet_manager,
+                &authorize_config.withdraw_authority,
+                "withdraw authority",
+            )?;
+            let new_stake_authority_pubkey =
+                resolve_new_stake_authority(wallet_manager, &authorize_config.new_stake_authority)?;
+            let new_withdraw_authority_pubkey = resolve_new_withdraw_authority(
+                wallet_manager,
+                &authorize_config.new_withdraw_authority,
+            )?;
+            let mut planned = vec![];
+            for base_pubkey in &base_pubkeys {
+                let messages = stake_accounts::authorize_stake_accounts(
+                    &fee_payer_pubkey,
+                    base_pubkey,
+                    &stake_authority_pubkey,
+                    &withdraw_authority_pubkey,
+                    &new_stake_authority_pubkey,
+                    &new_withdraw_authority_pubkey,
+                    &authorize_config.indices,
+                )?;
+                let addresses = stake_accounts::derive_stake_account_addresses(
+                    base_pubkey,
+                    &authorize_config.indices,
+                )?;
+                for ((&i, message), address) in
+                    authorize_config.indices.iter().zip(messages).zip(addresses)
+                {
+                    planned.push((i, address, message));
+                }
+            }
+            planned
+        }
+        PlannedCommand::Rebase(rebase_config) => {
+            let fee_payer_pubkey =
+                resolve_pubkey(wallet_manager, &rebase_config.fee_payer, "fee-payer")?;
+            let base_pubkey = resolve_base_pubkey(wallet_manager, &rebase_config.base_pubkey)?;
+            let new_base_pubkey = resolve_pubkey(
+                wallet_manager,
+                &rebase_config.new_base_keypair,
+                "new base keypair",
+            )?;
+            let stake_authority_pubkey = resolve_pubkey(
+                wallet_manager,
+                &rebase_config.stake_authority,
+                "stake authority",
+            )?;
+            let indices = &rebase_config.indices;
+            let pubkeys = stake_accounts::derive_stake_account_addresses(&base_pubkey, indices)?;
+            let balances = get_balances(client, indices, pubkeys)?;
+            let messages = stake_accounts::rebase_stake_accounts(
+                &fee_payer_pubkey,
+                &new_base_pubkey,
+                &stake_authority_pubkey,
+                indices,
+                &balances,
+            )?;
+            indices
+                .iter()
+                .zip(messages)
+                .zip(balances)
+                .map(|((&i, message), (address, _))| (i, address, message))
+                .collect()
+        }
+        PlannedCommand::Move(move_config) => {
+            let authorize_config = &move_config.authorize_config;
+            let rebase_config = &move_config.rebase_config;
+            let fee_payer_pubkey =
+                resolve_pubkey(wallet_manager, &authorize_config.fee_payer, "fee-payer")?;
+            let base_pubkey = resolve_base_pubkey(wallet_manager, &rebase_config.base_pubkey)?;
+            let new_base_pubkey = resolve_pubkey(
+                wallet_manager,
+                &rebase_config.new_base_keypair,
+                "new base keypair",
+            )?;
+            let stake_authority_pubkey = resolve_pubkey(
+                wallet_manager,
+                &authorize_config.stake_authority,
+                "stake authority",
+            )?;
+            let withdraw_authority_pubkey = resolve_pubkey(
+                wallet_manager,
+                &authorize_config.withdraw_authority,
+                "withdraw authority",
+            )?;
+            let new_stake_authority_pubkey =
+                resolve_new_stake_authority(wallet_manager, &authorize_config.new_stake_authority)?;
+            let new_withdraw_authority_pubkey = resolve_new_withdraw_authority(
+                wallet_manager,
+                &authorize_config.new_withdraw_authority,
+            )?;
+            let indices = &authorize_config.indices;
+            let pubkeys = stake_accounts::derive_stake_account_addresses(&base_pubkey, indices)?;
+            let balances = get_balances(client, indices, pubkeys)?;
+            let messages = stake_accounts::move_stake_accounts(
+                &fee_payer_pubkey,
+                &new_base_pubkey,
+                &stake_authority_pubkey,
+                &withdraw_authority_pubkey,
+                &new_stake_authority_pubkey,
+                &new_withdraw_authority_pubkey,
+                indices,
+                &balances,
+            )?;
+            indices
+                .iter()
+                .zip(messages)
+                .zip(balances)
+                .map(|((&i, message), (address, _))| (i, address, message))
+                .collect()
+        }
+    };
+    Ok(planned)
+}
+
+fn process_plan<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    plan_config: &PlanCommandConfig,
+) -> Result<(), Error> {
+    let command = match plan_config.command {
+        PlannedCommand::Authorize(_) => "authorize",
+        PlannedCommand::Rebase(_) => "rebase",
+        PlannedCommand::Move(_) => "move",
+    };
+    let nonce_authority = resolve_pubkey(
+        wallet_manager,
+        &plan_config.nonce_authority,
+        "nonce authority",
+    )?;
+    let mut nonce_pubkeys = vec![];
+    for nonce_account in &plan_config.nonce_accounts {
+        let nonce_pubkey = resolve_pubkey(wallet_manager, nonce_account, "nonce account")?;
+        if nonce_pubkeys.contains(&nonce_pubkey) {
+            return Err(Error::Config(format!(
+                "nonce account {} given more than once",
+                nonce_pubkey
+            )));
+        }
+        nonce_pubkeys.push(nonce_pubkey);
+    }
+    let messages = planned_messages(client, wallet_manager, &plan_config.command)?;
+    // Each transaction advances its nonce, so none can share one.
+    if nonce_pubkeys.len() < messages.len() {
+        return Err(Error::Config(format!(
+            "{} transactions planned, but only {} nonce accounts given",
+            messages.len(),
+            nonce_pubkeys.len()
+        )));
+    }
+    nonce_pubkeys.truncate(messages.len());
+    let mut nonce_blockhashes = vec![];
+    for (nonce_pubkey, account) in nonce_pubkeys
+        .iter()
+        .zip(get_accounts(client, &nonce_pubkeys)?)
+    {
+        let data = account.as_ref().and_then(nonce_data).ok_or_else(|| {
+            Error::Config(format!(
+                "{} is not an initialized nonce account",
+                nonce_pubkey
+            ))
+        })?;
+        if data.authority != nonce_authority {
+            return Err(Error::Config(format!(
+                "nonce account {} has authority {}, not {}",
+                nonce_pubkey, data.authority, nonce_authority
+            )));
+        }
+        nonce_blockhashes.push(data.blockhash);
+    }
+    let addresses: Vec<_> = messages.iter().map(|(_, address, _)| *address).collect();
+    let accounts = get_accounts(client, &addresses)?;
+
+    let mut transactions = vec![];
+    let nonces = nonce_pubkeys.iter().zip(&nonce_blockhashes);
+    for (((index, address, message), account), (nonce_pubkey, blockhash)) in
+        messages.into_iter().zip(accounts).zip(nonces)
+    {
+        let message = message_with_nonce(&message, nonce_pubkey, &nonce_authority, blockhash);
+        let transaction = account
+            .as_ref()
+            .and_then(|account| {
+                PlannedTransaction::new(index, &address, account, nonce_pubkey, &message)
+            })
+            .ok_or_else(|| Error::account(index, &address, Phase::Plan, "not a stake account"))?;
+        println!(
+            "{:<5} {:<44} {}",
+            index,
+            address,
+            transaction.missing_signers().join(" ")
+        );
+        transactions.push(transaction);
+    }
+    let plan = TransactionPlan {
+        command: command.to_string(),
+        transactions,
+    };
+    save_state(&plan_config.output_path, &plan)
+}
+
+fn process_sign(
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    sign_config: &SignCommandConfig,
+) -> Result<(), Error> {
+    let signer = signer_from_path(
+        &ArgMatches::default(),
+        &sign_config.signer,
+        "signer",
+        wallet_manager,
+    )
+    .map_err(|err| Error::resolve("signer", err))?;
+    let plan_path = &sign_config.plan_path;
+    let mut plan = read_transaction_plan_file(plan_path)?;
+    let num_signed = sign_plan(&mut plan, &*signer)?;
+    if num_signed == 0 {
+        return Err(Error::Config(format!(
+            "{}: no transaction needs a signature from {}",
+            plan_path,
+            signer.pubkey()
+        )));
+    }
+    save_state(plan_path, &plan)?;
+    let num_unsigned = plan
+        .transactions
+        .iter()
+        .filter(|transaction| !transaction.missing_signers().is_empty())
+        .count();
+    println!(
+        "Signed {} transactions, {} still need signatures",
+        num_signed, num_unsigned
+    );
+    Ok(())
+}
+
+fn process_submit<C: Client>(
+    client: &C,
+    submit_config: &SubmitCommandConfig,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    let plan_path = &submit_config.plan_path;
+    let plan = read_transaction_plan_file(plan_path)?;
+
+    // Check every signature before sending anything.
+    let mut transactions = vec![];
+    let mut nonce_pubkeys = vec![];
+    for planned in &plan.transactions {
+        let address = Pubkey::from_str(&planned.address)
+            .map_err(|err| Error::Config(format!("{}: invalid address: {:?}", plan_path, err)))?;
+        let nonce_pubkey = Pubkey::from_str(&planned.nonce_account).map_err(|err| {
+            Error::Config(format!("{}: invalid nonce account: {:?}", plan_path, err))
+        })?;
+        nonce_pubkeys.push(nonce_pubkey);
+        let missing_signers = planned.missing_signers();
+        if !missing_signers.is_empty() {
+            let reason = format!("missing signatures of {}", missing_signers.join(", "));
+            return Err(Error::account(planned.index, &address, Phase::Sign, reason));
+        }
+        let transaction = planned
+            .signed_transaction()
+            .map_err(|err| Error::account(planned.index, &address, Phase::Sign, err))?;
+        transactions.push((planned, address, transaction));
+    }
+
+    // The messages are only valid against the accounts and nonces they were
+    // planned for.
+    let addresses: Vec<_> = transactions
+        .iter()
+        .map(|(_, address, _)| *address)
+        .collect();
+    let accounts = get_accounts(client, &addresses)?;
+    let nonce_accounts = get_accounts(client, &nonce_pubkeys)?;
+    for (((planned, address, _), account), nonce_account) in
+        transactions.iter().zip(&accounts).zip(&nonce_accounts)
+    {
+        planned
+            .check_account(account.as_ref(), nonce_account.as_ref())
+            .map_err(|err| Error::account(planned.index, address, Phase::Plan, err))?;
+    }
+    let messages: Vec<_> = transactions
+        .iter()
+        .map(|(_, _, transaction)| transaction.message.clone())
+        .collect();
+    if let Some(message) = messages.first() {
+        check_payer_balances(client, &messages, &message.account_keys[0], None)?;
+    }
+
+    // Authorizing moves no lamports, rebasing and moving move the whole balance.
+    let moves_lamports = plan.command != "authorize";
+    let mut batch = Batch::new(client, messages.len(), receipt_path)?;
+    for ((planned, address, transaction), message) in transactions.into_iter().zip(messages) {
+        let result = client
+            .send_and_confirm_transaction(transaction)
+            .map(Some)
+            .map_err(|err| Error::send(planned.index, &address, &err, &message));
+        let lamports = if moves_lamports { planned.lamports } else { 0 };
+        let signature = batch.record(planned.index, &address, &message, lamports, result)?;
+        batch.println(format_signature(&signature));
+    }
+    batch.finish();
+    Ok(())
+}
+
+fn process_status<C: Client>(client: &C, status_config: &StatusCommandConfig) -> Result<(), Error> {
+    let receipt_path = &status_config.receipt_path;
+    let mut receipt: Receipt = load_state(receipt_path)?;
+    update_receipt(client, &mut receipt)?;
+    save_state(receipt_path, &receipt)?;
+    println!("{:<5} {:<88} STATUS", "INDEX", "SIGNATURE");
+    for entry in &receipt.entries {
+        println!(
+            "{:<5} {:<88} {}",
+            entry.index,
+            entry.signature.as_deref().unwrap_or("-"),
+            entry.status
+        );
+    }
+    Ok(())
+}
+
+fn process_locate<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    locate_config: &LocateCommandConfig,
+) -> Result<(), Error> {
+    let address = resolve_pubkey(wallet_manager, &locate_config.address, "address")?;
+    let bases = locate_config
+        .bases
+        .iter()
+        .map(|(key_url, seed_scheme)| {
+            resolve_base_pubkey(wallet_manager, key_url).map(|pubkey| (pubkey, *seed_scheme))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    write_location(
+        client,
+        io::stdout(),
+        &address,
+        &bases,
+        locate_config.search_limit,
+    )
+}
+
+// Send a message on behalf of the stake account at index `i` with address `address`,
+// retrying if it fails. Returns the signature, or None if `applied` shows that
+// the message was already applied.
+fn send_message<C: Client, S: Signers>(
+    client: &C,
+    i: usize,
+    address: &Pubkey,
+    message: &Message,
+    signers: &S,
+    applied: Option<Applied>,
+) -> Result<Option<String>, Error> {
+    send_with_retries(client, message, signers, applied.as_ref())
+        .map_err(|err| Error::send(i, address, &err, message))
+}
+
+// Describe the result of send_message
+fn format_signature(signature: &Option<String>) -> &str {
+    signature.as_deref().unwrap_or("already applied")
+}
+
+fn process_command<C: Client>(
+    client: &C,
+    wallet_manager: Option<&Arc<RemoteWalletManager>>,
+    command: Command,
+    receipt_path: Option<&str>,
+) -> Result<(), Error> {
+    match command {
+        Command::New(new_config) => {
+            process_new_stake_account(client, wallet_manager, &new_config, receipt_path)?;
+        }
+        Command::Count(count_config) => {
+            process_count_stake_accounts(client, wallet_manager, &count_config)?;
+        }
+        Command::Pubkeys(query_config) => {
+            process_pubkeys(wallet_manager, &query_config)?;
+        }
+        Command::Balance(query_config) => {
+            process_balance(client, wallet_manager, &query_config)?;
+        }
+        Command::Authorize(authorize_config) => {
+            process_authorize_stake_accounts(
+                client,
+                wallet_manager,
+                &authorize_config,
+                receipt_path,
+            )?;
+        }
+        Command::Rebase(rebase_config) => {
+            process_rebase_stake_accounts(client, wallet_manager, &rebase_config, receipt_path)?;
+        }
+        Command::Move(move_config) => {
+            process_move_stake_accounts(client, wallet_manager, &move_config, receipt_path)?;
+        }
+        Command::Snapshot(snapshot_config) => {
+            process_snapshot(client, wallet_manager, &snapshot_config)?;
+        }
+        Command::Diff(diff_config) => {
+            process_diff(&diff_config)?;
+        }
+        Command::Watch(watch_config) => {
+            process_watch(client, wallet_manager, &watch_config)?;
+        }
+        Command::History(history_config) => {
+            process_history(client, wallet_manager, &history_config)?;
+        }
+        Command::Distribute(distribute_config) => {
+            process_distribute(client, wallet_manager, &distribute_config, receipt_path)?;
+        }
+        Command::Vest(vest_config) => {
+            process_vest(client, wallet_manager, &vest_config, receipt_path)?;
+        }
+        Command::Clawback(clawback_config) => {
+            process_clawback(client, wallet_manager, &clawback_config, receipt_path)?;
+        }
+        Command::Unlocks(unlocks_config) => {
+            process_unlocks(client, wallet_manager, &unlocks_config)?;
+        }
+        Command::Audit(audit_config) => {
+            process_audit(client, wallet_manager, &audit_config)?;
+        }
+        Command::Delegate(delegate_config) => {
+            process_delegate(client, wallet_manager, &delegate_config, receipt_path)?;
+        }
+        Command::Rebalance(rebalance_config) => {
+            process_rebalance(client, wallet_manager, &rebalance_config, receipt_path)?;
+        }
+        Command::RunPlan(run_plan_config) => {
+            process_run_plan(client, wallet_manager, &run_plan_config, receipt_path)?;
+        }
+        Command::Plan(plan_config) => {
+            process_plan(client, wallet_manager, &plan_config)?;
+        }
+        Command::Sign(sign_config) => {
+            process_sign(wallet_manager, &sign_config)?;
+        }
+        Command::Submit(submit_config) => {
+            process_submit(client, &submit_config, receipt_path)?;
+        }
+        Command::Status(status_config) => {
+            process_status(client, &status_config)?;
+        }
+        Command::Locate(locate_config) => {
+            process_locate(client, wallet_manager, &locate_config)?;
+        }
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), Error> {
+    let command_config = parse_args(env::args_os())?;
+    let config = Config::load(&command_config.config_file)
+        .map_err(|err| Error::Config(format!("{}: {}", command_config.config_file, err)))?;
+    let urls = if command_config.urls.is_empty() {
+        vec![config.json_rpc_url]
+    } else {
+        command_config.urls
+    };
+    let commitment = command_config.commitment;
+    let finalized = command_config.finalized;
+    let clients = urls
+        .into_iter()
+        .map(|url| CommitmentRpcClient::new(RpcClient::new(url), commitment, finalized))
+        .collect();
+    let rate_limiter = command_config.requests_per_second.map(RateLimiter::new);
+    let client = FailoverClient::new(clients, rate_limiter);
+
+    let wallet_manager =
+        maybe_wallet_manager().map_err(|err| Error::resolve("remote wallet", err))?;
+    let wallet_manager = wallet_manager.as_ref();
+    process_command(
+        &client,
+        wallet_manager,
+        command_config.command,
+        command_config.receipt_path.as_deref(),
+    )
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        exit(err.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::bank_client::HistoryClient;
+    use crate::receipt::ConfirmationStatus;
+    use crate::scheduler::read_plan;
+    use solana_runtime::{bank::Bank, bank_client::BankClient};
+    use solana_sdk::{
+        fee_calculator::FeeCalculator,
+        genesis_config::create_genesis_config,
+        hash::hash,
+        native_token::sol_to_lamports,
+        nonce,
+        signature::{write_keypair_file, Keypair},
+        system_instruction, system_program,
+    };
+    use solana_stake_program::{
+        config as stake_config,
+        stake_instruction::{self, StakeAuthorize},
+    };
+    use solana_vote_program::vote_state;
+    use std::fs;
+    use std::iter;
+    use std::path::PathBuf;
+    use std::u64;
+
+    /// A directory of keypair and input files, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let path = env::temp_dir().join(format!("stake-accounts-{}", Pubkey::new_rand()));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).to_str().unwrap().to_string()
+        }
+
+        fn write_keypair(&self, name: &str, keypair: &Keypair) -> String {
+            let path = self.path(name);
+            write_keypair_file(keypair, &path).unwrap();
+            path
+        }
+
+        fn write_file(&self, name: &str, contents: &str) -> String {
+            let path = self.path(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn create_bank_client(
+        lamports: u64,
+        vote_pubkeys: &[Pubkey],
+        accounts: &[(Pubkey, Account)],
+    ) -> (BankClient, Keypair) {
+        let (genesis_config, mint_keypair) = create_genesis_config(lamports);
+        let mut bank = Bank::new(&genesis_config);
+        bank.add_instruction_processor(
+            solana_stake_program::id(),
+            stake_instruction::process_instruction,
+        );
+        bank.store_account(
+            &stake_config::id(),
+            &stake_config::create_account(1, &stake_config::Config::default()),
+        );
+        for vote_pubkey in vote_pubkeys {
+            let account = vote_state::create_account(
+                vote_pubkey,
+                &Pubkey::new_rand(),
+                0,
+                sol_to_lamports(1.0),
+            );
+            bank.store_account(vote_pubkey, &account);
+        }
+        for (pubkey, account) in accounts {
+            bank.store_account(pubkey, account);
+        }
+        (BankClient::new(bank), mint_keypair)
+    }
+
+    /// A bank, and keypair files for each of the roles commands take.
+    struct TestSetup {
+        dir: TestDir,
+        client: HistoryClient<BankClient>,
+        fee_payer_keypair: Keypair,
+        base_keypair: Keypair,
+        stake_authority_keypair: Keypair,
+        withdraw_authority_keypair: Keypair,
+        custodian_keypair: Keypair,
+    }
+
+    impl TestSetup {
+        fn new(vote_pubkeys: &[Pubkey]) -> Self {
+            Self::with_accounts(vote_pubkeys, &[])
+        }
+
+        /// Set up a bank that also holds the given accounts.
+        fn with_accounts(vote_pubkeys: &[Pubkey], accounts: &[(Pubkey, Account)]) -> Self {
+            let (client, mint_keypair) =
+                create_bank_client(sol_to_lamports(1_000.0), vote_pubkeys, accounts);
+            let fee_payer_keypair = Keypair::new();
+            let instruction = system_instruction::transfer(
+                &mint_keypair.pubkey(),
+                &fee_payer_keypair.pubkey(),
+                sol_to_lamports(1.0),
+            );
+            let message = Message::new_with_payer(&[instruction], Some(&mint_keypair.pubkey()));
+            client
+                .send_and_confirm_message(message, &[&mint_keypair])
+                .unwrap();
+
+            let dir = TestDir::new();
+            let setup = Self {
+                dir,
+                client: HistoryClient::new(client),
+                fee_payer_keypair,
+                base_keypair: Keypair::new(),
+                stake_authority_keypair: Keypair::new(),
+                withdraw_authority_keypair: Keypair::new(),
+                custodian_keypair: Keypair::new(),
+            };
+            setup.dir.write_keypair("sender.json", &mint_keypair);
+            setup
+                .dir
+                .write_keypair("fee_payer.json", &setup.fee_payer_keypair);
+            setup.dir.write_keypair("base.json", &setup.base_keypair);
+            setup
+                .dir
+                .write_keypair("stake_authority.json", &setup.stake_authority_keypair);
+            setup
+                .dir
+                .write_keypair("withdraw_authority.json", &setup.withdraw_authority_keypair);
+            setup
+                .dir
+                .write_keypair("custodian.json", &setup.custodian_keypair);
+            setup
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.path(name)
+        }
+
+        fn run(&self, args: &[&str]) -> Result<(), Error> {
+            let args = iter::once("solana-stake-accounts").chain(args.iter().cloned());
+            let command_config = parse_args(args)?;
+            // Confirm at the commitment run() would give the RPC clients.
+            self.client
+                .set_confirm_commitment(if command_config.finalized {
+                    CommitmentConfig::max()
+                } else {
+                    command_config.commitment
+                });
+            process_command(
+                &self.client,
+                None,
+                command_config.command,
+                command_config.receipt_path.as_deref(),
+            )
+        }
+
+        /// Split 30 SOL into `num_tranches` accounts, unlocking monthly from `start`.
+        fn vest(&self, num_tranches: usize, start: &str) {
+            self.run(&[
+                "vest",
+                "--fee-payer",
+                &self.path("fee_payer.json"),
+                &self.path("sender.json"),
+                &self.path("base.json"),
+                "30",
+                "--stake-authority",
+                &self.stake_authority_keypair.pubkey().to_string(),
+                "--withdraw-authority",
+                &self.withdraw_authority_keypair.pubkey().to_string(),
+                "--custodian",
+                &self.custodian_keypair.pubkey().to_string(),
+                "--start",
+                start,
+                "--period",
+                "1mo",
+                "--tranches",
+                &num_tranches.to_string(),
+            ])
+            .unwrap();
+        }
+
+        fn address(&self, base_pubkey: &Pubkey, i: usize) -> Pubkey {
+            stake_accounts::derive_stake_account_address(base_pubkey, i).unwrap()
+        }
+
+        fn account(&self, i: usize) -> Account {
+            let address = self.address(&self.base_keypair.pubkey(), i);
+            self.client.get_account(&address).unwrap().unwrap()
+        }
+
+        fn staker(&self, i: usize) -> Pubkey {
+            StakeState::authorized_from(&self.account(i))
+                .unwrap()
+                .staker
+        }
+    }
+
+    #[test]
+    fn test_new_and_query_commands() {
+        let setup = TestSetup::new(&[]);
+        let base_pubkey = setup.base_keypair.pubkey().to_string();
+        let stake_authority = setup.stake_authority_keypair.pubkey().to_string();
+        setup
+            .run(&[
+                "new",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.path("sender.json"),
+                &setup.path("base.json"),
+                "10",
+                "--stake-authority",
+                &stake_authority,
+                "--withdraw-authority",
+                &setup.withdraw_authority_keypair.pubkey().to_string(),
+            ])
+            .unwrap();
+        assert_eq!(setup.account(0).lamports, sol_to_lamports(10.0));
+        assert_eq!(
+            count_stake_accounts(&setup.client, &setup.base_keypair.pubkey()).unwrap(),
+            1
+        );
+        setup.run(&["count", &base_pubkey]).unwrap();
+        let mut output = vec![];
+        write_counts(&setup.client, &mut output, &[setup.base_keypair.pubkey()]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+        let other_base_pubkey = Pubkey::new_rand();
+        let mut output = vec![];
+        write_counts(
+            &setup.client,
+            &mut output,
+            &[setup.base_keypair.pubkey(), other_base_pubkey],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "{}: 1\n{}: 0\nTotal: 1\n",
+                setup.base_keypair.pubkey(),
+                other_base_pubkey
+            )
+        );
+        match setup.run(&["pubkeys", &base_pubkey]) {
+            Err(Error::Config(message)) => assert!(message.contains("num_accounts")),
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+        setup
+            .run(&["pubkeys", &base_pubkey, "--num-accounts", "1"])
+            .unwrap();
+        setup
+            .run(&["balance", &base_pubkey, "--num-accounts", "1"])
+            .unwrap();
+        setup
+            .run(&["unlocks", &base_pubkey, "--num-accounts", "1"])
+            .unwrap();
+
+        let before = setup.path("before.json");
+        let after = setup.path("after.json");
+        setup
+            .run(&[
+                "snapshot",
+                &base_pubkey,
+                "--num-accounts",
+                "1",
+                "--output",
+                &before,
+            ])
+            .unwrap();
+        setup
+            .run(&[
+                "snapshot",
+                &base_pubkey,
+                "--num-accounts",
+                "1",
+                "--output",
+                &after,
+            ])
+            .unwrap();
+        let snapshot = read_snapshot(&before).unwrap();
+        assert_eq!(snapshot.accounts.len(), 1);
+        assert_eq!(
+            snapshot.accounts[0].address,
+            setup.address(&setup.base_keypair.pubkey(), 0).to_string()
+        );
+        assert_eq!(snapshot.accounts[0].lamports, sol_to_lamports(10.0));
+
+        // Nothing changed between the two snapshots, so only the slots are written.
+        setup.run(&["diff", &before, &after]).unwrap();
+        let after_snapshot = read_snapshot(&after).unwrap();
+        let mut output = vec![];
+        write_diff(&mut output, &snapshot, &after_snapshot).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with(&format!("Slot {} ", snapshot.slot)));
+
+        let policy = setup.dir.write_file(
+            "policy.yml",
+            &format!("staker: {}\nmin_balance: 5\n", stake_authority),
+        );
+        setup
+            .run(&[
+                "audit",
+                &base_pubkey,
+                "--num-accounts",
+                "1",
+                "--policy",
+                &policy,
+            ])
+            .unwrap();
+        let policy = setup
+            .dir
+            .write_file("policy.yml", &format!("staker: {}\n", Pubkey::new_rand()));
+        match setup.run(&[
+            "audit",
+            &base_pubkey,
+            "--num-accounts",
+            "1",
+            "--policy",
+            &policy,
+        ]) {
+            Err(Error::AuditFailed {
+                severity,
+                num_accounts,
+            }) => {
+                assert_eq!(severity, Severity::Critical);
+                assert_eq!(num_accounts, 1);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn test_balance_of_many_bases() {
+        let setup = TestSetup::new(&[]);
+        let other_base_keypair = Keypair::new();
+        let other_base = setup
+            .dir
+            .write_keypair("other_base.json", &other_base_keypair);
+        for (base, amount) in &[(setup.path("base.json"), "10"), (other_base, "20")] {
+            setup
+                .run(&[
+                    "new",
+                    "--fee-payer",
+                    &setup.path("fee_payer.json"),
+                    &setup.path("sender.json"),
+                    base,
+                    amount,
+                    "--stake-authority",
+                    &setup.stake_authority_keypair.pubkey().to_string(),
+                    "--withdraw-authority",
+                    &setup.withdraw_authority_keypair.pubkey().to_string(),
+                ])
+                .unwrap();
+        }
+        let base_pubkeys = [setup.base_keypair.pubkey(), other_base_keypair.pubkey()];
+        for (base_pubkey, amount) in base_pubkeys.iter().zip(&[10.0, 20.0]) {
+            let address = setup.address(base_pubkey, 0);
+            assert_eq!(
+                setup.client.get_balance(&address).unwrap(),
+                sol_to_lamports(*amount)
+            );
+        }
+
+        let base_pubkeys: Vec<_> = base_pubkeys.iter().map(|x| x.to_string()).collect();
+        setup
+            .run(&[
+                "balance",
+                &base_pubkeys[0],
+                &base_pubkeys[1],
+                "--num-accounts",
+                "1",
+            ])
+            .unwrap();
+        let base_pubkeys_file = setup.dir.write_file(
+            "bases.txt",
+            &format!("# bases\n{}\n\n{}\n", base_pubkeys[0], base_pubkeys[1]),
+        );
+        setup
+            .run(&[
+                "balance",
+                "--base-pubkeys-file",
+                &base_pubkeys_file,
+                "--num-accounts",
+                "1",
+            ])
+            .unwrap();
+
+        let base_pubkeys_file = setup
+            .dir
+            .write_file("bases.txt", &format!("{}\nnot-a-pubkey\n", base_pubkeys[0]));
+        match setup.run(&[
+            "balance",
+            "--base-pubkeys-file",
+            &base_pubkeys_file,
+            "--num-accounts",
+            "1",
+        ]) {
+            Err(Error::Config(message)) => assert!(message.contains("line 2")),
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn test_check_balances() {
+        let setup = TestSetup::new(&[]);
+        let fee_payer_pubkey = setup.fee_payer_keypair.pubkey();
+        let new = |amount: &str| {
+            setup.run(&[
+                "new",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.path("fee_payer.json"),
+                &setup.path("base.json"),
+                amount,
+                "--stake-authority",
+                &setup.stake_authority_keypair.pubkey().to_string(),
+                "--withdraw-authority",
+                &setup.withdraw_authority_keypair.pubkey().to_string(),
+            ])
+        };
+
+        // The fee payer, also the sender, holds 1 SOL.
+        match new("10") {
+            Err(Error::InsufficientFunds {
+                name,
+                address,
+                balance,
+                required,
+            }) => {
+                assert_eq!(name, "fee payer");
+                assert_eq!(address, fee_payer_pubkey);
+                assert_eq!(balance, sol_to_lamports(1.0));
+                assert!(required > sol_to_lamports(10.0));
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+
+        match new("0.000001") {
+            Err(Error::BelowRentExemption { lamports, minimum }) => {
+                assert_eq!(lamports, sol_to_lamports(0.000_001));
+                assert!(minimum > lamports);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+        assert_eq!(
+            setup.client.get_balance(&fee_payer_pubkey).unwrap(),
+            sol_to_lamports(1.0)
+        );
+
+        // Fees and lamports that overflow can't be funded.
+        let instruction = system_instruction::transfer(&fee_payer_pubkey, &Pubkey::new_rand(), 1);
+        let message = Message::new_with_payer(&[instruction], Some(&fee_payer_pubkey));
+        match check_payer_balances(
+            &setup.client,
+            &[message],
+            &fee_payer_pubkey,
+            Some((&fee_payer_pubkey, u64::MAX)),
+        ) {
+            Err(Error::InsufficientFunds { required, .. }) => assert_eq!(required, u64::MAX),
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let setup = TestSetup::new(&[]);
+        let base_pubkey = setup.base_keypair.pubkey();
+        setup
+            .run(&[
+                "new",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.path("sender.json"),
+                &setup.path("base.json"),
+                "10",
+                "--stake-authority",
+                &setup.stake_authority_keypair.pubkey().to_string(),
+                "--withdraw-authority",
+                &setup.withdraw_authority_keypair.pubkey().to_string(),
+            ])
+            .unwrap();
+        let new_stake_authority = Pubkey::new_rand();
+        setup
+            .run(&[
+                "authorize",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &base_pubkey.to_string(),
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--withdraw-authority",
+                &setup.path("withdraw_authority.json"),
+                "--new-stake-authority",
+                &new_stake_authority.to_string(),
+                "--new-withdraw-authority",
+                &setup.withdraw_authority_keypair.pubkey().to_string(),
+                "--num-accounts",
+                "1",
+            ])
+            .unwrap();
+
+        let end_slot = setup.client.get_slot().unwrap();
+        let entries = fetch_history(&setup.client, &[base_pubkey], &[0], 0, end_slot).unwrap();
+        // Both transactions land in the same slot, so compare them unordered.
+        let mut kinds: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.instruction.as_str())
+            .collect();
+        kinds.sort();
+        assert_eq!(
+            kinds,
+            vec!["authorize", "authorize", "create", "initialize"]
+        );
+        let address = setup.address(&base_pubkey, 0).to_string();
+        assert!(entries.iter().all(|entry| entry.address == address));
+        let create = entries
+            .iter()
+            .find(|entry| entry.instruction == "create")
+            .unwrap();
+        assert_eq!(create.lamports, Some(sol_to_lamports(10.0)));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.detail == format!("staker {}", new_stake_authority)));
+
+        setup
+            .run(&[
+                "history",
+                &base_pubkey.to_string(),
+                "--num-accounts",
+                "1",
+                "--start-slot",
+                "0",
+            ])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_authorize_rebase_and_move() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(2, "2000-01-01");
+        let base_pubkey = setup.base_keypair.pubkey();
+        let new_stake_authority_keypair = Keypair::new();
+        let new_stake_authority = setup
+            .dir
+            .write_keypair("new_stake_authority.json", &new_stake_authority_keypair);
+        let new_withdraw_authority_keypair = Keypair::new();
+        let new_withdraw_authority = setup.dir.write_keypair(
+            "new_withdraw_authority.json",
+            &new_withdraw_authority_keypair,
+        );
+        setup
+            .run(&[
+                "authorize",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &base_pubkey.to_string(),
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--withdraw-authority",
+                &setup.path("withdraw_authority.json"),
+                "--new-stake-authority",
+                &new_stake_authority_keypair.pubkey().to_string(),
+                "--new-withdraw-authority",
+                &new_withdraw_authority_keypair.pubkey().to_string(),
+                "--num-accounts",
+                "2",
+            ])
+            .unwrap();
+        for i in 0..2 {
+            let authorized = StakeState::authorized_from(&setup.account(i)).unwrap();
+            assert_eq!(authorized.staker, new_stake_authority_keypair.pubkey());
+            assert_eq!(
+                authorized.withdrawer,
+                new_withdraw_authority_keypair.pubkey()
+            );
+        }
+
+        // Rebasing moves the lamports to accounts derived from the new base.
+        let rebased_keypair = Keypair::new();
+        let rebased = setup.dir.write_keypair("rebased.json", &rebased_keypair);
+        setup
+            .run(&[
+                "rebase",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &base_pubkey.to_string(),
+                &rebased,
+                "--stake-authority",
+                &new_stake_authority,
+                "--num-accounts",
+                "2",
+            ])
+            .unwrap();
+        for i in 0..2 {
+            let address = setup.address(&base_pubkey, i);
+            assert_eq!(setup.client.get_balance(&address).unwrap(), 0);
+            let address = setup.address(&rebased_keypair.pubkey(), i);
+            assert_eq!(
+                setup.client.get_balance(&address).unwrap(),
+                sol_to_lamports(15.0)
+            );
+        }
+
+        let moved_keypair = Keypair::new();
+        let moved = setup.dir.write_keypair("moved.json", &moved_keypair);
+        setup
+            .run(&[
+                "move",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &rebased_keypair.pubkey().to_string(),
+                &moved,
+                "--stake-authority",
+                &new_stake_authority,
+                "--withdraw-authority",
+                &new_withdraw_authority,
+                "--new-stake-authority",
+                &setup.stake_authority_keypair.pubkey().to_string(),
+                "--new-withdraw-authority",
+                &setup.withdraw_authority_keypair.pubkey().to_string(),
+                "--num-accounts",
+                "2",
+            ])
+            .unwrap();
+        for i in 0..2 {
+            let address = setup.address(&moved_keypair.pubkey(), i);
+            let account = setup.client.get_account(&address).unwrap().unwrap();
+            let authorized = StakeState::authorized_from(&account).unwrap();
+            assert_eq!(authorized.staker, setup.stake_authority_keypair.pubkey());
+            assert_eq!(
+                authorized.withdrawer,
+                setup.withdraw_authority_keypair.pubkey()
+            );
+        }
+    }
+
+    #[test]
+    fn test_authorize_fails_midway() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(3, "2000-01-01");
+        let base_pubkey = setup.base_keypair.pubkey();
+        let stake_authority_pubkey = setup.stake_authority_keypair.pubkey();
+
+        // Someone else takes over account 1, so the batch fails there.
+        let instruction = stake_instruction::authorize(
+            &setup.address(&base_pubkey, 1),
+            &stake_authority_pubkey,
+            &Pubkey::new_rand(),
+            StakeAuthorize::Staker,
+        );
+        let message =
+            Message::new_with_payer(&[instruction], Some(&setup.fee_payer_keypair.pubkey()));
+        setup
+            .client
+            .send_and_confirm_message(
+                message,
+                &[&setup.fee_payer_keypair, &setup.stake_authority_keypair],
+            )
+            .unwrap();
+
+        let new_authority_pubkey = Pubkey::new_rand();
+        let result = setup.run(&[
+            "authorize",
+            "--fee-payer",
+            &setup.path("fee_payer.json"),
+            &base_pubkey.to_string(),
+            "--stake-authority",
+            &setup.path("stake_authority.json"),
+            "--withdraw-authority",
+            &setup.path("withdraw_authority.json"),
+            "--new-stake-authority",
+            &new_authority_pubkey.to_string(),
+            "--new-withdraw-authority",
+            &new_authority_pubkey.to_string(),
+            "--num-accounts",
+            "3",
+        ]);
+        match result {
+            Err(Error::Account { index, phase, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(phase, Phase::Confirm);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+        assert_eq!(setup.staker(0), new_authority_pubkey);
+        assert_eq!(setup.staker(2), stake_authority_pubkey);
+    }
+
+    #[test]
+    fn test_authorize_rerun_recognizes_applied() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(2, "2000-01-01");
+        let base_pubkey = setup.base_keypair.pubkey().to_string();
+        let new_authority_pubkey = Pubkey::new_rand();
+        let args = [
+            "authorize",
+            "--fee-payer",
+            &setup.path("fee_payer.json"),
+            &base_pubkey,
+            "--stake-authority",
+            &setup.path("stake_authority.json"),
+            "--withdraw-authority",
+            &setup.path("withdraw_authority.json"),
+            "--new-stake-authority",
+            &new_authority_pubkey.to_string(),
+            "--new-withdraw-authority",
+            &new_authority_pubkey.to_string(),
+            "--num-accounts",
+            "2",
+        ];
+        setup.run(&args).unwrap();
+
+        // The old authorities can no longer sign, but each account already has
+        // the new ones, so the rerun succeeds without changing anything.
+        setup.run(&args).unwrap();
+        assert_eq!(setup.staker(0), new_authority_pubkey);
+        assert_eq!(setup.staker(1), new_authority_pubkey);
+    }
+
+    #[test]
+    fn test_authorize_indices() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(4, "2000-01-01");
+        let stake_authority_pubkey = setup.stake_authority_keypair.pubkey();
+        let new_authority_pubkey = Pubkey::new_rand();
+        let receipt_path = setup.path("receipt.json");
+        setup
+            .run(&[
+                "authorize",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.base_keypair.pubkey().to_string(),
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--withdraw-authority",
+                &setup.path("withdraw_authority.json"),
+                "--new-stake-authority",
+                &new_authority_pubkey.to_string(),
+                "--new-withdraw-authority",
+                &new_authority_pubkey.to_string(),
+                "--indices",
+                "1,3",
+                "--receipt",
+                &receipt_path,
+            ])
+            .unwrap();
+        assert_eq!(setup.staker(0), stake_authority_pubkey);
+        assert_eq!(setup.staker(1), new_authority_pubkey);
+        assert_eq!(setup.staker(2), stake_authority_pubkey);
+        assert_eq!(setup.staker(3), new_authority_pubkey);
+
+        // The receipt records each account's own index.
+        let receipt: Receipt = load_state(&receipt_path).unwrap();
+        let indices: Vec<_> = receipt.entries.iter().map(|entry| entry.index).collect();
+        assert_eq!(indices, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_locate() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(2, "2000-01-01");
+        let base_pubkey = setup.base_keypair.pubkey();
+        let address = setup.address(&base_pubkey, 1);
+        // Profiles are read from next to the config file, so none by default.
+        let config_file = setup.path("config.yml");
+        let locate = |args: &[&str]| {
+            let args = [
+                "solana-stake-accounts",
+                "--config",
+                config_file.as_str(),
+                "locate",
+            ]
+            .iter()
+            .chain(args)
+            .cloned();
+            let locate_config = match parse_args(args).unwrap().command {
+                Command::Locate(locate_config) => locate_config,
+                _ => panic!("expected a locate command"),
+            };
+            let bases: Vec<_> = locate_config
+                .bases
+                .iter()
+                .map(|(base_pubkey, seed_scheme)| {
+                    (Pubkey::from_str(base_pubkey).unwrap(), *seed_scheme)
+                })
+                .collect();
+            let mut output = vec![];
+            write_location(&setup.client, &mut output, &address, &bases, 10).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let found = format!("Base:  {}\nSeed:  1\nIndex: 1\n", base_pubkey);
+
+        let other_base_pubkey = Pubkey::new_rand().to_string();
+        assert_eq!(
+            locate(&[
+                &address.to_string(),
+                &other_base_pubkey,
+                &base_pubkey.to_string()
+            ]),
+            found
+        );
+
+        // The bases of every profile in the profile file are searched.
+        let profile_file = setup.dir.write_file(
+            "profiles.yml",
+            &format!(
+                "grants:\n  base_pubkey: {}\ntreasury:\n  base_pubkey: {}\n  seed_scheme: index\n",
+                other_base_pubkey, base_pubkey
+            ),
+        );
+        assert_eq!(
+            locate(&[&address.to_string(), "--profile-file", &profile_file]),
+            found
+        );
+
+        // With no known base, the account's authorities are looked up instead.
+        assert_eq!(
+            locate(&[&address.to_string(), &other_base_pubkey]),
+            format!(
+                "{} is not derived from any known base within 10 indices\n\
+                 Staker:     {}\n\
+                 Withdrawer: {}\n",
+                address,
+                setup.stake_authority_keypair.pubkey(),
+                setup.withdraw_authority_keypair.pubkey()
+            )
+        );
+
+        setup
+            .run(&["locate", &address.to_string(), &base_pubkey.to_string()])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_receipt_and_status() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(2, "2000-01-01");
+        let base_pubkey = setup.base_keypair.pubkey();
+        let lamports = setup.account(1).lamports;
+        let rebased_keypair = Keypair::new();
+        let rebased = setup.dir.write_keypair("rebased.json", &rebased_keypair);
+        let receipt_path = setup.path("receipt.json");
+        setup
+            .run(&[
+                "rebase",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &base_pubkey.to_string(),
+                &rebased,
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--num-accounts",
+                "2",
+                "--receipt",
+                &receipt_path,
+                "--commitment",
+                "recent",
+            ])
+            .unwrap();
+        let receipt: Receipt = load_state(&receipt_path).unwrap();
+        assert_eq!(receipt.entries.len(), 2);
+        let entry = &receipt.entries[1];
+        assert_eq!(entry.index, 1);
+        assert_eq!(entry.source, setup.address(&base_pubkey, 1).to_string());
+        assert_eq!(
+            entry.destination,
+            Some(setup.address(&rebased_keypair.pubkey(), 1).to_string())
+        );
+        assert_eq!(entry.lamports, lamports);
+        assert_eq!(entry.instructions.last().unwrap(), "split");
+        assert_eq!(entry.status, ConfirmationStatus::Confirmed);
+
+        setup.run(&["status", &receipt_path]).unwrap();
+        let receipt: Receipt = load_state(&receipt_path).unwrap();
+        assert!(receipt
+            .entries
+            .iter()
+            .all(|entry| entry.status == ConfirmationStatus::Finalized));
+
+        // With --finalized, transactions are recorded once final.
+        let finalized_receipt_path = setup.path("finalized_receipt.json");
+        let rebased_again = setup
+            .dir
+            .write_keypair("rebased_again.json", &Keypair::new());
+        setup
+            .run(&[
+                "rebase",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &rebased_keypair.pubkey().to_string(),
+                &rebased_again,
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--num-accounts",
+                "2",
+                "--receipt",
+                &finalized_receipt_path,
+                "--finalized",
+            ])
+            .unwrap();
+        let receipt: Receipt = load_state(&finalized_receipt_path).unwrap();
+        assert_eq!(receipt.entries.len(), 2);
+        assert!(receipt
+            .entries
+            .iter()
+            .all(|entry| entry.status == ConfirmationStatus::Finalized));
+    }
+
+    #[test]
+    fn test_distribute_resumes() {
+        let setup = TestSetup::new(&[]);
+        let alice = Pubkey::new_rand();
+        let bob = Pubkey::new_rand();
+        let allocations = setup.dir.write_file(
+            "allocations.csv",
+            &format!("recipient,amount\n{},5\n{},6\n", alice, bob),
+        );
+        let db_path = setup.path("db.json");
+        let args = [
+            "distribute",
+            "--fee-payer",
+            &setup.path("fee_payer.json"),
+            &setup.path("sender.json"),
+            &setup.path("base.json"),
+            "--allocations",
+            &allocations,
+            "--db-path",
+            &db_path,
+        ];
+        setup.run(&args).unwrap();
+        assert_eq!(setup.staker(0), alice);
+        assert_eq!(setup.staker(1), bob);
+
+        // A second run finds everything sent and pays no one twice.
+        setup.run(&args).unwrap();
+        assert_eq!(setup.account(1).lamports, sol_to_lamports(6.0));
+        let db: DistributionDb = load_state(&db_path).unwrap();
+        assert_eq!(db.sent.len(), 2);
+    }
+
+    #[test]
+    fn test_vest_and_clawback() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(3, "2100-01-01");
+        // Vesting again skips the tranches already created.
+        setup.vest(3, "2100-01-01");
+        for i in 0..3 {
+            assert_eq!(setup.account(i).lamports, sol_to_lamports(10.0));
+        }
+
+        let treasury_pubkey = Pubkey::new_rand();
+        setup
+            .run(&[
+                "clawback",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.base_keypair.pubkey().to_string(),
+                "--withdraw-authority",
+                &setup.path("withdraw_authority.json"),
+                "--custodian",
+                &setup.path("custodian.json"),
+                "--treasury",
+                &treasury_pubkey.to_string(),
+                "--num-accounts",
+                "3",
+            ])
+            .unwrap();
+        assert_eq!(
+            setup.client.get_balance(&treasury_pubkey).unwrap(),
+            sol_to_lamports(30.0)
+        );
+    }
+
+    #[test]
+    fn test_delegate_and_rebalance() {
+        let vote_pubkeys = [Pubkey::new_rand(), Pubkey::new_rand()];
+        let setup = TestSetup::new(&vote_pubkeys);
+        setup.vest(2, "2000-01-01");
+        let weights = setup.dir.write_file(
+            "weights.csv",
+            &format!(
+                "vote_account,weight\n{},1\n{},1\n",
+                vote_pubkeys[0], vote_pubkeys[1]
+            ),
+        );
+        setup
+            .run(&[
+                "delegate",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.path("base.json"),
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--weights",
+                &weights,
+                "--num-accounts",
+                "2",
+            ])
+            .unwrap();
+        let voter = |i| {
+            StakeState::stake_from(&setup.account(i))
+                .unwrap()
+                .delegation
+                .voter_pubkey
+        };
+        let mut voters = vec![voter(0), voter(1)];
+        voters.sort();
+        let mut expected = vote_pubkeys.to_vec();
+        expected.sort();
+        assert_eq!(voters, expected);
+
+        // Moving all the weight to one vote account deactivates the other's stake.
+        let weights = setup.dir.write_file(
+            "weights.csv",
+            &format!("vote_account,weight\n{},1\n", vote_pubkeys[0]),
+        );
+        let state_path = setup.path("rebalance.json");
+        setup
+            .run(&[
+                "rebalance",
+                "--fee-payer",
+                &setup.path("fee_payer.json"),
+                &setup.path("base.json"),
+                "--stake-authority",
+                &setup.path("stake_authority.json"),
+                "--weights",
+                &weights,
+                "--state-file",
+                &state_path,
+                "--num-accounts",
+                "2",
+            ])
+            .unwrap();
+        let i = if voter(0) == vote_pubkeys[1] { 0 } else { 1 };
+        let stake = StakeState::stake_from(&setup.account(i)).unwrap();
+        assert_ne!(stake.delegation.deactivation_epoch, u64::MAX);
+        let state: RebalanceState = load_state(&state_path).unwrap();
+        assert_eq!(state.deactivations.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_run_plan() {
+        let setup = TestSetup::new(&[]);
+        setup.vest(1, "2000-01-01");
+        let recipient = Pubkey::new_rand();
+        let yaml = format!(
+            "
+base_pubkey: {}
+steps:
+  - name: withdraw
+    index: 0
+    action: withdraw
+    recipient: {}
+    amount: 1
+  - name: deactivate
+    index: 0
+    action: deactivate
+    after: [withdraw]
+",
+            setup.base_keypair.pubkey(),
+            recipient
+        );
+        let plan = read_plan(yaml.as_bytes()).unwrap();
+        let state_path = setup.path("progress.json");
+        let mut progress = PlanProgress::default();
+        let waiting = poll_plan(
+            &setup.client,
+            &plan,
+            &mut progress,
+            &state_path,
+            &setup.fee_payer_keypair,
+            Some(&setup.stake_authority_keypair as &dyn Signer),
+            Some(&setup.withdraw_authority_keypair as &dyn Signer),
+            &mut None,
+        )
+        .unwrap();
+        assert!(waiting.is_empty());
+        assert_eq!(
+            setup.client.get_balance(&recipient).unwrap(),
+            sol_to_lamports(1.0)
+        );
+
+        // The undelegated account has nothing to deactivate.
+        assert!(progress.is_done("withdraw"));
+        assert_eq!(progress.done[1].signature, None);
+        assert!(progress.sent.is_empty());
+        assert_eq!(load_state::<PlanProgress>(&state_path).unwrap(), progress);
+
+        // After a restart, a sent withdrawal that landed is done without
+        // being sent again, and one not yet seen is waited on.
+        let withdraw_signature = progress.done[0].signature.clone().unwrap();
+        let yaml = yaml.replace("name: withdraw", "name: withdraw-again");
+        let yaml = yaml.replace("after: [withdraw]", "after: [withdraw-again]");
+        let plan = read_plan(yaml.as_bytes()).unwrap();
+        let mut progress = PlanProgress {
+            done: vec![],
+            sent: vec![SentStep {
+                name: "withdraw-again".to_string(),
+                signature: withdraw_signature.clone(),
+                slot: 0,
+            }],
+        };
+        let poll = |progress: &mut PlanProgress| {
+            poll_plan(
+                &setup.client,
+                &plan,
+                progress,
+                &state_path,
+                &setup.fee_payer_keypair,
+                Some(&setup.stake_authority_keypair as &dyn Signer),
+                Some(&setup.withdraw_authority_keypair as &dyn Signer),
+                &mut None,
+            )
+            .unwrap()
+        };
+        assert!(poll(&mut progress).is_empty());
+        assert_eq!(progress.done[0].signature, Some(withdraw_signature));
+        assert!(progress.sent.is_empty());
+        assert_eq!(
+            setup.client.get_balance(&recipient).unwrap(),
+            sol_to_lamports(1.0)
+        );
+
+        let mut progress = PlanProgress {
+            done: vec![],
+            sent: vec![SentStep {
+                name: "withdraw-again".to_string(),
+                signature: Signature::default().to_string(),
+                slot: 0,
+            }],
+        };
+        let waiting = poll(&mut progress);
+        assert_eq!(waiting.len(), 2);
+        assert!(waiting[0].1.starts_with("waiting for transaction"));
+        assert!(progress.done.is_empty());
+    }
+
+    /// An initialized nonce account, holding a blockhash the bank has never
+    /// seen, as it would be long after planning.
+    fn create_nonce_account(pubkey: &Pubkey, authority: &Pubkey) -> Account {
+        let data = nonce::state::Data {
+            authority: *authority,
+            blockhash: hash(pubkey.as_ref()),
+            fee_calculator: FeeCalculator::default(),
+        };
+        let versions = nonce::state::Versions::new_current(nonce::State::Initialized(data));
+        Account::new_data(sol_to_lamports(1.0), &versions, &system_program::id()).unwrap()
+    }
+
+    #[test]
+    fn test_plan_sign_and_submit() {
+        let nonce_authority_keypair = Keypair::new();
+        let nonce_pubkeys = [Pubkey::new_rand(), Pubkey::new_rand()];
+        let nonce_accounts: Vec<_> = nonce_pubkeys
+            .iter()
+            .map(|pubkey| {
+                let account = create_nonce_account(pubkey, &nonce_authority_keypair.pubkey());
+                (*pubkey, account)
+            })
+            .collect();
+        let setup = TestSetup::with_accounts(&[], &nonce_accounts);
+        setup.vest(2, "2000-01-01");
+        let new_base_keypair = Keypair::new();
+        let new_base = setup.dir.write_keypair("new_base.json", &new_base_keypair);
+        let nonce_authority = setup
+            .dir
+            .write_keypair("nonce_authority.json", &nonce_authority_keypair);
+        let plan_path = setup.path("move.json");
+        let fee_payer = setup.fee_payer_keypair.pubkey().to_string();
+        let base = setup.base_keypair.pubkey().to_string();
+        let new_base_pubkey = new_base_keypair.pubkey().to_string();
+        let stake_authority = setup.stake_authority_keypair.pubkey().to_string();
+        let withdraw_authority = setup.withdraw_authority_keypair.pubkey().to_string();
+        let new_authority = Pubkey::new_rand().to_string();
+        let nonce_authority_pubkey = nonce_authority_keypair.pubkey().to_string();
+        let nonces: Vec<_> = nonce_pubkeys
+            .iter()
+            .map(|pubkey| pubkey.to_string())
+            .collect();
+        let plan = |nonces: &[String]| {
+            let mut args = vec![
+                "plan",
+                "move",
+                "--fee-payer",
+                &fee_payer,
+                &base,
+                &new_base_pubkey,
+                "--stake-authority",
+                &stake_authority,
+                "--withdraw-authority",
+                &withdraw_authority,
+                "--new-stake-authority",
+                &new_authority,
+                "--new-withdraw-authority",
+                &new_authority,
+                "--num-accounts",
+                "2",
+                "--output",
+                &plan_path,
+                "--nonce-authority",
+                &nonce_authority_pubkey,
+                "--nonce",
+            ];
+            args.extend(nonces.iter().map(|nonce| nonce.as_str()));
+            setup.run(&args)
+        };
+
+        // Each transaction needs a nonce of its own.
+        assert!(plan(&nonces[..1]).is_err());
+        assert!(plan(&[nonces[0].clone(), nonces[0].clone()]).is_err());
+        plan(&nonces).unwrap();
+        let plan = read_transaction_plan_file(&plan_path).unwrap();
+        assert_eq!(plan.transactions.len(), 2);
+        assert_eq!(plan.transactions[1].nonce_account, nonces[1]);
+        assert_eq!(plan.transactions[0].signers.len(), 5);
+
+        // Each key holder signs in turn, and nothing is sent until all have.
+        setup.run(&["sign", &plan_path, &new_base]).unwrap();
+        setup
+            .run(&["sign", &plan_path, &setup.path("stake_authority.json")])
+            .unwrap();
+        assert!(setup
+            .run(&["sign", &plan_path, &setup.path("base.json")])
+            .is_err());
+        match setup.run(&["submit", &plan_path]) {
+            Err(Error::Account { index, phase, .. }) => {
+                assert_eq!(index, 0);
+                assert_eq!(phase, Phase::Sign);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+        setup
+            .run(&["sign", &plan_path, &setup.path("withdraw_authority.json")])
+            .unwrap();
+        setup
+            .run(&["sign", &plan_path, &setup.path("fee_payer.json")])
+            .unwrap();
+        setup.run(&["sign", &plan_path, &nonce_authority]).unwrap();
+
+        // A plan made against a different balance is refused.
+        let mut changed_plan = read_transaction_plan_file(&plan_path).unwrap();
+        changed_plan.transactions[1].lamports += 1;
+        let changed_path = setup.path("changed.json");
+        save_state(&changed_path, &changed_plan).unwrap();
+        match setup.run(&["submit", &changed_path]) {
+            Err(Error::Account { index, phase, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(phase, Phase::Plan);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+
+        // So is one whose nonce has since been advanced.
+        let mut changed_plan = read_transaction_plan_file(&plan_path).unwrap();
+        changed_plan.transactions[1].nonce_account = nonces[0].clone();
+        save_state(&changed_path, &changed_plan).unwrap();
+        match setup.run(&["submit", &changed_path]) {
+            Err(Error::Account { index, phase, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(phase, Phase::Plan);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map_err(|err| err.to_string())
+            ),
+        }
+        assert_eq!(setup.account(0).lamports, sol_to_lamports(15.0));
+
+        setup.run(&["submit", &plan_path]).unwrap();
+        for i in 0..2 {
+            let address = setup.address(&new_base_keypair.pubkey(), i);
+            assert_eq!(
+                setup.client.get_balance(&address).unwrap(),
+                sol_to_lamports(15.0)
+            );
         }
     }
-    Ok(())
 }
@@ -0,0 +1,111 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+pub const PROFILE_FILE_NAME: &str = "stake-accounts.yml";
+
+/// How the seed of each derived stake account is chosen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SeedScheme {
+    /// The seed is the account's index, i.e. "0", "1", "2", ...
+    Index,
+}
+
+impl SeedScheme {
+    /// Every scheme, in the order `locate` tries them.
+    pub const ALL: [SeedScheme; 1] = [SeedScheme::Index];
+
+    /// Return the seed of the derived stake account at `index`.
+    pub fn seed(self, index: usize) -> String {
+        match self {
+            SeedScheme::Index => index.to_string(),
+        }
+    }
+}
+
+impl Default for SeedScheme {
+    fn default() -> Self {
+        SeedScheme::Index
+    }
+}
+
+/// A named group of stake accounts and the defaults used to manage them.
+/// Any value given on the command line overrides the profile's.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Profile {
+    pub base_pubkey: Option<String>,
+    pub seed_scheme: SeedScheme,
+    pub stake_authority: Option<String>,
+    pub withdraw_authority: Option<String>,
+    pub new_stake_authority: Option<String>,
+    pub new_withdraw_authority: Option<String>,
+    pub custodian: Option<String>,
+    pub fee_payer: Option<String>,
+    pub num_accounts: Option<usize>,
+    pub url: Option<String>,
+    /// Further RPC nodes to fail over to.
+    pub urls: Vec<String>,
+}
+
+/// Return the path of the profile file that sits next to the given Solana CLI config file.
+pub(crate) fn default_profile_file(config_file: &str) -> String {
+    Path::new(config_file)
+        .with_file_name(PROFILE_FILE_NAME)
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub(crate) fn load_profiles(path: &str) -> Result<HashMap<String, Profile>, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    serde_yaml::from_reader(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+pub(crate) fn load_profile(path: &str, name: &str) -> Result<Profile, Error> {
+    let mut profiles = load_profiles(path)?;
+    profiles
+        .remove(name)
+        .ok_or_else(|| Error::Config(format!("{}: no profile named '{}'", path, name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let yaml = "
+treasury:
+  base_pubkey: 5HjNj8bBv1yxqCmWVHdzRo7RrbS5YJCVXbXyW4k8xjXY
+  stake_authority: ~/.config/solana/stake-authority.json
+  withdraw_authority: usb://ledger
+  num_accounts: 12
+grants:
+  seed_scheme: index
+";
+        let profiles: HashMap<String, Profile> = serde_yaml::from_str(yaml).unwrap();
+        let treasury = &profiles["treasury"];
+        assert_eq!(treasury.seed_scheme, SeedScheme::Index);
+        assert_eq!(
+            treasury.withdraw_authority,
+            Some("usb://ledger".to_string())
+        );
+        assert_eq!(treasury.num_accounts, Some(12));
+        assert_eq!(treasury.fee_payer, None);
+        assert_eq!(profiles["grants"], Profile::default());
+
+        let yaml = "treasury:\n  base: 5HjNj8bBv1yxqCmWVHdzRo7RrbS5YJCVXbXyW4k8xjXY\n";
+        assert!(serde_yaml::from_str::<HashMap<String, Profile>>(yaml).is_err());
+    }
+
+    #[test]
+    fn test_default_profile_file() {
+        assert_eq!(
+            default_profile_file("/home/sol/.config/solana/cli/config.yml"),
+            "/home/sol/.config/solana/cli/stake-accounts.yml"
+        );
+    }
+}
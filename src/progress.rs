@@ -0,0 +1,272 @@
+use crate::client::Client;
+use crate::error::Error;
+use crate::receipt::ReceiptFile;
+use indicatif::{ProgressBar, ProgressStyle};
+use solana_sdk::{
+    fee_calculator::FeeCalculator, message::Message, native_token::lamports_to_sol, pubkey::Pubkey,
+};
+use std::time::{Duration, Instant};
+
+/// How often to log progress when stdout is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Outcome {
+    index: usize,
+    address: Pubkey,
+    fee: u64,
+    lamports: u64,
+    /// The signature, None if the message was already applied, or the
+    /// reason it failed.
+    result: Result<Option<String>, String>,
+}
+
+/// Tracks a batch of transactions, displaying progress as each one is sent,
+/// and a summary once the batch finishes or fails. Each transaction is also
+/// added to the receipt, if there is one.
+pub(crate) struct Batch<'a, C> {
+    client: &'a C,
+    receipt: Option<ReceiptFile>,
+    bar: Option<ProgressBar>,
+    fee_calculator: FeeCalculator,
+    total: usize,
+    start: Instant,
+    last_log: Instant,
+    outcomes: Vec<Outcome>,
+}
+
+impl<'a, C: Client> Batch<'a, C> {
+    /// Display progress as a bar if stdout is a terminal, otherwise as log
+    /// lines. Entries are appended to the receipt at `receipt_path`, if given.
+    pub fn new(client: &'a C, total: usize, receipt_path: Option<&str>) -> Result<Self, Error> {
+        let (_, fee_calculator) = client.get_recent_blockhash().map_err(Error::fetch)?;
+        let receipt = receipt_path.map(ReceiptFile::open).transpose()?;
+        let terminal = atty::is(atty::Stream::Stdout);
+        Ok(Self::with_terminal(
+            client,
+            total,
+            fee_calculator,
+            receipt,
+            terminal,
+        ))
+    }
+
+    pub fn with_terminal(
+        client: &'a C,
+        total: usize,
+        fee_calculator: FeeCalculator,
+        receipt: Option<ReceiptFile>,
+        terminal: bool,
+    ) -> Self {
+        let bar = if terminal {
+            let bar = ProgressBar::new(total as u64);
+            bar.set_style(ProgressStyle::default_bar().template("{bar:40} {pos}/{len} {msg}"));
+            Some(bar)
+        } else {
+            None
+        };
+        let start = Instant::now();
+        Self {
+            client,
+            receipt,
+            bar,
+            fee_calculator,
+            total,
+            start,
+            last_log: start,
+            outcomes: vec![],
+        }
+    }
+
+    /// Print a line without disturbing the progress bar.
+    pub fn println(&self, line: &str) {
+        match &self.bar {
+            Some(bar) => bar.println(line),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Record the result of sending `message`, which moves `lamports`, on
+    /// behalf of the stake account at `index`. On failure the summary is
+    /// printed before the error is returned.
+    pub fn record(
+        &mut self,
+        index: usize,
+        address: &Pubkey,
+        message: &Message,
+        lamports: u64,
+        result: Result<Option<String>, Error>,
+    ) -> Result<Option<String>, Error> {
+        let (fee, moved) = match &result {
+            Ok(Some(_)) => (self.fee_calculator.calculate_fee(message), lamports),
+            _ => (0, 0),
+        };
+        let outcome = match &result {
+            Ok(signature) => Ok(signature.clone()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Some(receipt) = &mut self.receipt {
+            receipt.record(self.client, index, address, message, outcome.clone())?;
+        }
+        self.outcomes.push(Outcome {
+            index,
+            address: *address,
+            fee,
+            lamports: moved,
+            result: outcome,
+        });
+        let signature = match result {
+            Ok(signature) => signature,
+            Err(err) => {
+                self.print_summary();
+                return Err(err);
+            }
+        };
+        let status = self.status();
+        match &self.bar {
+            Some(bar) => {
+                bar.set_position(self.outcomes.len() as u64);
+                bar.set_message(&status);
+            }
+            None => {
+                if self.last_log.elapsed() >= LOG_INTERVAL {
+                    println!("progress: {}", status);
+                    self.last_log = Instant::now();
+                }
+            }
+        }
+        Ok(signature)
+    }
+
+    /// Print the summary once every transaction is sent.
+    pub fn finish(self) {
+        self.print_summary();
+    }
+
+    fn num_failed(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .count()
+    }
+
+    fn status(&self) -> String {
+        let num_done = self.outcomes.len();
+        let num_pending = self.total - num_done;
+        let mut status = format!(
+            "{} completed, {} failed, {} pending",
+            num_done - self.num_failed(),
+            self.num_failed(),
+            num_pending
+        );
+        if num_done > 0 && num_pending > 0 {
+            let eta = self.start.elapsed() * num_pending as u32 / num_done as u32;
+            status += &format!(", eta {}s", eta.as_secs());
+        }
+        status
+    }
+
+    fn summary(&self) -> String {
+        let num_applied = self
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.result == Ok(None))
+            .count();
+        let num_failed = self.num_failed();
+        let fees: u64 = self.outcomes.iter().map(|outcome| outcome.fee).sum();
+        let lamports: u64 = self.outcomes.iter().map(|outcome| outcome.lamports).sum();
+        let mut summary = format!(
+            "{:<10} {:>6}\n{:<10} {:>6}\n{:<10} {:>6}\n{:<10} {:>6}\n{:<10} {} SOL\n{:<10} {} SOL\n",
+            "Succeeded",
+            self.outcomes.len() - num_failed,
+            "Applied",
+            num_applied,
+            "Failed",
+            num_failed,
+            "Pending",
+            self.total - self.outcomes.len(),
+            "Fees",
+            lamports_to_sol(fees),
+            "Moved",
+            lamports_to_sol(lamports),
+        );
+        for outcome in &self.outcomes {
+            if let Err(reason) = &outcome.result {
+                summary += &format!(
+                    "Failed {:<5} {:<44} {}\n",
+                    outcome.index, outcome.address, reason
+                );
+            }
+        }
+        summary
+    }
+
+    fn print_summary(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        print!("{}", self.summary());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receipt::{ConfirmationStatus, Receipt};
+    use crate::state_file::load_state;
+    use solana_runtime::{bank::Bank, bank_client::BankClient};
+    use solana_sdk::{genesis_config::create_genesis_config, system_instruction};
+    use std::{env, fs};
+
+    #[test]
+    fn test_batch_summary() {
+        let (genesis_config, _) = create_genesis_config(1);
+        let client = BankClient::new(Bank::new(&genesis_config));
+        let fee_calculator = FeeCalculator::new(5);
+        let receipt_path = env::temp_dir()
+            .join(format!("receipt-{}.json", Pubkey::new_rand()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let receipt = ReceiptFile::open(&receipt_path).unwrap();
+        let mut batch = Batch::with_terminal(&client, 4, fee_calculator, Some(receipt), false);
+        let from_pubkey = Pubkey::new_rand();
+        let instruction = system_instruction::transfer(&from_pubkey, &Pubkey::new_rand(), 1);
+        let message = Message::new_with_payer(&[instruction], Some(&from_pubkey));
+        let address = Pubkey::new_rand();
+
+        let result = batch.record(0, &address, &message, 100, Ok(Some("sig".to_string())));
+        assert_eq!(result.unwrap(), Some("sig".to_string()));
+        batch.record(1, &address, &message, 200, Ok(None)).unwrap();
+        assert_eq!(batch.status(), "2 completed, 0 failed, 2 pending, eta 0s");
+
+        let err = Error::Config("bad".to_string());
+        assert!(batch.record(2, &address, &message, 300, Err(err)).is_err());
+        let summary = batch.summary();
+        assert!(summary.starts_with("Succeeded       2\nApplied         1\nFailed          1\n"));
+        assert!(summary.contains("Pending         1\n"));
+        assert!(summary.contains("Fees       0.000000005 SOL\n"));
+        // Messages found already applied moved nothing.
+        assert!(summary.contains("Moved      0.0000001 SOL\n"));
+        assert!(summary.contains(&format!(
+            "Failed 2     {:<44} invalid configuration: bad",
+            address
+        )));
+
+        // The failure is in the receipt too.
+        let receipt: Receipt = load_state(&receipt_path).unwrap();
+        fs::remove_file(&receipt_path).unwrap();
+        let statuses: Vec<_> = receipt.entries.iter().map(|entry| entry.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ConfirmationStatus::Confirmed,
+                ConfirmationStatus::Applied,
+                ConfirmationStatus::Failed
+            ]
+        );
+        assert_eq!(
+            receipt.entries[2].error,
+            Some("invalid configuration: bad".to_string())
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use crate::delegate::{DelegateAction, StakeAccountInfo, Target};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    account::Account, clock::Epoch, native_token::lamports_to_sol, pubkey::Pubkey,
+    sysvar::stake_history::StakeHistory,
+};
+use solana_stake_program::stake_state::StakeState;
+use std::fmt;
+use std::u64;
+
+/// A transaction that moves the derived accounts toward the target weights.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum RebalanceAction {
+    /// Deactivate all of the account at `index`.
+    Deactivate {
+        index: usize,
+    },
+    /// Split `lamports` from the account at `index` into a new account at
+    /// `new_index`, and deactivate the new account.
+    SplitAndDeactivate {
+        index: usize,
+        new_index: usize,
+        lamports: u64,
+    },
+    Delegate {
+        index: usize,
+        vote_pubkey: String,
+    },
+    SplitAndDelegate {
+        index: usize,
+        new_index: usize,
+        lamports: u64,
+        vote_pubkey: String,
+    },
+}
+
+impl From<&DelegateAction> for RebalanceAction {
+    fn from(action: &DelegateAction) -> Self {
+        match action {
+            DelegateAction::Delegate { index, vote_pubkey } => RebalanceAction::Delegate {
+                index: *index,
+                vote_pubkey: vote_pubkey.to_string(),
+            },
+            DelegateAction::SplitAndDelegate {
+                index,
+                new_index,
+                lamports,
+                vote_pubkey,
+            } => RebalanceAction::SplitAndDelegate {
+                index: *index,
+                new_index: *new_index,
+                lamports: *lamports,
+                vote_pubkey: vote_pubkey.to_string(),
+            },
+        }
+    }
+}
+
+impl RebalanceAction {
+    /// The index of the account the action's transaction is sent on behalf of.
+    pub fn index(&self) -> usize {
+        match self {
+            RebalanceAction::Deactivate { index }
+            | RebalanceAction::SplitAndDeactivate { index, .. }
+            | RebalanceAction::Delegate { index, .. }
+            | RebalanceAction::SplitAndDelegate { index, .. } => *index,
+        }
+    }
+
+    /// The index of the account the action creates, if any.
+    pub fn new_index(&self) -> Option<usize> {
+        match self {
+            RebalanceAction::SplitAndDeactivate { new_index, .. }
+            | RebalanceAction::SplitAndDelegate { new_index, .. } => Some(*new_index),
+            _ => None,
+        }
+    }
+
+    /// The lamports the action splits into a new account, or 0 if it splits none.
+    pub fn split_lamports(&self) -> u64 {
+        match self {
+            RebalanceAction::SplitAndDeactivate { lamports, .. }
+            | RebalanceAction::SplitAndDelegate { lamports, .. } => *lamports,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for RebalanceAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RebalanceAction::Deactivate { index } => write!(f, "Deactivate account {}", index),
+            RebalanceAction::SplitAndDeactivate {
+                index,
+                new_index,
+                lamports,
+            } => write!(
+                f,
+                "Split {} SOL from account {} into account {} and deactivate it",
+                lamports_to_sol(*lamports),
+                index,
+                new_index
+            ),
+            RebalanceAction::Delegate { index, vote_pubkey } => {
+                write!(f, "Delegate account {} to {}", index, vote_pubkey)
+            }
+            RebalanceAction::SplitAndDelegate {
+                index,
+                new_index,
+                lamports,
+                vote_pubkey,
+            } => write!(
+                f,
+                "Split {} SOL from account {} into account {} and delegate it to {}",
+                lamports_to_sol(*lamports),
+                index,
+                new_index,
+                vote_pubkey
+            ),
+        }
+    }
+}
+
+/// An action and the signature of its transaction, None until it is sent.
+/// `applied` is set instead when a retry found the action already applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct RebalanceStep {
+    #[serde(flatten)]
+    pub action: RebalanceAction,
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub applied: bool,
+}
+
+impl RebalanceStep {
+    pub fn is_done(&self) -> bool {
+        self.signature.is_some() || self.applied
+    }
+}
+
+impl From<RebalanceAction> for RebalanceStep {
+    fn from(action: RebalanceAction) -> Self {
+        Self {
+            action,
+            signature: None,
+            applied: false,
+        }
+    }
+}
+
+/// The progress of a rebalance, saved between runs. Deactivations are planned
+/// on the first run, and redelegations on the first run after all the
+/// deactivated stake is inactive.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct RebalanceState {
+    pub base_pubkey: String,
+    pub deactivations: Option<Vec<RebalanceStep>>,
+    pub redelegations: Option<Vec<RebalanceStep>>,
+}
+
+/// Describe a derived stake account for planning. Stake that has been
+/// deactivated counts as undelegated, since it can be redelegated once
+/// inactive. Returns None if the account isn't a stake account.
+pub(crate) fn stake_account_info(index: usize, account: &Account) -> Option<StakeAccountInfo> {
+    let voter_pubkey = match StakeState::from(account)? {
+        StakeState::Initialized(_) => None,
+        StakeState::Stake(_, stake) => Some(stake.delegation)
+            .filter(|delegation| delegation.deactivation_epoch == u64::MAX)
+            .map(|delegation| delegation.voter_pubkey),
+        _ => return None,
+    };
+    Some(StakeAccountInfo {
+        index,
+        lamports: account.lamports,
+        voter_pubkey,
+    })
+}
+
+/// Return true if the account's stake has been deactivated but is not yet inactive.
+pub(crate) fn is_cooling_down(
+    account: &Account,
+    epoch: Epoch,
+    stake_history: &StakeHistory,
+) -> bool {
+    match StakeState::stake_from(account) {
+        Some(stake) => {
+            let delegation = &stake.delegation;
+            let (effective, activating, _) =
+                delegation.stake_activating_and_deactivating(epoch, Some(stake_history));
+            delegation.deactivation_epoch != u64::MAX && effective + activating > 0
+        }
+        None => false,
+    }
+}
+
+/// Deactivate the stake each vote account holds beyond its share of the
+/// lamports in all the accounts, or all of it if the vote account isn't a
+/// target. Whole accounts that fit in the excess are deactivated first, largest
+/// first; any excess left is split from an account that can keep at least
+/// `min_lamports`, into a new account at the next free index.
+pub(crate) fn plan_deactivations(
+    accounts: &[StakeAccountInfo],
+    targets: &[Target],
+    mut next_index: usize,
+    min_lamports: u64,
+) -> Vec<RebalanceAction> {
+    let total_lamports: u64 = accounts.iter().map(|account| account.lamports).sum();
+    let total_weight: f64 = targets.iter().map(|target| target.weight).sum();
+    let goal = |vote_pubkey: &Pubkey| {
+        targets
+            .iter()
+            .find(|target| target.vote_pubkey == *vote_pubkey)
+            .map(|target| (total_lamports as f64 * target.weight / total_weight) as u64)
+            .unwrap_or(0)
+    };
+
+    let mut voters: Vec<(Pubkey, Vec<&StakeAccountInfo>)> = vec![];
+    for account in accounts {
+        if let Some(voter_pubkey) = &account.voter_pubkey {
+            match voters.iter_mut().find(|(pubkey, _)| pubkey == voter_pubkey) {
+                Some((_, delegated)) => delegated.push(account),
+                None => voters.push((*voter_pubkey, vec![account])),
+            }
+        }
+    }
+
+    let mut actions = vec![];
+    for (voter_pubkey, mut delegated) in voters {
+        let achieved: u64 = delegated.iter().map(|account| account.lamports).sum();
+        let mut excess = achieved.saturating_sub(goal(&voter_pubkey));
+        delegated.sort_by(|a, b| b.lamports.cmp(&a.lamports).then(a.index.cmp(&b.index)));
+        let mut remaining = vec![];
+        for account in delegated {
+            if account.lamports <= excess {
+                actions.push(RebalanceAction::Deactivate {
+                    index: account.index,
+                });
+                excess -= account.lamports;
+            } else {
+                remaining.push(account);
+            }
+        }
+        if excess >= min_lamports {
+            if let Some(account) = remaining
+                .iter()
+                .rev()
+                .find(|account| account.lamports >= excess + min_lamports)
+            {
+                actions.push(RebalanceAction::SplitAndDeactivate {
+                    index: account.index,
+                    new_index: next_index,
+                    lamports: excess,
+                });
+                next_index += 1;
+            }
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(index: usize, lamports: u64, voter_pubkey: Option<Pubkey>) -> StakeAccountInfo {
+        StakeAccountInfo {
+            index,
+            lamports,
+            voter_pubkey,
+        }
+    }
+
+    #[test]
+    fn test_plan_deactivations() {
+        let vote0 = Pubkey::new_rand();
+        let vote1 = Pubkey::new_rand();
+        let targets = vec![
+            Target {
+                vote_pubkey: vote0,
+                weight: 1.0,
+            },
+            Target {
+                vote_pubkey: vote1,
+                weight: 1.0,
+            },
+        ];
+
+        // Of 200 lamports, vote0 should have 100, so its 50 lamport account is
+        // deactivated whole. Stake with a vote account that isn't a target is
+        // all deactivated.
+        let accounts = vec![
+            info(0, 100, Some(vote0)),
+            info(1, 50, Some(vote0)),
+            info(2, 30, Some(Pubkey::new_rand())),
+            info(3, 20, None),
+        ];
+        assert_eq!(
+            plan_deactivations(&accounts, &targets, 4, 10),
+            vec![
+                RebalanceAction::Deactivate { index: 1 },
+                RebalanceAction::Deactivate { index: 2 },
+            ]
+        );
+
+        // With 210 lamports, vote0's excess of 55 fits no account, so it is
+        // split from the smallest account that can spare it.
+        let mut accounts = accounts;
+        accounts[1].lamports = 60;
+        assert_eq!(
+            plan_deactivations(&accounts, &targets, 4, 10)[0],
+            RebalanceAction::SplitAndDeactivate {
+                index: 0,
+                new_index: 4,
+                lamports: 55
+            }
+        );
+
+        // Excess too small to split off is left alone.
+        assert_eq!(plan_deactivations(&accounts, &targets, 4, 60).len(), 1);
+    }
+}
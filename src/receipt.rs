@@ -0,0 +1,257 @@
+use crate::client::Client;
+use crate::error::{describe_instruction, Error};
+use crate::state_file::{load_state, save_state};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    clock::Slot, commitment_config::CommitmentConfig, message::Message, pubkey::Pubkey,
+    signature::Signature, system_instruction::SystemInstruction, system_program,
+};
+use solana_stake_program::stake_instruction::StakeInstruction;
+use std::fmt;
+use std::str::FromStr;
+
+/// How far a transaction in a receipt is known to have been confirmed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ConfirmationStatus {
+    /// Nothing was sent, since the account already showed the effect.
+    Applied,
+    Confirmed,
+    Finalized,
+    /// The transaction was found with an error.
+    Failed,
+    /// The cluster has no record of the transaction.
+    Missing,
+}
+
+impl fmt::Display for ConfirmationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ConfirmationStatus::Applied => "already applied",
+            ConfirmationStatus::Confirmed => "confirmed",
+            ConfirmationStatus::Finalized => "finalized",
+            ConfirmationStatus::Failed => "failed",
+            ConfirmationStatus::Missing => "missing",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A transaction sent on behalf of the stake account at `index`. Lamports move
+/// from `source` to `destination`; a transaction that moves none has no
+/// destination, and its source is the stake account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ReceiptEntry {
+    pub index: usize,
+    pub source: String,
+    pub destination: Option<String>,
+    pub instructions: Vec<String>,
+    pub lamports: u64,
+    pub signature: Option<String>,
+    pub slot: Slot,
+    pub status: ConfirmationStatus,
+    /// Why the transaction failed to send, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The receipt file, in JSON.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct Receipt {
+    pub entries: Vec<ReceiptEntry>,
+}
+
+// Return the source, destination and lamports of the first instruction that
+// moves lamports.
+fn find_transfer(message: &Message) -> Option<(Pubkey, Pubkey, u64)> {
+    message.instructions.iter().find_map(|instruction| {
+        let program_id = instruction.program_id(&message.account_keys);
+        let lamports = if system_program::check_id(program_id) {
+            match bincode::deserialize(&instruction.data).ok()? {
+                SystemInstruction::CreateAccount { lamports, .. }
+                | SystemInstruction::CreateAccountWithSeed { lamports, .. }
+                | SystemInstruction::Transfer { lamports } => lamports,
+                _ => return None,
+            }
+        } else if solana_stake_program::check_id(program_id) {
+            match bincode::deserialize(&instruction.data).ok()? {
+                StakeInstruction::Split(lamports) | StakeInstruction::Withdraw(lamports) => {
+                    lamports
+                }
+                _ => return None,
+            }
+        } else {
+            return None;
+        };
+        if lamports == 0 {
+            return None;
+        }
+        let source = message.account_keys[*instruction.accounts.get(0)? as usize];
+        let destination = message.account_keys[*instruction.accounts.get(1)? as usize];
+        Some((source, destination, lamports))
+    })
+}
+
+impl ReceiptEntry {
+    /// Describe `message`, sent on behalf of the stake account at `index`.
+    /// `result` holds the signature, None if the message was found already
+    /// applied, or the reason it failed to send. A sent transaction is
+    /// recorded with the status `sent_status` it was confirmed to.
+    pub fn new(
+        index: usize,
+        address: &Pubkey,
+        message: &Message,
+        result: Result<Option<String>, String>,
+        sent_status: ConfirmationStatus,
+        slot: Slot,
+    ) -> Self {
+        let instructions = (0..message.instructions.len())
+            .map(|i| {
+                describe_instruction(message, i)
+                    .map(|(kind, _)| kind)
+                    .unwrap_or("instruction")
+                    .to_string()
+            })
+            .collect();
+        let (source, destination, lamports) = match find_transfer(message) {
+            Some((source, destination, lamports)) => (source, Some(destination), lamports),
+            None => (*address, None, 0),
+        };
+        let (signature, status, error) = match result {
+            Ok(Some(signature)) => (Some(signature), sent_status, None),
+            Ok(None) => (None, ConfirmationStatus::Applied, None),
+            Err(reason) => (None, ConfirmationStatus::Failed, Some(reason)),
+        };
+        Self {
+            index,
+            source: source.to_string(),
+            destination: destination.map(|destination| destination.to_string()),
+            instructions,
+            lamports,
+            signature,
+            slot,
+            status,
+            error,
+        }
+    }
+}
+
+/// A receipt that is saved after every entry is added, so it survives the
+/// command failing partway through. Entries are appended to an existing file.
+pub(crate) struct ReceiptFile {
+    path: String,
+    receipt: Receipt,
+}
+
+impl ReceiptFile {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.to_string(),
+            receipt: load_state(path)?,
+        })
+    }
+
+    /// Add an entry for a message sent on behalf of the stake account at `index`.
+    pub fn record<C: Client>(
+        &mut self,
+        client: &C,
+        index: usize,
+        address: &Pubkey,
+        message: &Message,
+        result: Result<Option<String>, String>,
+    ) -> Result<(), Error> {
+        let slot = client.get_slot().map_err(Error::fetch)?;
+        // The client has waited for the transaction to reach its commitment.
+        let sent_status = if client.confirm_commitment() == CommitmentConfig::max() {
+            ConfirmationStatus::Finalized
+        } else {
+            ConfirmationStatus::Confirmed
+        };
+        let entry = ReceiptEntry::new(index, address, message, result, sent_status, slot);
+        self.receipt.entries.push(entry);
+        save_state(&self.path, &self.receipt)
+    }
+}
+
+// Return how far the transaction is confirmed, checking finality first.
+fn confirmation_status<C: Client>(
+    client: &C,
+    signature: &Signature,
+) -> Result<ConfirmationStatus, Error> {
+    for (commitment, confirmed) in &[
+        (CommitmentConfig::max(), ConfirmationStatus::Finalized),
+        (CommitmentConfig::recent(), ConfirmationStatus::Confirmed),
+    ] {
+        match client
+            .get_signature_status(signature, *commitment)
+            .map_err(Error::fetch)?
+        {
+            Some(Ok(())) => return Ok(*confirmed),
+            Some(Err(_)) => return Ok(ConfirmationStatus::Failed),
+            None => {}
+        }
+    }
+    Ok(ConfirmationStatus::Missing)
+}
+
+/// Re-check every transaction in the receipt that isn't yet final, updating
+/// its status.
+pub(crate) fn update_receipt<C: Client>(client: &C, receipt: &mut Receipt) -> Result<(), Error> {
+    for entry in &mut receipt.entries {
+        let signature = match &entry.signature {
+            Some(signature) if entry.status != ConfirmationStatus::Finalized => signature,
+            _ => continue,
+        };
+        let signature = Signature::from_str(signature)
+            .map_err(|err| Error::Config(format!("invalid signature {}: {:?}", signature, err)))?;
+        entry.status = confirmation_status(client, &signature)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_stake_program::stake_instruction;
+
+    #[test]
+    fn test_receipt_entry() {
+        let stake_account_address = Pubkey::new_rand();
+        let stake_authority_pubkey = Pubkey::new_rand();
+        let split_stake_address = Pubkey::new_rand();
+        let instructions = stake_instruction::split_with_seed(
+            &stake_account_address,
+            &stake_authority_pubkey,
+            42,
+            &split_stake_address,
+            &Pubkey::new_rand(),
+            "0",
+        );
+        let message = Message::new(&instructions);
+        let result = Ok(Some("sig".to_string()));
+        let confirmed = ConfirmationStatus::Confirmed;
+        let entry = ReceiptEntry::new(3, &stake_account_address, &message, result, confirmed, 7);
+        assert_eq!(entry.source, stake_account_address.to_string());
+        assert_eq!(entry.destination, Some(split_stake_address.to_string()));
+        assert_eq!(entry.lamports, 42);
+        assert_eq!(entry.instructions.last().unwrap(), "split");
+        assert_eq!(entry.status, ConfirmationStatus::Confirmed);
+
+        let instruction =
+            stake_instruction::deactivate_stake(&stake_account_address, &stake_authority_pubkey);
+        let message = Message::new(&[instruction]);
+        let entry = ReceiptEntry::new(3, &stake_account_address, &message, Ok(None), confirmed, 7);
+        assert_eq!(entry.source, stake_account_address.to_string());
+        assert_eq!(entry.destination, None);
+        assert_eq!(entry.lamports, 0);
+        assert_eq!(entry.instructions, vec!["deactivate"]);
+        assert_eq!(entry.status, ConfirmationStatus::Applied);
+        assert_eq!(entry.error, None);
+
+        let result = Err("send failed".to_string());
+        let entry = ReceiptEntry::new(3, &stake_account_address, &message, result, confirmed, 7);
+        assert_eq!(entry.signature, None);
+        assert_eq!(entry.status, ConfirmationStatus::Failed);
+        assert_eq!(entry.error, Some("send failed".to_string()));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::client::Client;
+use solana_client::client_error::ClientError;
+use solana_sdk::{account::Account, message::Message, pubkey::Pubkey, signers::Signers};
+use solana_stake_program::stake_state::StakeState;
+use std::thread::sleep;
+use std::time::Duration;
+use std::u64;
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The state a message leaves an account in, used to recognize that an
+/// earlier attempt landed even though its confirmation was lost.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Applied {
+    /// The account exists with at least `lamports`, as after a create or split.
+    Funded { address: Pubkey, lamports: u64 },
+    /// The stake account has these authorities.
+    Authorized {
+        address: Pubkey,
+        staker: Pubkey,
+        withdrawer: Pubkey,
+    },
+    /// The stake account is delegated to the vote account, and not deactivated.
+    Delegated {
+        address: Pubkey,
+        vote_pubkey: Pubkey,
+    },
+    /// The stake account's delegation is deactivated.
+    Deactivated { address: Pubkey },
+    /// The account's whole balance was withdrawn.
+    Withdrawn { address: Pubkey },
+}
+
+impl Applied {
+    /// The account whose state shows the message was applied.
+    pub fn address(&self) -> &Pubkey {
+        match self {
+            Applied::Funded { address, .. }
+            | Applied::Authorized { address, .. }
+            | Applied::Delegated { address, .. }
+            | Applied::Deactivated { address }
+            | Applied::Withdrawn { address } => address,
+        }
+    }
+
+    pub fn is_applied(&self, account: Option<&Account>) -> bool {
+        match self {
+            Applied::Funded { lamports, .. } => {
+                account.map_or(false, |account| account.lamports >= *lamports)
+            }
+            Applied::Authorized {
+                staker, withdrawer, ..
+            } => account
+                .and_then(StakeState::authorized_from)
+                .map_or(false, |authorized| {
+                    authorized.staker == *staker && authorized.withdrawer == *withdrawer
+                }),
+            Applied::Delegated { vote_pubkey, .. } => account
+                .and_then(StakeState::stake_from)
+                .map_or(false, |stake| {
+                    stake.delegation.voter_pubkey == *vote_pubkey
+                        && stake.delegation.deactivation_epoch == u64::MAX
+                }),
+            Applied::Deactivated { .. } => account
+                .and_then(StakeState::stake_from)
+                .map_or(false, |stake| {
+                    stake.delegation.deactivation_epoch != u64::MAX
+                }),
+            Applied::Withdrawn { .. } => account.map_or(true, |account| account.lamports == 0),
+        }
+    }
+}
+
+/// Send the message, signing each attempt with a fresh blockhash. After a
+/// failure, re-read the account `applied` describes, and if the message's
+/// effect is already there, return None rather than sending it again. Failures
+/// other than the cluster rejecting the transaction are retried with
+/// exponential backoff. Without `applied`, the message is sent only once, since
+/// a blind retry could apply it twice.
+pub(crate) fn send_with_retries<C: Client, S: Signers>(
+    client: &C,
+    message: &Message,
+    signers: &S,
+    applied: Option<&Applied>,
+) -> Result<Option<String>, ClientError> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let err = match client.send_and_confirm_message(message.clone(), signers) {
+            Ok(signature) => return Ok(Some(signature)),
+            Err(err) => err,
+        };
+        let applied = match applied {
+            Some(applied) => applied,
+            None => return Err(err),
+        };
+        let retry = match err {
+            ClientError::TransactionError(_) | ClientError::SigningError(_) => false,
+            _ => attempt < MAX_ATTEMPTS,
+        };
+        if retry {
+            sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+        // An account that can't be read is treated as not yet updated.
+        if let Ok(account) = client.get_account(applied.address()) {
+            if applied.is_applied(account.as_ref()) {
+                return Ok(None);
+            }
+        }
+        if !retry {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_stake_program::stake_state::{Authorized, Delegation, Meta, Stake};
+
+    #[test]
+    fn test_is_applied() {
+        let address = Pubkey::new_rand();
+        let staker = Pubkey::new_rand();
+        let withdrawer = Pubkey::new_rand();
+        let vote_pubkey = Pubkey::new_rand();
+        let create_account = |deactivation_epoch| {
+            let meta = Meta {
+                authorized: Authorized { staker, withdrawer },
+                ..Meta::default()
+            };
+            let stake = Stake {
+                delegation: Delegation {
+                    voter_pubkey: vote_pubkey,
+                    stake: 40,
+                    deactivation_epoch,
+                    ..Delegation::default()
+                },
+                ..Stake::default()
+            };
+            let state = StakeState::Stake(meta, stake);
+            Account::new_data(42, &state, &solana_stake_program::id()).unwrap()
+        };
+        let account = create_account(u64::MAX);
+
+        let funded = Applied::Funded {
+            address,
+            lamports: 42,
+        };
+        assert!(funded.is_applied(Some(&account)));
+        assert!(!funded.is_applied(None));
+
+        let authorized = Applied::Authorized {
+            address,
+            staker,
+            withdrawer,
+        };
+        assert!(authorized.is_applied(Some(&account)));
+        let authorized = Applied::Authorized {
+            address,
+            staker: withdrawer,
+            withdrawer,
+        };
+        assert!(!authorized.is_applied(Some(&account)));
+
+        let delegated = Applied::Delegated {
+            address,
+            vote_pubkey,
+        };
+        assert!(delegated.is_applied(Some(&account)));
+        let deactivated = Applied::Deactivated { address };
+        assert!(!deactivated.is_applied(Some(&account)));
+
+        let account = create_account(3);
+        assert!(!delegated.is_applied(Some(&account)));
+        assert!(deactivated.is_applied(Some(&account)));
+
+        let withdrawn = Applied::Withdrawn { address };
+        assert!(!withdrawn.is_applied(Some(&account)));
+        assert!(withdrawn.is_applied(None));
+    }
+}
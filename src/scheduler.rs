@@ -0,0 +1,370 @@
+use crate::error::Error;
+use crate::lockup::{get_lockup, is_locked};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, Slot},
+    native_token::sol_to_lamports,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, stake_history::StakeHistory},
+};
+use solana_stake_program::stake_state::{Delegation, StakeState};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+use std::u64;
+
+/// The plan file, in YAML.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct PlanRecord {
+    base_pubkey: String,
+    steps: Vec<StepRecord>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct StepRecord {
+    name: String,
+    index: usize,
+    action: String,
+    #[serde(default)]
+    vote_account: Option<String>,
+    #[serde(default)]
+    recipient: Option<String>,
+    #[serde(default)]
+    amount: Option<f64>,
+    #[serde(default)]
+    after: Vec<String>,
+    #[serde(default)]
+    epoch: Option<Epoch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StepAction {
+    Deactivate,
+    Delegate {
+        vote_pubkey: Pubkey,
+    },
+    /// Withdraw `lamports` to the recipient, or the whole balance if None.
+    Withdraw {
+        recipient: Pubkey,
+        lamports: Option<u64>,
+    },
+}
+
+/// An operation on a derived stake account, sent once the steps named in
+/// `after` are done, `epoch` is reached, and the account is in a state the
+/// action can be applied to.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Step {
+    pub name: String,
+    pub index: usize,
+    pub action: StepAction,
+    pub after: Vec<String>,
+    pub epoch: Option<Epoch>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Plan {
+    pub base_pubkey: Pubkey,
+    pub steps: Vec<Step>,
+}
+
+/// A step that is done, and the signature of its transaction, which is None if
+/// the account was found already in the state the step would have put it in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DoneStep {
+    pub name: String,
+    pub epoch: Epoch,
+    pub signature: Option<String>,
+}
+
+/// A transaction sent for a step whose effect can't be recognized from the
+/// account, saved before it is confirmed, with the slot it was signed in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct SentStep {
+    pub name: String,
+    pub signature: String,
+    pub slot: Slot,
+}
+
+/// The steps of a plan that are done, and those sent but not yet known to
+/// have landed, saved between polls.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct PlanProgress {
+    pub done: Vec<DoneStep>,
+    #[serde(default)]
+    pub sent: Vec<SentStep>,
+}
+
+impl PlanProgress {
+    pub fn is_done(&self, name: &str) -> bool {
+        self.done.iter().any(|step| step.name == name)
+    }
+
+    pub fn sent_step(&self, name: &str) -> Option<&SentStep> {
+        self.sent.iter().find(|step| step.name == name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Readiness {
+    Ready,
+    /// The account is already in the state the step would put it in.
+    Satisfied,
+    Waiting(String),
+}
+
+fn parse_pubkey(step: &str, name: &str, value: Option<String>) -> Result<Pubkey, String> {
+    let value = value.ok_or_else(|| format!("step {}: missing {}", step, name))?;
+    Pubkey::from_str(&value).map_err(|err| format!("step {}: invalid {}: {:?}", step, name, err))
+}
+
+fn parse_step(record: StepRecord) -> Result<Step, String> {
+    let action = match record.action.as_str() {
+        "deactivate" => StepAction::Deactivate,
+        "delegate" => StepAction::Delegate {
+            vote_pubkey: parse_pubkey(&record.name, "vote_account", record.vote_account)?,
+        },
+        "withdraw" => StepAction::Withdraw {
+            recipient: parse_pubkey(&record.name, "recipient", record.recipient)?,
+            lamports: record.amount.map(sol_to_lamports),
+        },
+        action => {
+            return Err(format!(
+                "step {}: unknown action '{}', expected deactivate, delegate or withdraw",
+                record.name, action
+            ))
+        }
+    };
+    Ok(Step {
+        name: record.name,
+        index: record.index,
+        action,
+        after: record.after,
+        epoch: record.epoch,
+    })
+}
+
+/// Read a plan, checking that step names are unique and that each step only
+/// waits on steps before it.
+pub(crate) fn read_plan<R: Read>(reader: R) -> Result<Plan, String> {
+    let record: PlanRecord = serde_yaml::from_reader(reader).map_err(|err| err.to_string())?;
+    let base_pubkey = Pubkey::from_str(&record.base_pubkey)
+        .map_err(|err| format!("invalid base_pubkey: {:?}", err))?;
+    let mut names = HashSet::new();
+    let mut steps = vec![];
+    for record in record.steps {
+        let step = parse_step(record)?;
+        if let Some(after) = step.after.iter().find(|after| !names.contains(*after)) {
+            return Err(format!(
+                "step {}: '{}' is not an earlier step",
+                step.name, after
+            ));
+        }
+        if !names.insert(step.name.clone()) {
+            return Err(format!("step {}: duplicate name", step.name));
+        }
+        steps.push(step);
+    }
+    Ok(Plan { base_pubkey, steps })
+}
+
+pub(crate) fn read_plan_file(path: &str) -> Result<Plan, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    read_plan(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+/// Decide whether the action can be applied to the account now.
+pub(crate) fn readiness(
+    action: &StepAction,
+    account: Option<&Account>,
+    clock: &Clock,
+    stake_history: &StakeHistory,
+) -> Readiness {
+    let account = match (account, action) {
+        (Some(account), _) => account,
+        // Withdrawing the whole balance closes the account.
+        (None, StepAction::Withdraw { lamports: None, .. }) => return Readiness::Satisfied,
+        (None, _) => return Readiness::Waiting("account does not exist".to_string()),
+    };
+    // The delegation, and whether its stake is still effective or activating.
+    let delegation = match StakeState::from(account) {
+        Some(StakeState::Initialized(_)) => None,
+        Some(StakeState::Stake(_, stake)) => {
+            let delegation = stake.delegation;
+            let (effective, activating, _) =
+                delegation.stake_activating_and_deactivating(clock.epoch, Some(stake_history));
+            Some((delegation, effective + activating > 0))
+        }
+        _ => return Readiness::Waiting("not an initialized stake account".to_string()),
+    };
+    let deactivated = |delegation: &Delegation| delegation.deactivation_epoch != u64::MAX;
+    match action {
+        StepAction::Deactivate => match delegation {
+            Some((delegation, _)) if !deactivated(&delegation) => Readiness::Ready,
+            _ => Readiness::Satisfied,
+        },
+        StepAction::Delegate { vote_pubkey } => match delegation {
+            None | Some((_, false)) => Readiness::Ready,
+            Some((delegation, true)) if deactivated(&delegation) => {
+                Readiness::Waiting("stake is cooling down".to_string())
+            }
+            Some((delegation, true)) if delegation.voter_pubkey == *vote_pubkey => {
+                Readiness::Satisfied
+            }
+            Some((delegation, true)) => {
+                Readiness::Waiting(format!("stake is delegated to {}", delegation.voter_pubkey))
+            }
+        },
+        StepAction::Withdraw { .. } => match get_lockup(account) {
+            Some(lockup) if is_locked(&lockup, clock) => {
+                Readiness::Waiting("lockup is in force".to_string())
+            }
+            _ => match delegation {
+                Some((delegation, true)) if deactivated(&delegation) => {
+                    Readiness::Waiting("stake is cooling down".to_string())
+                }
+                Some((_, true)) => Readiness::Waiting("stake is active".to_string()),
+                _ => Readiness::Ready,
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_stake_program::stake_state::{Lockup, Meta, Stake};
+
+    fn create_stake_account(
+        voter_pubkey: Pubkey,
+        deactivation_epoch: Epoch,
+        unix_timestamp: i64,
+    ) -> Account {
+        let meta = Meta {
+            lockup: Lockup {
+                unix_timestamp,
+                ..Lockup::default()
+            },
+            ..Meta::default()
+        };
+        let stake = Stake {
+            delegation: Delegation {
+                voter_pubkey,
+                stake: 40,
+                activation_epoch: 0,
+                deactivation_epoch,
+                ..Delegation::default()
+            },
+            ..Stake::default()
+        };
+        let state = StakeState::Stake(meta, stake);
+        Account::new_data(42, &state, &solana_stake_program::id()).unwrap()
+    }
+
+    #[test]
+    fn test_readiness() {
+        let vote_pubkey = Pubkey::new_rand();
+        let recipient = Pubkey::new_rand();
+        let yaml = format!(
+            "
+base_pubkey: {}
+steps:
+  - name: deactivate
+    index: 0
+    action: deactivate
+  - name: withdraw
+    index: 0
+    action: withdraw
+    recipient: {}
+    after: [deactivate]
+  - name: delegate
+    index: 1
+    action: delegate
+    vote_account: {}
+    epoch: 12
+",
+            Pubkey::new_rand(),
+            recipient,
+            vote_pubkey
+        );
+        let plan = read_plan(yaml.as_bytes()).unwrap();
+        assert_eq!(plan.steps[2].epoch, Some(12));
+        let withdraw = &plan.steps[1].action;
+        assert_eq!(
+            *withdraw,
+            StepAction::Withdraw {
+                recipient,
+                lamports: None
+            }
+        );
+        let bad_yaml = yaml.replace("after: [deactivate]", "after: [delegate]");
+        assert!(read_plan(bad_yaml.as_bytes()).is_err());
+
+        let clock = Clock {
+            epoch: 10,
+            unix_timestamp: 1_000,
+            ..Clock::default()
+        };
+        let stake_history = StakeHistory::default();
+        let delegate = &plan.steps[2].action;
+
+        // An active delegation can be deactivated, but not withdrawn from or
+        // redelegated.
+        let other_vote_pubkey = Pubkey::new_rand();
+        let account = create_stake_account(other_vote_pubkey, u64::MAX, 0);
+        let readiness = |action, account| readiness(action, Some(account), &clock, &stake_history);
+        assert_eq!(
+            readiness(&StepAction::Deactivate, &account),
+            Readiness::Ready
+        );
+        assert_eq!(
+            readiness(withdraw, &account),
+            Readiness::Waiting("stake is active".to_string())
+        );
+        assert_eq!(
+            readiness(delegate, &account),
+            Readiness::Waiting(format!("stake is delegated to {}", other_vote_pubkey))
+        );
+
+        // Deactivated this epoch, the stake is still cooling down.
+        let account = create_stake_account(vote_pubkey, 10, 0);
+        assert_eq!(
+            readiness(&StepAction::Deactivate, &account),
+            Readiness::Satisfied
+        );
+        assert_eq!(
+            readiness(delegate, &account),
+            Readiness::Waiting("stake is cooling down".to_string())
+        );
+
+        // Once inactive, it can be withdrawn unless locked up.
+        let account = create_stake_account(vote_pubkey, 5, 0);
+        assert_eq!(readiness(withdraw, &account), Readiness::Ready);
+        assert_eq!(readiness(delegate, &account), Readiness::Ready);
+        let account = create_stake_account(vote_pubkey, 5, 2_000);
+        assert_eq!(
+            readiness(withdraw, &account),
+            Readiness::Waiting("lockup is in force".to_string())
+        );
+
+        // Once the whole balance is withdrawn, the account is gone.
+        let missing = |action| super::readiness(action, None, &clock, &stake_history);
+        assert_eq!(missing(withdraw), Readiness::Satisfied);
+        let partial_withdraw = StepAction::Withdraw {
+            recipient,
+            lamports: Some(1),
+        };
+        assert_eq!(
+            missing(&partial_withdraw),
+            Readiness::Waiting("account does not exist".to_string())
+        );
+        assert_eq!(
+            missing(delegate),
+            Readiness::Waiting("account does not exist".to_string())
+        );
+    }
+}
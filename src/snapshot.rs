@@ -0,0 +1,520 @@
+use crate::client::Client;
+use crate::error::{Error, Phase};
+use crate::stake_accounts;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, Slot},
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+};
+use solana_stake_program::stake_state::{Delegation, Lockup, Meta, StakeState};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A point-in-time inventory of derived stake accounts.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Snapshot {
+    pub version: u32,
+    /// The slot at which the snapshot began.
+    pub slot: Slot,
+    pub epoch: Epoch,
+    pub accounts: Vec<AccountSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct AccountSnapshot {
+    pub base_pubkey: String,
+    pub index: usize,
+    pub address: String,
+    pub lamports: u64,
+    pub state: StakeStateSnapshot,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum StakeStateSnapshot {
+    /// There is no account at the address.
+    Missing,
+    /// The account exists but is not a stake account.
+    Invalid,
+    Uninitialized,
+    Initialized {
+        meta: MetaSnapshot,
+    },
+    Stake {
+        meta: MetaSnapshot,
+        delegation: DelegationSnapshot,
+    },
+    RewardsPool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct MetaSnapshot {
+    pub rent_exempt_reserve: u64,
+    pub staker: String,
+    pub withdrawer: String,
+    pub lockup: LockupSnapshot,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct LockupSnapshot {
+    pub unix_timestamp: i64,
+    pub epoch: Epoch,
+    pub custodian: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DelegationSnapshot {
+    pub voter: String,
+    pub stake: u64,
+    pub activation_epoch: Epoch,
+    pub deactivation_epoch: Epoch,
+}
+
+impl From<&Lockup> for LockupSnapshot {
+    fn from(lockup: &Lockup) -> Self {
+        Self {
+            unix_timestamp: lockup.unix_timestamp,
+            epoch: lockup.epoch,
+            custodian: lockup.custodian.to_string(),
+        }
+    }
+}
+
+impl From<&Meta> for MetaSnapshot {
+    fn from(meta: &Meta) -> Self {
+        Self {
+            rent_exempt_reserve: meta.rent_exempt_reserve,
+            staker: meta.authorized.staker.to_string(),
+            withdrawer: meta.authorized.withdrawer.to_string(),
+            lockup: LockupSnapshot::from(&meta.lockup),
+        }
+    }
+}
+
+impl From<&Delegation> for DelegationSnapshot {
+    fn from(delegation: &Delegation) -> Self {
+        Self {
+            voter: delegation.voter_pubkey.to_string(),
+            stake: delegation.stake,
+            activation_epoch: delegation.activation_epoch,
+            deactivation_epoch: delegation.deactivation_epoch,
+        }
+    }
+}
+
+impl StakeStateSnapshot {
+    pub fn new(account: Option<&Account>) -> Self {
+        let account = match account {
+            Some(account) => account,
+            None => return StakeStateSnapshot::Missing,
+        };
+        match StakeState::from(account) {
+            Some(StakeState::Uninitialized) => StakeStateSnapshot::Uninitialized,
+            Some(StakeState::Initialized(meta)) => StakeStateSnapshot::Initialized {
+                meta: MetaSnapshot::from(&meta),
+            },
+            Some(StakeState::Stake(meta, stake)) => StakeStateSnapshot::Stake {
+                meta: MetaSnapshot::from(&meta),
+                delegation: DelegationSnapshot::from(&stake.delegation),
+            },
+            Some(StakeState::RewardsPool) => StakeStateSnapshot::RewardsPool,
+            None => StakeStateSnapshot::Invalid,
+        }
+    }
+
+    pub fn meta(&self) -> Option<&MetaSnapshot> {
+        match self {
+            StakeStateSnapshot::Initialized { meta } | StakeStateSnapshot::Stake { meta, .. } => {
+                Some(meta)
+            }
+            _ => None,
+        }
+    }
+
+    fn delegation(&self) -> Option<&DelegationSnapshot> {
+        match self {
+            StakeStateSnapshot::Stake { delegation, .. } => Some(delegation),
+            _ => None,
+        }
+    }
+}
+
+impl AccountSnapshot {
+    pub fn new(
+        base_pubkey: &Pubkey,
+        index: usize,
+        address: &Pubkey,
+        account: Option<&Account>,
+    ) -> Self {
+        Self {
+            base_pubkey: base_pubkey.to_string(),
+            index,
+            address: address.to_string(),
+            lamports: account.map(|account| account.lamports).unwrap_or(0),
+            state: StakeStateSnapshot::new(account),
+        }
+    }
+
+    fn exists(&self) -> bool {
+        self.state != StakeStateSnapshot::Missing
+    }
+}
+
+/// Fetch the state of the derived accounts at `indices` of each base.
+pub(crate) fn fetch_account_snapshots<C: Client>(
+    client: &C,
+    base_pubkeys: &[Pubkey],
+    indices: &[usize],
+) -> Result<Vec<AccountSnapshot>, Error> {
+    let mut accounts = vec![];
+    for base_pubkey in base_pubkeys {
+        let addresses = stake_accounts::derive_stake_account_addresses(base_pubkey, indices)?;
+        for (&i, address) in indices.iter().zip(&addresses) {
+            let account = client
+                .get_account(address)
+                .map_err(|err| Error::account(i, address, Phase::Fetch, err))?;
+            accounts.push(AccountSnapshot::new(
+                base_pubkey,
+                i,
+                address,
+                account.as_ref(),
+            ));
+        }
+    }
+    Ok(accounts)
+}
+
+pub(crate) fn write_snapshot(path: &str, snapshot: &Snapshot) -> Result<(), Error> {
+    let file = File::create(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    serde_json::to_writer_pretty(file, snapshot)
+        .map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+pub(crate) fn read_snapshot(path: &str) -> Result<Snapshot, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    let snapshot: Snapshot =
+        serde_json::from_reader(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::Config(format!(
+            "{}: unsupported snapshot version {}, expected {}",
+            path, snapshot.version, SNAPSHOT_VERSION
+        )));
+    }
+    Ok(snapshot)
+}
+
+/// A difference between two snapshots of the same derived stake account.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Change {
+    Appeared {
+        lamports: u64,
+    },
+    Disappeared {
+        lamports: u64,
+    },
+    Balance {
+        before: u64,
+        after: u64,
+    },
+    Authority {
+        role: &'static str,
+        before: String,
+        after: String,
+    },
+    Lockup {
+        before: LockupSnapshot,
+        after: LockupSnapshot,
+    },
+    Delegation {
+        before: Option<DelegationSnapshot>,
+        after: Option<DelegationSnapshot>,
+    },
+}
+
+impl Change {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Change::Appeared { .. } => "appeared",
+            Change::Disappeared { .. } => "disappeared",
+            Change::Balance { .. } => "balance",
+            Change::Authority { .. } => "authority",
+            Change::Lockup { .. } => "lockup",
+            Change::Delegation { .. } => "delegation",
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Appeared { lamports } => {
+                write!(f, "appeared with {} SOL", lamports_to_sol(*lamports))
+            }
+            Change::Disappeared { lamports } => {
+                write!(f, "disappeared with {} SOL", lamports_to_sol(*lamports))
+            }
+            Change::Balance { before, after } => {
+                let sign = if after >= before { "+" } else { "-" };
+                let delta = if after >= before {
+                    after - before
+                } else {
+                    before - after
+                };
+                write!(
+                    f,
+                    "balance {}{} SOL ({} SOL -> {} SOL)",
+                    sign,
+                    lamports_to_sol(delta),
+                    lamports_to_sol(*before),
+                    lamports_to_sol(*after)
+                )
+            }
+            Change::Authority {
+                role,
+                before,
+                after,
+            } => write!(f, "{} {} -> {}", role, before, after),
+            Change::Lockup { before, after } => write!(
+                f,
+                "lockup (timestamp {}, epoch {}, custodian {}) -> (timestamp {}, epoch {}, custodian {})",
+                before.unix_timestamp,
+                before.epoch,
+                before.custodian,
+                after.unix_timestamp,
+                after.epoch,
+                after.custodian
+            ),
+            Change::Delegation { before, after } => {
+                let describe = |delegation: &Option<DelegationSnapshot>| match delegation {
+                    Some(delegation) => format!(
+                        "{} SOL to {} (activation epoch {}, deactivation epoch {})",
+                        lamports_to_sol(delegation.stake),
+                        delegation.voter,
+                        delegation.activation_epoch,
+                        delegation.deactivation_epoch
+                    ),
+                    None => "undelegated".to_string(),
+                };
+                write!(f, "delegation {} -> {}", describe(before), describe(after))
+            }
+        }
+    }
+}
+
+fn diff_accounts(before: &AccountSnapshot, after: &AccountSnapshot) -> Vec<Change> {
+    match (before.exists(), after.exists()) {
+        (false, false) => return vec![],
+        (false, true) => {
+            return vec![Change::Appeared {
+                lamports: after.lamports,
+            }]
+        }
+        (true, false) => {
+            return vec![Change::Disappeared {
+                lamports: before.lamports,
+            }]
+        }
+        (true, true) => {}
+    }
+
+    let mut changes = vec![];
+    if before.lamports != after.lamports {
+        changes.push(Change::Balance {
+            before: before.lamports,
+            after: after.lamports,
+        });
+    }
+    if let (Some(before_meta), Some(after_meta)) = (before.state.meta(), after.state.meta()) {
+        if before_meta.staker != after_meta.staker {
+            changes.push(Change::Authority {
+                role: "staker",
+                before: before_meta.staker.clone(),
+                after: after_meta.staker.clone(),
+            });
+        }
+        if before_meta.withdrawer != after_meta.withdrawer {
+            changes.push(Change::Authority {
+                role: "withdrawer",
+                before: before_meta.withdrawer.clone(),
+                after: after_meta.withdrawer.clone(),
+            });
+        }
+        if before_meta.lockup != after_meta.lockup {
+            changes.push(Change::Lockup {
+                before: before_meta.lockup.clone(),
+                after: after_meta.lockup.clone(),
+            });
+        }
+    }
+    if before.state.delegation() != after.state.delegation() {
+        changes.push(Change::Delegation {
+            before: before.state.delegation().cloned(),
+            after: after.state.delegation().cloned(),
+        });
+    }
+    changes
+}
+
+fn write_error<E: fmt::Display>(err: E) -> Error {
+    Error::Config(format!("unable to write diff: {}", err))
+}
+
+/// Write the slots of both snapshots, followed by one line per change to
+/// each account between them.
+pub(crate) fn write_diff<W: Write>(
+    mut writer: W,
+    before: &Snapshot,
+    after: &Snapshot,
+) -> Result<(), Error> {
+    writeln!(
+        writer,
+        "Slot {} (epoch {}) -> slot {} (epoch {})",
+        before.slot, before.epoch, after.slot, after.epoch
+    )
+    .map_err(write_error)?;
+    for (account, changes) in diff_snapshots(&before.accounts, &after.accounts) {
+        for change in changes {
+            writeln!(
+                writer,
+                "{} {} {}: {}",
+                account.base_pubkey, account.index, account.address, change
+            )
+            .map_err(write_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Return the changes to each account between two snapshots, in the order
+/// the accounts appear in the later snapshot, followed by accounts only in
+/// the earlier one.
+pub(crate) fn diff_snapshots<'a>(
+    before: &'a [AccountSnapshot],
+    after: &'a [AccountSnapshot],
+) -> Vec<(&'a AccountSnapshot, Vec<Change>)> {
+    let missing = |account: &AccountSnapshot| AccountSnapshot {
+        lamports: 0,
+        state: StakeStateSnapshot::Missing,
+        ..account.clone()
+    };
+    let before_accounts: HashMap<_, _> = before
+        .iter()
+        .map(|account| (&account.address, account))
+        .collect();
+    let after_addresses: HashMap<_, _> = after
+        .iter()
+        .map(|account| (&account.address, account))
+        .collect();
+
+    let mut diffs = vec![];
+    for after_account in after {
+        let changes = match before_accounts.get(&after_account.address) {
+            Some(before_account) => diff_accounts(before_account, after_account),
+            None => diff_accounts(&missing(after_account), after_account),
+        };
+        if !changes.is_empty() {
+            diffs.push((after_account, changes));
+        }
+    }
+    for before_account in before {
+        if !after_addresses.contains_key(&before_account.address) {
+            let changes = diff_accounts(before_account, &missing(before_account));
+            if !changes.is_empty() {
+                diffs.push((before_account, changes));
+            }
+        }
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_account_snapshot(index: usize, lamports: u64, staker: &str) -> AccountSnapshot {
+        let meta = MetaSnapshot {
+            rent_exempt_reserve: 1,
+            staker: staker.to_string(),
+            withdrawer: "withdrawer".to_string(),
+            lockup: LockupSnapshot {
+                unix_timestamp: 0,
+                epoch: 0,
+                custodian: "custodian".to_string(),
+            },
+        };
+        AccountSnapshot {
+            base_pubkey: "base".to_string(),
+            index,
+            address: format!("address{}", index),
+            lamports,
+            state: StakeStateSnapshot::Initialized { meta },
+        }
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let before = Snapshot {
+            version: SNAPSHOT_VERSION,
+            slot: 1,
+            epoch: 0,
+            accounts: vec![
+                create_account_snapshot(0, 10, "staker"),
+                create_account_snapshot(1, 10, "staker"),
+                create_account_snapshot(2, 10, "staker"),
+            ],
+        };
+        let after = Snapshot {
+            version: SNAPSHOT_VERSION,
+            slot: 2,
+            epoch: 1,
+            accounts: vec![
+                create_account_snapshot(0, 10, "staker"),
+                create_account_snapshot(1, 12, "new_staker"),
+                create_account_snapshot(3, 5, "staker"),
+            ],
+        };
+        let diffs = diff_snapshots(&before.accounts, &after.accounts);
+        assert_eq!(diffs.len(), 3);
+
+        assert_eq!(diffs[0].0.index, 1);
+        assert_eq!(
+            diffs[0].1,
+            vec![
+                Change::Balance {
+                    before: 10,
+                    after: 12
+                },
+                Change::Authority {
+                    role: "staker",
+                    before: "staker".to_string(),
+                    after: "new_staker".to_string(),
+                },
+            ]
+        );
+        assert_eq!(diffs[1].0.index, 3);
+        assert_eq!(diffs[1].1, vec![Change::Appeared { lamports: 5 }]);
+        assert_eq!(diffs[2].0.index, 2);
+        assert_eq!(diffs[2].1, vec![Change::Disappeared { lamports: 10 }]);
+
+        assert!(diff_snapshots(&before.accounts, &before.accounts).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_json_roundtrip() {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            slot: 1,
+            epoch: 0,
+            accounts: vec![create_account_snapshot(0, 10, "staker")],
+        };
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.contains(r#""type":"initialized""#));
+        assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+    }
+}
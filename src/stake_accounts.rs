@@ -1,3 +1,5 @@
+use crate::error::Error;
+use crate::profile::SeedScheme;
 use solana_sdk::{hash::hashv, instruction::Instruction, message::Message, pubkey::Pubkey};
 use solana_stake_program::{
     stake_instruction,
@@ -11,6 +13,16 @@ pub enum PubkeyError {
     MaxSeedLengthExceeded,
 }
 
+impl std::fmt::Display for PubkeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PubkeyError::MaxSeedLengthExceeded => {
+                write!(f, "seed exceeds {} bytes", MAX_SEED_LEN)
+            }
+        }
+    }
+}
+
 // TODO: Once solana-1.1 is released, use `Pubkey::create_with_seed`.
 fn create_with_seed(base: &Pubkey, seed: &str, program_id: &Pubkey) -> Result<Pubkey, PubkeyError> {
     if seed.len() > MAX_SEED_LEN {
@@ -22,17 +34,44 @@ fn create_with_seed(base: &Pubkey, seed: &str, program_id: &Pubkey) -> Result<Pu
     ))
 }
 
-pub(crate) fn derive_stake_account_address(base_pubkey: &Pubkey, i: usize) -> Pubkey {
-    create_with_seed(base_pubkey, &i.to_string(), &solana_stake_program::id()).unwrap()
+// Return the seed of the stake account at index `i`. Accounts are created,
+// split and rebased under the default scheme.
+fn seed(i: usize) -> String {
+    SeedScheme::default().seed(i)
+}
+
+pub(crate) fn derive_stake_account_address_with_scheme(
+    base_pubkey: &Pubkey,
+    seed_scheme: SeedScheme,
+    i: usize,
+) -> Result<Pubkey, Error> {
+    create_with_seed(
+        base_pubkey,
+        &seed_scheme.seed(i),
+        &solana_stake_program::id(),
+    )
+    .map_err(|err| Error::Derive {
+        base_pubkey: *base_pubkey,
+        index: i,
+        reason: err.to_string(),
+    })
+}
+
+pub(crate) fn derive_stake_account_address(
+    base_pubkey: &Pubkey,
+    i: usize,
+) -> Result<Pubkey, Error> {
+    derive_stake_account_address_with_scheme(base_pubkey, SeedScheme::default(), i)
 }
 
-// Return derived addresses
+// Return the derived address at each index
 pub(crate) fn derive_stake_account_addresses(
     base_pubkey: &Pubkey,
-    num_accounts: usize,
-) -> Vec<Pubkey> {
-    (0..num_accounts)
-        .map(|i| derive_stake_account_address(base_pubkey, i))
+    indices: &[usize],
+) -> Result<Vec<Pubkey>, Error> {
+    indices
+        .iter()
+        .map(|i| derive_stake_account_address(base_pubkey, *i))
         .collect()
 }
 
@@ -43,23 +82,147 @@ pub(crate) fn new_stake_account(
     lamports: u64,
     stake_authority_pubkey: &Pubkey,
     withdraw_authority_pubkey: &Pubkey,
-) -> Message {
-    let seed = 0;
-    let stake_account_address = derive_stake_account_address(base_pubkey, seed);
+) -> Result<Message, Error> {
     let authorized = Authorized {
         staker: *stake_authority_pubkey,
         withdrawer: *withdraw_authority_pubkey,
     };
+    new_derived_stake_account(
+        fee_payer_pubkey,
+        sender_pubkey,
+        base_pubkey,
+        0,
+        lamports,
+        &authorized,
+        &Lockup::default(),
+    )
+}
+
+// Create the stake account derived from the base pubkey at index `i`
+pub(crate) fn new_derived_stake_account(
+    fee_payer_pubkey: &Pubkey,
+    sender_pubkey: &Pubkey,
+    base_pubkey: &Pubkey,
+    i: usize,
+    lamports: u64,
+    authorized: &Authorized,
+    lockup: &Lockup,
+) -> Result<Message, Error> {
+    let stake_account_address = derive_stake_account_address(base_pubkey, i)?;
     let instructions = stake_instruction::create_account_with_seed(
         sender_pubkey,
         &stake_account_address,
         &base_pubkey,
-        &seed.to_string(),
-        &authorized,
-        &Lockup::default(),
+        &seed(i),
+        authorized,
+        lockup,
         lamports,
     );
-    Message::new_with_payer(&instructions, Some(fee_payer_pubkey))
+    Ok(Message::new_with_payer(
+        &instructions,
+        Some(fee_payer_pubkey),
+    ))
+}
+
+// Withdraw lamports from a stake account, signed by the custodian if it is still locked up
+pub(crate) fn withdraw_stake_account(
+    fee_payer_pubkey: &Pubkey,
+    stake_account_address: &Pubkey,
+    withdraw_authority_pubkey: &Pubkey,
+    custodian_pubkey: Option<&Pubkey>,
+    recipient_pubkey: &Pubkey,
+    lamports: u64,
+) -> Message {
+    let instruction = stake_instruction::withdraw(
+        stake_account_address,
+        withdraw_authority_pubkey,
+        recipient_pubkey,
+        lamports,
+        custodian_pubkey,
+    );
+    Message::new_with_payer(&[instruction], Some(fee_payer_pubkey))
+}
+
+pub(crate) fn delegate_stake_account(
+    fee_payer_pubkey: &Pubkey,
+    stake_account_address: &Pubkey,
+    stake_authority_pubkey: &Pubkey,
+    vote_pubkey: &Pubkey,
+) -> Message {
+    let instruction = stake_instruction::delegate_stake(
+        stake_account_address,
+        stake_authority_pubkey,
+        vote_pubkey,
+    );
+    Message::new_with_payer(&[instruction], Some(fee_payer_pubkey))
+}
+
+// Split lamports into the account derived at index `i` and delegate it
+pub(crate) fn split_and_delegate_stake_account(
+    fee_payer_pubkey: &Pubkey,
+    stake_account_address: &Pubkey,
+    base_pubkey: &Pubkey,
+    i: usize,
+    stake_authority_pubkey: &Pubkey,
+    lamports: u64,
+    vote_pubkey: &Pubkey,
+) -> Result<Message, Error> {
+    let split_stake_account_address = derive_stake_account_address(base_pubkey, i)?;
+    let mut instructions = stake_instruction::split_with_seed(
+        stake_account_address,
+        stake_authority_pubkey,
+        lamports,
+        &split_stake_account_address,
+        base_pubkey,
+        &seed(i),
+    );
+    instructions.push(stake_instruction::delegate_stake(
+        &split_stake_account_address,
+        stake_authority_pubkey,
+        vote_pubkey,
+    ));
+    Ok(Message::new_with_payer(
+        &instructions,
+        Some(fee_payer_pubkey),
+    ))
+}
+
+pub(crate) fn deactivate_stake_account(
+    fee_payer_pubkey: &Pubkey,
+    stake_account_address: &Pubkey,
+    stake_authority_pubkey: &Pubkey,
+) -> Message {
+    let instruction =
+        stake_instruction::deactivate_stake(stake_account_address, stake_authority_pubkey);
+    Message::new_with_payer(&[instruction], Some(fee_payer_pubkey))
+}
+
+// Split lamports into the account derived at index `i` and deactivate it
+pub(crate) fn split_and_deactivate_stake_account(
+    fee_payer_pubkey: &Pubkey,
+    stake_account_address: &Pubkey,
+    base_pubkey: &Pubkey,
+    i: usize,
+    stake_authority_pubkey: &Pubkey,
+    lamports: u64,
+) -> Result<Message, Error> {
+    let split_stake_account_address = derive_stake_account_address(base_pubkey, i)?;
+    let mut instructions = stake_instruction::split_with_seed(
+        stake_account_address,
+        stake_authority_pubkey,
+        lamports,
+        &split_stake_account_address,
+        base_pubkey,
+        &seed(i),
+    );
+    instructions.push(stake_instruction::deactivate_stake(
+        &split_stake_account_address,
+        stake_authority_pubkey,
+    ));
+    Ok(Message::new_with_payer(
+        &instructions,
+        Some(fee_payer_pubkey),
+    ))
 }
 
 fn authorize_stake_accounts_instructions(
@@ -91,17 +254,20 @@ fn rebase_stake_account(
     fee_payer_pubkey: &Pubkey,
     stake_authority_pubkey: &Pubkey,
     lamports: u64,
-) -> Message {
-    let new_stake_account_address = derive_stake_account_address(new_base_pubkey, i);
+) -> Result<Message, Error> {
+    let new_stake_account_address = derive_stake_account_address(new_base_pubkey, i)?;
     let instructions = stake_instruction::split_with_seed(
         stake_account_address,
         stake_authority_pubkey,
         lamports,
         &new_stake_account_address,
         new_base_pubkey,
-        &i.to_string(),
+        &seed(i),
     );
-    Message::new_with_payer(&instructions, Some(&fee_payer_pubkey))
+    Ok(Message::new_with_payer(
+        &instructions,
+        Some(&fee_payer_pubkey),
+    ))
 }
 
 fn move_stake_account(
@@ -114,15 +280,15 @@ fn move_stake_account(
     new_stake_authority_pubkey: &Pubkey,
     new_withdraw_authority_pubkey: &Pubkey,
     lamports: u64,
-) -> Message {
-    let new_stake_account_address = derive_stake_account_address(new_base_pubkey, i);
+) -> Result<Message, Error> {
+    let new_stake_account_address = derive_stake_account_address(new_base_pubkey, i)?;
     let mut instructions = stake_instruction::split_with_seed(
         stake_account_address,
         stake_authority_pubkey,
         lamports,
         &new_stake_account_address,
         new_base_pubkey,
-        &i.to_string(),
+        &seed(i),
     );
 
     let authorize_instructions = authorize_stake_accounts_instructions(
@@ -134,7 +300,10 @@ fn move_stake_account(
     );
 
     instructions.extend(authorize_instructions.into_iter());
-    Message::new_with_payer(&instructions, Some(&fee_payer_pubkey))
+    Ok(Message::new_with_payer(
+        &instructions,
+        Some(&fee_payer_pubkey),
+    ))
 }
 
 pub(crate) fn authorize_stake_accounts(
@@ -144,10 +313,10 @@ pub(crate) fn authorize_stake_accounts(
     withdraw_authority_pubkey: &Pubkey,
     new_stake_authority_pubkey: &Pubkey,
     new_withdraw_authority_pubkey: &Pubkey,
-    num_accounts: usize,
-) -> Vec<Message> {
-    let stake_account_addresses = derive_stake_account_addresses(base_pubkey, num_accounts);
-    stake_account_addresses
+    indices: &[usize],
+) -> Result<Vec<Message>, Error> {
+    let stake_account_addresses = derive_stake_account_addresses(base_pubkey, indices)?;
+    let messages = stake_account_addresses
         .iter()
         .map(|stake_account_address| {
             let instructions = authorize_stake_accounts_instructions(
@@ -159,23 +328,26 @@ pub(crate) fn authorize_stake_accounts(
             );
             Message::new_with_payer(&instructions, Some(&fee_payer_pubkey))
         })
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+    Ok(messages)
 }
 
+// Rebase the stake account at each index to the same index of the new base
 pub(crate) fn rebase_stake_accounts(
     fee_payer_pubkey: &Pubkey,
     new_base_pubkey: &Pubkey,
     stake_authority_pubkey: &Pubkey,
+    indices: &[usize],
     balances: &[(Pubkey, u64)],
-) -> Vec<Message> {
-    balances
+) -> Result<Vec<Message>, Error> {
+    indices
         .iter()
-        .enumerate()
+        .zip(balances)
         .map(|(i, (stake_account_address, lamports))| {
             rebase_stake_account(
                 stake_account_address,
                 new_base_pubkey,
-                i,
+                *i,
                 fee_payer_pubkey,
                 stake_authority_pubkey,
                 *lamports,
@@ -191,16 +363,17 @@ pub(crate) fn move_stake_accounts(
     withdraw_authority_pubkey: &Pubkey,
     new_stake_authority_pubkey: &Pubkey,
     new_withdraw_authority_pubkey: &Pubkey,
+    indices: &[usize],
     balances: &[(Pubkey, u64)],
-) -> Vec<Message> {
-    balances
+) -> Result<Vec<Message>, Error> {
+    indices
         .iter()
-        .enumerate()
+        .zip(balances)
         .map(|(i, (stake_account_address, lamports))| {
             move_stake_account(
                 stake_account_address,
                 new_base_pubkey,
-                i,
+                *i,
                 fee_payer_pubkey,
                 stake_authority_pubkey,
                 withdraw_authority_pubkey,
@@ -213,7 +386,7 @@ pub(crate) fn move_stake_accounts(
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use solana_runtime::{bank::Bank, bank_client::BankClient};
     use solana_sdk::{
@@ -224,7 +397,7 @@ mod tests {
     };
     use solana_stake_program::stake_state::StakeState;
 
-    fn create_bank(lamports: u64) -> (Bank, Keypair, u64) {
+    pub(crate) fn create_bank(lamports: u64) -> (Bank, Keypair, u64) {
         let (genesis_config, mint_keypair) = create_genesis_config(lamports);
         let mut bank = Bank::new(&genesis_config);
         bank.add_instruction_processor(
@@ -248,19 +421,19 @@ mod tests {
     }
 
     fn get_account_at<C: SyncClient>(client: &C, base_pubkey: &Pubkey, i: usize) -> Account {
-        let account_address = derive_stake_account_address(&base_pubkey, i);
+        let account_address = derive_stake_account_address(&base_pubkey, i).unwrap();
         client.get_account(&account_address).unwrap().unwrap()
     }
 
     fn get_balances<C: SyncClient>(
         client: &C,
         base_pubkey: &Pubkey,
-        num_accounts: usize,
+        indices: &[usize],
     ) -> Vec<(Pubkey, u64)> {
-        (0..num_accounts)
-            .into_iter()
+        indices
+            .iter()
             .map(|i| {
-                let address = derive_stake_account_address(&base_pubkey, i);
+                let address = derive_stake_account_address(&base_pubkey, *i).unwrap();
                 (address, client.get_balance(&address).unwrap())
             })
             .collect()
@@ -287,7 +460,8 @@ mod tests {
             lamports,
             &stake_authority_pubkey,
             &withdraw_authority_pubkey,
-        );
+        )
+        .unwrap();
 
         let signers = [&sender_keypair, &fee_payer_keypair, &base_keypair];
         bank_client.send_message(&signers, message).unwrap();
@@ -297,6 +471,103 @@ mod tests {
         let authorized = StakeState::authorized_from(&account).unwrap();
         assert_eq!(authorized.staker, stake_authority_pubkey);
         assert_eq!(authorized.withdrawer, withdraw_authority_pubkey);
+
+        let authorized = Authorized {
+            staker: stake_authority_pubkey,
+            withdrawer: withdraw_authority_pubkey,
+        };
+        let lockup = Lockup {
+            unix_timestamp: 1_600_000_000,
+            epoch: 0,
+            custodian: Pubkey::new_rand(),
+        };
+        let message = new_derived_stake_account(
+            &fee_payer_pubkey,
+            &sender_pubkey,
+            &base_pubkey,
+            1,
+            lamports,
+            &authorized,
+            &lockup,
+        )
+        .unwrap();
+        bank_client.send_message(&signers, message).unwrap();
+
+        let account = get_account_at(&bank_client, &base_pubkey, 1);
+        match StakeState::from(&account) {
+            Some(StakeState::Initialized(meta)) => assert_eq!(meta.lockup, lockup),
+            state => panic!("unexpected stake state {:?}", state),
+        }
+    }
+
+    #[test]
+    fn test_withdraw_locked_stake_account() {
+        let (bank, sender_keypair, rent) = create_bank(10_000_000);
+        let sender_pubkey = sender_keypair.pubkey();
+        let bank_client = BankClient::new(bank);
+        let fee_payer_keypair = create_account(&bank_client, &sender_keypair, 1);
+        let fee_payer_pubkey = fee_payer_keypair.pubkey();
+
+        let base_keypair = Keypair::new();
+        let base_pubkey = base_keypair.pubkey();
+        let withdraw_authority_keypair = Keypair::new();
+        let withdraw_authority_pubkey = withdraw_authority_keypair.pubkey();
+        let custodian_keypair = Keypair::new();
+        let custodian_pubkey = custodian_keypair.pubkey();
+        let authorized = Authorized {
+            staker: Pubkey::new_rand(),
+            withdrawer: withdraw_authority_pubkey,
+        };
+        let lockup = Lockup {
+            unix_timestamp: std::i64::MAX,
+            epoch: 0,
+            custodian: custodian_pubkey,
+        };
+        let lamports = rent + 1;
+        let message = new_derived_stake_account(
+            &fee_payer_pubkey,
+            &sender_pubkey,
+            &base_pubkey,
+            0,
+            lamports,
+            &authorized,
+            &lockup,
+        )
+        .unwrap();
+        let signers = [&sender_keypair, &fee_payer_keypair, &base_keypair];
+        bank_client.send_message(&signers, message).unwrap();
+
+        let stake_account_address = derive_stake_account_address(&base_pubkey, 0).unwrap();
+        let treasury_pubkey = Pubkey::new_rand();
+
+        // Without the custodian, the lockup is in force.
+        let message = withdraw_stake_account(
+            &fee_payer_pubkey,
+            &stake_account_address,
+            &withdraw_authority_pubkey,
+            None,
+            &treasury_pubkey,
+            lamports,
+        );
+        let signers = [&fee_payer_keypair, &withdraw_authority_keypair];
+        assert!(bank_client.send_message(&signers, message).is_err());
+
+        let message = withdraw_stake_account(
+            &fee_payer_pubkey,
+            &stake_account_address,
+            &withdraw_authority_pubkey,
+            Some(&custodian_pubkey),
+            &treasury_pubkey,
+            lamports,
+        );
+        let signers = [
+            &fee_payer_keypair,
+            &withdraw_authority_keypair,
+            &custodian_keypair,
+        ];
+        bank_client.send_message(&signers, message).unwrap();
+        assert_eq!(bank_client.get_balance(&treasury_pubkey).unwrap(), lamports);
+        assert_eq!(bank_client.get_balance(&stake_account_address).unwrap(), 0);
     }
 
     #[test]
@@ -323,7 +594,8 @@ mod tests {
             lamports,
             &stake_authority_pubkey,
             &withdraw_authority_pubkey,
-        );
+        )
+        .unwrap();
 
         let signers = [&sender_keypair, &fee_payer_keypair, &base_keypair];
         bank_client.send_message(&signers, message).unwrap();
@@ -337,8 +609,9 @@ mod tests {
             &withdraw_authority_pubkey,
             &new_stake_authority_pubkey,
             &new_withdraw_authority_pubkey,
-            1,
-        );
+            &[0],
+        )
+        .unwrap();
 
         let signers = [
             &fee_payer_keypair,
@@ -372,7 +645,7 @@ mod tests {
         let withdraw_authority_keypair = Keypair::new();
         let withdraw_authority_pubkey = withdraw_authority_keypair.pubkey();
 
-        let num_accounts = 1;
+        let indices = [0];
         let message = new_stake_account(
             &fee_payer_pubkey,
             &sender_pubkey,
@@ -380,21 +653,24 @@ mod tests {
             lamports,
             &stake_authority_pubkey,
             &withdraw_authority_pubkey,
-        );
+        )
+        .unwrap();
 
         let signers = [&sender_keypair, &fee_payer_keypair, &base_keypair];
         bank_client.send_message(&signers, message).unwrap();
 
         let new_base_keypair = Keypair::new();
         let new_base_pubkey = new_base_keypair.pubkey();
-        let balances = get_balances(&bank_client, &base_pubkey, num_accounts);
+        let balances = get_balances(&bank_client, &base_pubkey, &indices);
         let messages = rebase_stake_accounts(
             &fee_payer_pubkey,
             &new_base_pubkey,
             &stake_authority_pubkey,
+            &indices,
             &balances,
-        );
-        assert_eq!(messages.len(), num_accounts);
+        )
+        .unwrap();
+        assert_eq!(messages.len(), indices.len());
 
         let signers = [
             &fee_payer_keypair,
@@ -429,7 +705,7 @@ mod tests {
         let withdraw_authority_keypair = Keypair::new();
         let withdraw_authority_pubkey = withdraw_authority_keypair.pubkey();
 
-        let num_accounts = 1;
+        let indices = [0];
         let message = new_stake_account(
             &fee_payer_pubkey,
             &sender_pubkey,
@@ -437,7 +713,8 @@ mod tests {
             lamports,
             &stake_authority_pubkey,
             &withdraw_authority_pubkey,
-        );
+        )
+        .unwrap();
 
         let signers = [&sender_keypair, &fee_payer_keypair, &base_keypair];
         bank_client.send_message(&signers, message).unwrap();
@@ -446,7 +723,7 @@ mod tests {
         let new_base_pubkey = new_base_keypair.pubkey();
         let new_stake_authority_pubkey = Pubkey::new_rand();
         let new_withdraw_authority_pubkey = Pubkey::new_rand();
-        let balances = get_balances(&bank_client, &base_pubkey, num_accounts);
+        let balances = get_balances(&bank_client, &base_pubkey, &indices);
         let messages = move_stake_accounts(
             &fee_payer_pubkey,
             &new_base_pubkey,
@@ -454,9 +731,11 @@ mod tests {
             &withdraw_authority_pubkey,
             &new_stake_authority_pubkey,
             &new_withdraw_authority_pubkey,
+            &indices,
             &balances,
-        );
-        assert_eq!(messages.len(), num_accounts);
+        )
+        .unwrap();
+        assert_eq!(messages.len(), indices.len());
 
         let signers = [
             &fee_payer_keypair,
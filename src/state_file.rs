@@ -0,0 +1,25 @@
+use crate::error::Error;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, File};
+use std::path::Path;
+
+/// Load the JSON state at `path`, or return the default state if the file
+/// doesn't exist yet.
+pub(crate) fn load_state<T: DeserializeOwned + Default>(path: &str) -> Result<T, Error> {
+    if !Path::new(path).exists() {
+        return Ok(T::default());
+    }
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    serde_json::from_reader(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+/// Save the state by replacing the file, so that a crash mid-write can't lose
+/// the progress recorded by earlier saves.
+pub(crate) fn save_state<T: Serialize>(path: &str, state: &T) -> Result<(), Error> {
+    let tmp_path = format!("{}.tmp", path);
+    let file =
+        File::create(&tmp_path).map_err(|err| Error::Config(format!("{}: {}", tmp_path, err)))?;
+    serde_json::to_writer_pretty(file, state)
+        .map_err(|err| Error::Config(format!("{}: {}", tmp_path, err)))?;
+    fs::rename(&tmp_path, path).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
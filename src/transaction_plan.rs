@@ -0,0 +1,344 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    account::Account,
+    account_utils::StateMut,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    nonce::{self, state::Versions},
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+    system_program,
+    transaction::Transaction,
+};
+use solana_stake_program::stake_state::StakeState;
+use std::fs::File;
+use std::str::FromStr;
+
+/// A signer a message requires, and its signature once added.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PlannedSigner {
+    pub pubkey: String,
+    pub signature: Option<String>,
+}
+
+/// An unsigned transaction on behalf of a derived stake account, with the
+/// state of the account it was planned against.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PlannedTransaction {
+    pub index: usize,
+    pub address: String,
+    pub lamports: u64,
+    pub staker: String,
+    pub withdrawer: String,
+    /// The nonce account the message advances.
+    pub nonce_account: String,
+    /// The message, bincode serialized and base58 encoded.
+    pub message: String,
+    pub signers: Vec<PlannedSigner>,
+}
+
+/// The plan file, in JSON.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct TransactionPlan {
+    pub command: String,
+    pub transactions: Vec<PlannedTransaction>,
+}
+
+fn encode_message(message: &Message) -> String {
+    bs58::encode(message.serialize()).into_string()
+}
+
+/// Return the state of an initialized nonce account.
+pub(crate) fn nonce_data(account: &Account) -> Option<nonce::state::Data> {
+    if account.owner != system_program::id() {
+        return None;
+    }
+    let versions: Versions = account.state().ok()?;
+    match versions.convert_to_current() {
+        nonce::State::Initialized(data) => Some(data),
+        nonce::State::Uninitialized => None,
+    }
+}
+
+/// Rebuild the message to advance the nonce account first, and to use its
+/// stored blockhash, so that it doesn't expire before it's submitted.
+pub(crate) fn message_with_nonce(
+    message: &Message,
+    nonce_account: &Pubkey,
+    nonce_authority: &Pubkey,
+    blockhash: &Hash,
+) -> Message {
+    let instructions = message
+        .instructions
+        .iter()
+        .map(|instruction| {
+            let accounts = instruction
+                .accounts
+                .iter()
+                .map(|&i| {
+                    let i = i as usize;
+                    let pubkey = message.account_keys[i];
+                    if message.is_writable(i) {
+                        AccountMeta::new(pubkey, message.is_signer(i))
+                    } else {
+                        AccountMeta::new_readonly(pubkey, message.is_signer(i))
+                    }
+                })
+                .collect();
+            Instruction {
+                program_id: message.account_keys[instruction.program_id_index as usize],
+                accounts,
+                data: instruction.data.clone(),
+            }
+        })
+        .collect();
+    let payer = message.account_keys[0];
+    let mut message =
+        Message::new_with_nonce(instructions, Some(&payer), nonce_account, nonce_authority);
+    message.recent_blockhash = *blockhash;
+    message
+}
+
+fn decode_message(encoded: &str) -> Result<Message, String> {
+    let data = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|err| format!("invalid message encoding: {:?}", err))?;
+    bincode::deserialize(&data).map_err(|err| format!("invalid message: {}", err))
+}
+
+impl PlannedTransaction {
+    /// Plan the message against the stake account it is sent on behalf of.
+    /// Returns None if the account isn't an initialized stake account.
+    pub fn new(
+        index: usize,
+        address: &Pubkey,
+        account: &Account,
+        nonce_account: &Pubkey,
+        message: &Message,
+    ) -> Option<Self> {
+        let authorized = StakeState::authorized_from(account)?;
+        let num_signers = message.header.num_required_signatures as usize;
+        let signers = message.account_keys[..num_signers]
+            .iter()
+            .map(|pubkey| PlannedSigner {
+                pubkey: pubkey.to_string(),
+                signature: None,
+            })
+            .collect();
+        Some(Self {
+            index,
+            address: address.to_string(),
+            lamports: account.lamports,
+            staker: authorized.staker.to_string(),
+            withdrawer: authorized.withdrawer.to_string(),
+            nonce_account: nonce_account.to_string(),
+            message: encode_message(message),
+            signers,
+        })
+    }
+
+    /// Return the signers with no signature yet.
+    pub fn missing_signers(&self) -> Vec<&str> {
+        self.signers
+            .iter()
+            .filter(|signer| signer.signature.is_none())
+            .map(|signer| signer.pubkey.as_str())
+            .collect()
+    }
+
+    /// Return the transaction, checking that every required signature is
+    /// present and valid.
+    pub fn signed_transaction(&self) -> Result<Transaction, String> {
+        let message = decode_message(&self.message)?;
+        let num_signers = message.header.num_required_signatures as usize;
+        if self.signers.len() != num_signers {
+            return Err(format!(
+                "message requires {} signers, plan lists {}",
+                num_signers,
+                self.signers.len()
+            ));
+        }
+        let message_data = message.serialize();
+        let mut signatures = vec![];
+        for (signer, pubkey) in self.signers.iter().zip(&message.account_keys) {
+            if signer.pubkey != pubkey.to_string() {
+                return Err(format!("signer {} is not in the message", signer.pubkey));
+            }
+            let signature = signer
+                .signature
+                .as_ref()
+                .ok_or_else(|| format!("missing signature of {}", signer.pubkey))?;
+            let signature = Signature::from_str(signature)
+                .map_err(|err| format!("invalid signature of {}: {:?}", signer.pubkey, err))?;
+            if !signature.verify(pubkey.as_ref(), &message_data) {
+                return Err(format!("signature of {} does not verify", signer.pubkey));
+            }
+            signatures.push(signature);
+        }
+        Ok(Transaction {
+            signatures,
+            message,
+        })
+    }
+
+    /// Check that the account and the nonce are still in the state the
+    /// message was planned against.
+    pub fn check_account(
+        &self,
+        account: Option<&Account>,
+        nonce_account: Option<&Account>,
+    ) -> Result<(), String> {
+        let account = account.ok_or("account no longer exists")?;
+        if account.lamports != self.lamports {
+            return Err(format!(
+                "balance is {} lamports, planned against {}",
+                account.lamports, self.lamports
+            ));
+        }
+        let authorized = StakeState::authorized_from(account).ok_or("not a stake account")?;
+        if authorized.staker.to_string() != self.staker {
+            return Err(format!(
+                "stake authority is {}, planned against {}",
+                authorized.staker, self.staker
+            ));
+        }
+        if authorized.withdrawer.to_string() != self.withdrawer {
+            return Err(format!(
+                "withdraw authority is {}, planned against {}",
+                authorized.withdrawer, self.withdrawer
+            ));
+        }
+        let nonce_data = nonce_account
+            .and_then(nonce_data)
+            .ok_or_else(|| format!("{} is not a nonce account", self.nonce_account))?;
+        let message = decode_message(&self.message)?;
+        if nonce_data.blockhash != message.recent_blockhash {
+            return Err(format!("nonce {} has advanced", self.nonce_account));
+        }
+        Ok(())
+    }
+}
+
+/// Add the signer's signature to every transaction that requires it,
+/// returning how many were signed.
+pub(crate) fn sign_plan(plan: &mut TransactionPlan, signer: &dyn Signer) -> Result<usize, Error> {
+    let pubkey = signer.pubkey().to_string();
+    let mut num_signed = 0;
+    for transaction in &mut plan.transactions {
+        let planned_signer = match transaction
+            .signers
+            .iter_mut()
+            .find(|planned_signer| planned_signer.pubkey == pubkey)
+        {
+            Some(planned_signer) => planned_signer,
+            None => continue,
+        };
+        let message = decode_message(&transaction.message)
+            .map_err(|err| Error::Config(format!("transaction {}: {}", transaction.index, err)))?;
+        let signature = signer
+            .try_sign_message(&message.serialize())
+            .map_err(|err| Error::resolve("signer", err))?;
+        planned_signer.signature = Some(signature.to_string());
+        num_signed += 1;
+    }
+    Ok(num_signed)
+}
+
+pub(crate) fn read_transaction_plan_file(path: &str) -> Result<TransactionPlan, Error> {
+    let file = File::open(path).map_err(|err| Error::Config(format!("{}: {}", path, err)))?;
+    serde_json::from_reader(file).map_err(|err| Error::Config(format!("{}: {}", path, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{
+        fee_calculator::FeeCalculator, hash::hash, signature::Keypair, system_instruction,
+    };
+    use solana_stake_program::stake_state::{Authorized, Meta};
+
+    #[test]
+    fn test_sign_plan() {
+        let fee_payer_keypair = Keypair::new();
+        let sender_keypair = Keypair::new();
+        let stake_authority_pubkey = Pubkey::new_rand();
+        let authorized = Authorized {
+            staker: stake_authority_pubkey,
+            withdrawer: Pubkey::new_rand(),
+        };
+        let state = StakeState::Initialized(Meta {
+            authorized,
+            ..Meta::default()
+        });
+        let account = Account::new_data(42, &state, &solana_stake_program::id()).unwrap();
+        let address = Pubkey::new_rand();
+        let instruction =
+            system_instruction::transfer(&sender_keypair.pubkey(), &Pubkey::new_rand(), 1);
+        let message = Message::new_with_payer(&[instruction], Some(&fee_payer_keypair.pubkey()));
+        let nonce_pubkey = Pubkey::new_rand();
+        let blockhash = hash(&[0]);
+        let message = message_with_nonce(
+            &message,
+            &nonce_pubkey,
+            &fee_payer_keypair.pubkey(),
+            &blockhash,
+        );
+        assert_eq!(message.recent_blockhash, blockhash);
+        let transaction =
+            PlannedTransaction::new(0, &address, &account, &nonce_pubkey, &message).unwrap();
+        assert_eq!(transaction.signers.len(), 2);
+        let mut plan = TransactionPlan {
+            command: "authorize".to_string(),
+            transactions: vec![transaction],
+        };
+
+        // A key the plan doesn't need signs nothing.
+        assert_eq!(sign_plan(&mut plan, &Keypair::new()).unwrap(), 0);
+        assert_eq!(sign_plan(&mut plan, &sender_keypair).unwrap(), 1);
+        let transaction = &plan.transactions[0];
+        assert_eq!(
+            transaction.missing_signers(),
+            vec![fee_payer_keypair.pubkey().to_string()]
+        );
+        assert!(transaction.signed_transaction().is_err());
+
+        sign_plan(&mut plan, &fee_payer_keypair).unwrap();
+        let transaction = &mut plan.transactions[0];
+        assert_eq!(transaction.signed_transaction().unwrap().message, message);
+
+        // A signature from the wrong key is rejected.
+        transaction.signers[1].signature = transaction.signers[0].signature.clone();
+        assert!(transaction.signed_transaction().is_err());
+
+        let nonce_account = |blockhash| {
+            let data = nonce::state::Data {
+                authority: fee_payer_keypair.pubkey(),
+                blockhash,
+                fee_calculator: FeeCalculator::default(),
+            };
+            let versions = Versions::new_current(nonce::State::Initialized(data));
+            Account::new_data(1, &versions, &system_program::id()).unwrap()
+        };
+        let nonce = nonce_account(blockhash);
+        assert_eq!(
+            transaction.check_account(Some(&account), Some(&nonce)),
+            Ok(())
+        );
+        let mut changed = account.clone();
+        changed.lamports += 1;
+        assert!(transaction
+            .check_account(Some(&changed), Some(&nonce))
+            .is_err());
+        assert!(transaction.check_account(None, Some(&nonce)).is_err());
+
+        // The nonce has been advanced or closed.
+        let advanced = nonce_account(hash(&[1]));
+        assert_eq!(
+            transaction.check_account(Some(&account), Some(&advanced)),
+            Err(format!("nonce {} has advanced", nonce_pubkey))
+        );
+        assert!(transaction.check_account(Some(&account), None).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use crate::args::OutputFormat;
+use crate::error::Error;
+use crate::lockup::{format_unix_timestamp, get_lockup, is_locked, DATE_TIME_FORMAT};
+use serde::Serialize;
+use solana_sdk::{
+    account::Account,
+    clock::{Epoch, UnixTimestamp},
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+    sysvar::clock::Clock,
+};
+use std::fmt;
+use std::io::Write;
+
+/// When a derived stake account's lockup expires.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct UnlockEntry {
+    pub base_pubkey: String,
+    pub index: usize,
+    pub address: String,
+    pub lamports: u64,
+    /// The lockup's unix timestamp, if it has not yet passed.
+    pub unix_timestamp: Option<UnixTimestamp>,
+    /// The lockup's epoch, if it has not yet passed.
+    pub epoch: Option<Epoch>,
+    pub bucket: String,
+}
+
+/// The lamports that unlock in a month or epoch, or that are already unlocked.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct UnlockBucket {
+    pub bucket: String,
+    pub lamports: u64,
+    pub num_accounts: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct UnlockSchedule {
+    pub epoch: Epoch,
+    pub unix_timestamp: UnixTimestamp,
+    pub buckets: Vec<UnlockBucket>,
+    pub timeline: Vec<UnlockEntry>,
+}
+
+// Name the bucket of an account that unlocks at the given time and epoch,
+// preferring the calendar month when the time is known.
+fn bucket_name(unix_timestamp: Option<UnixTimestamp>, epoch: Option<Epoch>) -> String {
+    match (unix_timestamp, epoch) {
+        (Some(unix_timestamp), _) => format_unix_timestamp(unix_timestamp, "%Y-%m"),
+        (None, Some(epoch)) => format!("epoch {}", epoch),
+        (None, None) => "unlocked".to_string(),
+    }
+}
+
+/// Bucket the lamports of each stake account by when its lockup expires.
+/// Accounts that don't exist or aren't stake accounts are skipped.
+pub(crate) fn unlock_schedule(
+    accounts: &[(Pubkey, usize, Pubkey, Option<Account>)],
+    clock: &Clock,
+) -> UnlockSchedule {
+    let mut timeline: Vec<_> = accounts
+        .iter()
+        .filter_map(|(base_pubkey, index, address, account)| {
+            let account = account.as_ref()?;
+            let lockup = get_lockup(account)?;
+            let (unix_timestamp, epoch) = if is_locked(&lockup, clock) {
+                let unix_timestamp = Some(lockup.unix_timestamp)
+                    .filter(|unix_timestamp| *unix_timestamp > clock.unix_timestamp);
+                let epoch = Some(lockup.epoch).filter(|epoch| *epoch > clock.epoch);
+                (unix_timestamp, epoch)
+            } else {
+                (None, None)
+            };
+            Some(UnlockEntry {
+                base_pubkey: base_pubkey.to_string(),
+                index: *index,
+                address: address.to_string(),
+                lamports: account.lamports,
+                unix_timestamp,
+                epoch,
+                bucket: bucket_name(unix_timestamp, epoch),
+            })
+        })
+        .collect();
+    timeline.sort_by_key(|entry| {
+        (
+            entry.unix_timestamp.unwrap_or(0),
+            entry.epoch.unwrap_or(0),
+            entry.base_pubkey.clone(),
+            entry.index,
+        )
+    });
+
+    let mut buckets: Vec<UnlockBucket> = vec![];
+    for entry in &timeline {
+        match buckets
+            .iter_mut()
+            .find(|bucket| bucket.bucket == entry.bucket)
+        {
+            Some(bucket) => {
+                bucket.lamports += entry.lamports;
+                bucket.num_accounts += 1;
+            }
+            None => buckets.push(UnlockBucket {
+                bucket: entry.bucket.clone(),
+                lamports: entry.lamports,
+                num_accounts: 1,
+            }),
+        }
+    }
+    UnlockSchedule {
+        epoch: clock.epoch,
+        unix_timestamp: clock.unix_timestamp,
+        buckets,
+        timeline,
+    }
+}
+
+fn write_table<W: Write>(writer: &mut W, schedule: &UnlockSchedule) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "As of epoch {}, {}",
+        schedule.epoch,
+        format_unix_timestamp(schedule.unix_timestamp, DATE_TIME_FORMAT)
+    )?;
+    writeln!(writer, "{:<12} {:>8} {:>14}", "UNLOCKS", "ACCOUNTS", "SOL")?;
+    for bucket in &schedule.buckets {
+        writeln!(
+            writer,
+            "{:<12} {:>8} {:>14}",
+            bucket.bucket,
+            bucket.num_accounts,
+            lamports_to_sol(bucket.lamports)
+        )?;
+    }
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:<19} {:>8} {:<44} {:>5} {:>14}",
+        "UNLOCK TIME", "EPOCH", "ADDRESS", "INDEX", "SOL"
+    )?;
+    for entry in schedule
+        .timeline
+        .iter()
+        .filter(|entry| entry.bucket != "unlocked")
+    {
+        let unlock_time = entry
+            .unix_timestamp
+            .map(|unix_timestamp| format_unix_timestamp(unix_timestamp, DATE_TIME_FORMAT))
+            .unwrap_or_default();
+        let epoch = entry
+            .epoch
+            .map(|epoch| epoch.to_string())
+            .unwrap_or_default();
+        writeln!(
+            writer,
+            "{:<19} {:>8} {:<44} {:>5} {:>14}",
+            unlock_time,
+            epoch,
+            entry.address,
+            entry.index,
+            lamports_to_sol(entry.lamports)
+        )?;
+    }
+    Ok(())
+}
+
+fn write_error<E: fmt::Display>(err: E) -> Error {
+    Error::Config(format!("unable to write unlock schedule: {}", err))
+}
+
+/// Write the schedule in the given format. CSV has one row per account, from
+/// which the buckets can be summed.
+pub(crate) fn write_unlock_schedule<W: Write>(
+    mut writer: W,
+    schedule: &UnlockSchedule,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Table => write_table(&mut writer, schedule).map_err(write_error),
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, schedule).map_err(write_error),
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for entry in &schedule.timeline {
+                writer.serialize(entry).map_err(write_error)?;
+            }
+            writer.flush().map_err(write_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use solana_stake_program::stake_state::{Lockup, Meta, StakeState};
+
+    fn timestamp(year: i32, month: u32, day: u32) -> UnixTimestamp {
+        NaiveDate::from_ymd(year, month, day)
+            .and_hms(0, 0, 0)
+            .timestamp()
+    }
+
+    fn create_stake_account(lamports: u64, unix_timestamp: UnixTimestamp, epoch: Epoch) -> Account {
+        let meta = Meta {
+            lockup: Lockup {
+                unix_timestamp,
+                epoch,
+                ..Lockup::default()
+            },
+            ..Meta::default()
+        };
+        let state = StakeState::Initialized(meta);
+        Account::new_data(lamports, &state, &solana_stake_program::id()).unwrap()
+    }
+
+    #[test]
+    fn test_unlock_schedule() {
+        let clock = Clock {
+            epoch: 10,
+            unix_timestamp: timestamp(2020, 6, 15),
+            ..Clock::default()
+        };
+        let base_pubkey = Pubkey::new_rand();
+        let accounts: Vec<_> = vec![
+            Some(create_stake_account(1, timestamp(2020, 1, 1), 0)),
+            Some(create_stake_account(2, timestamp(2020, 8, 1), 0)),
+            Some(create_stake_account(4, timestamp(2020, 7, 1), 0)),
+            Some(create_stake_account(8, timestamp(2020, 7, 31), 0)),
+            Some(create_stake_account(16, 0, 12)),
+            None,
+            Some(create_stake_account(32, i64::MAX, 0)),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, account)| (base_pubkey, i, Pubkey::new_rand(), account))
+        .collect();
+
+        let schedule = unlock_schedule(&accounts, &clock);
+        let buckets: Vec<_> = schedule
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.bucket.as_str(), bucket.lamports, bucket.num_accounts))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                ("unlocked", 1, 1),
+                ("epoch 12", 16, 1),
+                ("2020-07", 12, 2),
+                ("2020-08", 2, 1),
+                (&*i64::MAX.to_string(), 32, 1),
+            ]
+        );
+        let indexes: Vec<_> = schedule.timeline.iter().map(|entry| entry.index).collect();
+        assert_eq!(indexes, vec![0, 4, 2, 3, 1, 6]);
+    }
+}
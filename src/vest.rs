@@ -0,0 +1,184 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use solana_sdk::clock::UnixTimestamp;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A span of calendar time, given on the command line as "30d", "2w", "1mo" or "1y".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Period {
+    Days(u32),
+    Months(u32),
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(split);
+        let count: u32 = count
+            .parse()
+            .map_err(|_| format!("invalid period '{}', expected e.g. 30d, 2w, 1mo or 1y", s))?;
+        if count == 0 {
+            return Err(format!("invalid period '{}', must be at least 1", s));
+        }
+        let too_long = || format!("period '{}' is too long", s);
+        match unit {
+            "d" => Ok(Period::Days(count)),
+            "w" => Ok(Period::Days(count.checked_mul(7).ok_or_else(too_long)?)),
+            "mo" => Ok(Period::Months(count)),
+            "y" => Ok(Period::Months(count.checked_mul(12).ok_or_else(too_long)?)),
+            _ => Err(format!(
+                "invalid period unit in '{}', expected d, w, mo or y",
+                s
+            )),
+        }
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    let (next_year, next_month) = if month == 12 {
+        (year.checked_add(1)?, 1)
+    } else {
+        (year, month + 1)
+    };
+    Some(
+        NaiveDate::from_ymd_opt(next_year, next_month, 1)?
+            .pred()
+            .day(),
+    )
+}
+
+// Add calendar months, moving to the end of the month if the day doesn't exist
+// in it. Returns None if the result is beyond the calendar.
+fn add_months(date_time: NaiveDateTime, months: u32) -> Option<NaiveDateTime> {
+    let month0 = u64::from(date_time.month0()) + u64::from(months);
+    let years = i32::try_from(month0 / 12).ok()?;
+    let year = date_time.year().checked_add(years)?;
+    let month = (month0 % 12) as u32 + 1;
+    let day = date_time.day().min(days_in_month(year, month)?);
+    Some(NaiveDate::from_ymd_opt(year, month, day)?.and_time(date_time.time()))
+}
+
+impl Period {
+    /// Return the time `n` periods after `start`, or an error if it's beyond
+    /// the calendar.
+    pub fn after(&self, start: UnixTimestamp, n: u32) -> Result<UnixTimestamp, String> {
+        let unix_timestamp = match self {
+            Period::Days(days) => days
+                .checked_mul(n)
+                .and_then(|days| i64::from(days).checked_mul(SECONDS_PER_DAY))
+                .and_then(|seconds| start.checked_add(seconds)),
+            Period::Months(months) => months.checked_mul(n).and_then(|months| {
+                let start = NaiveDateTime::from_timestamp_opt(start, 0)?;
+                Some(add_months(start, months)?.timestamp())
+            }),
+        };
+        unix_timestamp.ok_or_else(|| format!("{} periods after {} is out of range", n, start))
+    }
+}
+
+/// One account of a vesting grant and the time its lockup expires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Tranche {
+    pub lamports: u64,
+    pub unix_timestamp: UnixTimestamp,
+}
+
+/// Split a grant into equal tranches, the first unlocking one period after
+/// `start` and each later one a period after the previous, except that none
+/// unlock before the cliff. Any remainder goes to the last tranche.
+pub(crate) fn vesting_schedule(
+    total_lamports: u64,
+    start: UnixTimestamp,
+    period: Period,
+    num_tranches: usize,
+    cliff: Option<Period>,
+) -> Result<Vec<Tranche>, String> {
+    let cliff_timestamp = match cliff {
+        Some(cliff) => cliff.after(start, 1)?,
+        None => start,
+    };
+    let share = total_lamports / num_tranches as u64;
+    let remainder = total_lamports % num_tranches as u64;
+    (0..num_tranches)
+        .map(|i| {
+            let lamports = if i == num_tranches - 1 {
+                share + remainder
+            } else {
+                share
+            };
+            let n = u32::try_from(i + 1).map_err(|_| format!("too many tranches: {}", i + 1))?;
+            let unix_timestamp = period.after(start, n)?.max(cliff_timestamp);
+            Ok(Tranche {
+                lamports,
+                unix_timestamp,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(year: i32, month: u32, day: u32) -> UnixTimestamp {
+        NaiveDate::from_ymd(year, month, day)
+            .and_hms(0, 0, 0)
+            .timestamp()
+    }
+
+    #[test]
+    fn test_vesting_schedule() {
+        assert_eq!("30d".parse(), Ok(Period::Days(30)));
+        assert_eq!("2w".parse(), Ok(Period::Days(14)));
+        assert_eq!("1mo".parse(), Ok(Period::Months(1)));
+        assert_eq!("4y".parse(), Ok(Period::Months(48)));
+        assert!("1h".parse::<Period>().is_err());
+        assert!("mo".parse::<Period>().is_err());
+        assert!("0d".parse::<Period>().is_err());
+        assert!("1000000000w".parse::<Period>().is_err());
+        assert!("1000000000y".parse::<Period>().is_err());
+
+        // Months keep the day of the start date where they can.
+        let start = timestamp(2020, 1, 31);
+        assert_eq!(
+            Period::Months(1).after(start, 1),
+            Ok(timestamp(2020, 2, 29))
+        );
+        assert_eq!(
+            Period::Months(1).after(start, 2),
+            Ok(timestamp(2020, 3, 31))
+        );
+        assert_eq!(
+            Period::Months(1).after(start, 12),
+            Ok(timestamp(2021, 1, 31))
+        );
+
+        // Times beyond the calendar are errors rather than overflows.
+        assert!(Period::Days(u32::MAX).after(start, 2).is_err());
+        assert!(Period::Days(1).after(i64::MAX, 1).is_err());
+        assert!(Period::Months(u32::MAX).after(start, 1).is_err());
+        assert!(Period::Months(1).after(i64::MAX, 1).is_err());
+
+        let start = timestamp(2020, 1, 1);
+        let schedule =
+            vesting_schedule(100, start, Period::Months(1), 6, Some(Period::Months(3))).unwrap();
+        let unix_timestamps: Vec<_> = schedule.iter().map(|t| t.unix_timestamp).collect();
+        assert_eq!(
+            unix_timestamps,
+            vec![
+                timestamp(2020, 4, 1),
+                timestamp(2020, 4, 1),
+                timestamp(2020, 4, 1),
+                timestamp(2020, 5, 1),
+                timestamp(2020, 6, 1),
+                timestamp(2020, 7, 1),
+            ]
+        );
+        let lamports: Vec<_> = schedule.iter().map(|t| t.lamports).collect();
+        assert_eq!(lamports, vec![16, 16, 16, 16, 16, 20]);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::client::Client;
+use crate::error::Error;
+use crate::snapshot::{diff_snapshots, fetch_account_snapshots, AccountSnapshot};
+use serde::Serialize;
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use std::fs::File;
+use std::io::Write;
+use std::process::Command;
+
+/// The authorities every derived stake account is expected to have. Accounts
+/// are not checked for authorities that are None.
+#[derive(Debug, Default)]
+pub(crate) struct ExpectedAuthorities {
+    pub staker: Option<Pubkey>,
+    pub withdrawer: Option<Pubkey>,
+    pub custodian: Option<Pubkey>,
+}
+
+/// A change to a derived stake account, printed as one line of JSON.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct Alert {
+    pub slot: Slot,
+    pub base_pubkey: String,
+    pub index: usize,
+    pub address: String,
+    pub kind: String,
+    pub message: String,
+}
+
+impl Alert {
+    fn new(slot: Slot, account: &AccountSnapshot, kind: &str, message: String) -> Self {
+        Self {
+            slot,
+            base_pubkey: account.base_pubkey.clone(),
+            index: account.index,
+            address: account.address.clone(),
+            kind: kind.to_string(),
+            message,
+        }
+    }
+}
+
+// Return a description of each authority of the account that differs from the expected one.
+fn unexpected_authorities(
+    account: &AccountSnapshot,
+    expected: &ExpectedAuthorities,
+) -> Vec<String> {
+    let meta = match account.state.meta() {
+        Some(meta) => meta,
+        None => return vec![],
+    };
+    let checks = [
+        ("staker", &meta.staker, &expected.staker),
+        ("withdrawer", &meta.withdrawer, &expected.withdrawer),
+        ("custodian", &meta.lockup.custodian, &expected.custodian),
+    ];
+    checks
+        .iter()
+        .filter_map(|(role, actual, expected)| {
+            let expected = expected.as_ref()?.to_string();
+            if **actual == expected {
+                None
+            } else {
+                Some(format!("{} is {}, expected {}", role, actual, expected))
+            }
+        })
+        .collect()
+}
+
+/// Polls derived stake accounts and reports what changed since the previous poll.
+pub(crate) struct Watcher<'a, C> {
+    client: &'a C,
+    base_pubkeys: Vec<Pubkey>,
+    indices: Vec<usize>,
+    expected: ExpectedAuthorities,
+    previous: Option<Vec<AccountSnapshot>>,
+}
+
+impl<'a, C: Client> Watcher<'a, C> {
+    pub fn new(
+        client: &'a C,
+        base_pubkeys: Vec<Pubkey>,
+        indices: Vec<usize>,
+        expected: ExpectedAuthorities,
+    ) -> Self {
+        Self {
+            client,
+            base_pubkeys,
+            indices,
+            expected,
+            previous: None,
+        }
+    }
+
+    /// Fetch the accounts and return alerts for every change since the last
+    /// poll. Accounts with unexpected authorities are reported on the first
+    /// poll, and again whenever they change.
+    pub fn poll(&mut self) -> Result<Vec<Alert>, Error> {
+        let slot = self.client.get_slot().map_err(Error::fetch)?;
+        let current = fetch_account_snapshots(self.client, &self.base_pubkeys, &self.indices)?;
+
+        let mut alerts = vec![];
+        if let Some(previous) = &self.previous {
+            for (account, changes) in diff_snapshots(previous, &current) {
+                for change in changes {
+                    alerts.push(Alert::new(slot, account, change.kind(), change.to_string()));
+                }
+            }
+        }
+        for (i, account) in current.iter().enumerate() {
+            let previous_unexpected = self
+                .previous
+                .as_ref()
+                .and_then(|previous| previous.get(i))
+                .map(|previous| unexpected_authorities(previous, &self.expected))
+                .unwrap_or_default();
+            for message in unexpected_authorities(account, &self.expected) {
+                if !previous_unexpected.contains(&message) {
+                    alerts.push(Alert::new(slot, account, "unexpected_authority", message));
+                }
+            }
+        }
+        self.previous = Some(current);
+        Ok(alerts)
+    }
+}
+
+/// Print the alert as a line of JSON to the log file, or stdout if there is
+/// none, and then run the alert command, if any, with the alert in its
+/// STAKE_ACCOUNTS_ALERT environment variable.
+pub(crate) fn report_alert(
+    alert: &Alert,
+    log_file: Option<&mut File>,
+    alert_command: Option<&str>,
+) -> Result<(), Error> {
+    let line = serde_json::to_string(alert).unwrap();
+    match log_file {
+        Some(file) => writeln!(file, "{}", line)
+            .map_err(|err| Error::Config(format!("unable to write log file: {}", err)))?,
+        None => println!("{}", line),
+    }
+    if let Some(alert_command) = alert_command {
+        // A failing alert command is reported but does not stop the watch.
+        match Command::new("sh")
+            .arg("-c")
+            .arg(alert_command)
+            .env("STAKE_ACCOUNTS_ALERT", &line)
+            .status()
+        {
+            Ok(status) if !status.success() => {
+                eprintln!("warning: alert command exited with {}", status)
+            }
+            Err(err) => eprintln!("warning: unable to run alert command: {}", err),
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stake_accounts::{authorize_stake_accounts, new_stake_account, tests::create_bank};
+    use solana_runtime::bank_client::BankClient;
+    use solana_sdk::{
+        client::SyncClient,
+        signature::{Keypair, Signer},
+    };
+
+    #[test]
+    fn test_watch_authority_and_balance_changes() {
+        let (bank, sender_keypair, rent) = create_bank(10_000_000);
+        let sender_pubkey = sender_keypair.pubkey();
+        let bank_client = BankClient::new(bank);
+
+        let base_keypair = Keypair::new();
+        let base_pubkey = base_keypair.pubkey();
+        let stake_authority_keypair = Keypair::new();
+        let stake_authority_pubkey = stake_authority_keypair.pubkey();
+        let withdraw_authority_keypair = Keypair::new();
+        let withdraw_authority_pubkey = withdraw_authority_keypair.pubkey();
+
+        let expected = ExpectedAuthorities {
+            staker: Some(stake_authority_pubkey),
+            withdrawer: Some(withdraw_authority_pubkey),
+            custodian: None,
+        };
+        let mut watcher = Watcher::new(&bank_client, vec![base_pubkey], vec![0], expected);
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+
+        let message = new_stake_account(
+            &sender_pubkey,
+            &sender_pubkey,
+            &base_pubkey,
+            rent + 1,
+            &stake_authority_pubkey,
+            &withdraw_authority_pubkey,
+        )
+        .unwrap();
+        bank_client
+            .send_message(&[&sender_keypair, &base_keypair], message)
+            .unwrap();
+        let alerts = watcher.poll().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, "appeared");
+        assert_eq!(alerts[0].index, 0);
+
+        let new_stake_authority_pubkey = Pubkey::new_rand();
+        let messages = authorize_stake_accounts(
+            &sender_pubkey,
+            &base_pubkey,
+            &stake_authority_pubkey,
+            &withdraw_authority_pubkey,
+            &new_stake_authority_pubkey,
+            &withdraw_authority_pubkey,
+            &[0],
+        )
+        .unwrap();
+        let signers = [
+            &sender_keypair,
+            &stake_authority_keypair,
+            &withdraw_authority_keypair,
+        ];
+        for message in messages {
+            bank_client.send_message(&signers, message).unwrap();
+        }
+        let kinds: Vec<_> = watcher
+            .poll()
+            .unwrap()
+            .into_iter()
+            .map(|alert| alert.kind)
+            .collect();
+        assert_eq!(kinds, vec!["authority", "unexpected_authority"]);
+
+        // Unchanged accounts raise no further alerts.
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+    }
+}